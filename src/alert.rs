@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Local, Utc};
+use futures::future::{join_all, BoxFuture};
+use log::error;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    classify::Label,
+    config::{AlertRule, Config, CooldownMode, PushoverPriority, CONFIG},
+    email::Email,
+    event::EventMetadata,
+    modect::MotionDetectionEvent,
+    modect_mp4,
+    mqtt::Mqtt,
+    pushover::Pushover,
+    webhook::Webhook,
+};
+
+lazy_static::lazy_static! {
+    static ref ALERT_FAILURES: IntCounterVec = register_int_counter_vec!("rmr_alert_failures", "count of alerts a notifier failed to deliver", &["camera", "notifier"]).unwrap();
+    static ref ALERTS_SUPPRESSED: IntCounterVec = register_int_counter_vec!("rmr_alerts_suppressed", "count of alerts held back by a camera's alert cooldown", &["camera", "mode"]).unwrap();
+    static ref COOLDOWNS: Mutex<HashMap<String, Cooldown>> = Mutex::new(HashMap::new());
+}
+
+/// Names of every notifier, as used by `AlertRule::notifiers`
+pub const NOTIFIERS: &[&str] = &["pushover", "webhook", "mqtt", "email"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Confirmed,
+    Completed,
+    CompletedAfterConfirm,
+}
+
+/// A motion event to notify about
+pub struct Alert {
+    pub time: DateTime<Utc>,
+    pub camera: String,
+    pub state: AlertState,
+    pub event: Arc<MotionDetectionEvent>,
+    pub frame_rate: f64,
+    /// Camera override of the Pushover priority
+    pub priority: Option<PushoverPriority>,
+    /// Filename of the event clip in `CONFIG.event_dir`, only known once the event completed
+    pub event_filename: Option<String>,
+    /// Filename of the preview JPEG in `CONFIG.event_dir`, written once the alert is sent
+    pub preview_filename: Option<String>,
+    /// Set if this alert summarizes the alerts of an aggregating cooldown window, in which case it is the highest scoring one
+    pub aggregate: Option<Aggregate>,
+    /// What the classifier saw in the event, most confident first
+    pub labels: Vec<Label>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aggregate {
+    /// Events during the window, including the one whose alert opened it
+    pub events: usize,
+    pub window: Duration,
+}
+
+fn format_window(window: Duration) -> String {
+    let secs = window.as_secs();
+    match secs {
+        1 => "second".to_string(),
+        60 => "minute".to_string(),
+        3600 => "hour".to_string(),
+        _ if secs % 3600 == 0 => format!("{} hours", secs / 3600),
+        _ if secs % 60 == 0 => format!("{} minutes", secs / 60),
+        _ => format!("{secs} seconds"),
+    }
+}
+
+impl Alert {
+    pub fn metadata(&self) -> EventMetadata {
+        EventMetadata {
+            labels: self.labels.clone(),
+            ..EventMetadata::new(&self.camera, self.time, &self.event)
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let camera_name = &self.camera;
+        if let Some(aggregate) = &self.aggregate {
+            let events = aggregate.events;
+            return format!(
+                "{events} {} on {camera_name} in the last {}",
+                if events == 1 { "event" } else { "events" },
+                format_window(aggregate.window)
+            );
+        }
+        match self.state {
+            AlertState::Confirmed => format!("Ongoing Motion @ {camera_name}"),
+            AlertState::Completed => format!("Motion @ {camera_name}"),
+            AlertState::CompletedAfterConfirm => format!("Ended Ongoing Motion @ {camera_name}"),
+        }
+    }
+
+    fn external_url(filename: &Option<String>) -> Option<Url> {
+        let config = CONFIG.get();
+        let external_url = config.external_url.as_ref()?;
+        external_url
+            .join(&format!("events/{}", filename.as_ref()?))
+            .ok()
+    }
+
+    /// Link to the event clip in the web UI, if `CONFIG.external_url` is set
+    pub fn event_url(&self) -> Option<Url> {
+        Self::external_url(&self.event_filename)
+    }
+
+    /// Link to the preview JPEG in the web UI, if `CONFIG.external_url` is set
+    pub fn preview_url(&self) -> Option<Url> {
+        Self::external_url(&self.preview_filename)
+    }
+}
+
+/// A destination for alerts
+pub trait Notifier: Send + Sync {
+    /// Name for logs and metrics
+    fn name(&self) -> &'static str;
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<()>>;
+}
+
+fn notifiers(config: &Config) -> Vec<Box<dyn Notifier>> {
+    let mut out: Vec<Box<dyn Notifier>> = vec![];
+    if let Some(pushover) = &config.pushover {
+        out.push(Box::new(Pushover::new(pushover.clone())));
+    }
+    for webhook in &config.webhooks {
+        out.push(Box::new(Webhook::new(webhook.clone())));
+    }
+    if let Some(mqtt) = config.mqtt.as_ref().filter(|x| x.alerts) {
+        out.push(Box::new(Mqtt::new(mqtt.clone())));
+    }
+    if let Some(smtp) = &config.smtp {
+        out.push(Box::new(Email::new(smtp.clone())));
+    }
+    out
+}
+
+/// Days and times are compared in the local timezone of the host (`TZ`), as that is what people write schedules in
+fn rule_matches(rule: &AlertRule, alert: &Alert) -> bool {
+    let local_time = alert.time.with_timezone(&Local);
+    let score = alert.event.total_score;
+    rule.cameras
+        .as_ref()
+        .map_or(true, |x| x.contains(&alert.camera))
+        && rule
+            .states
+            .as_ref()
+            .map_or(true, |x| x.contains(&alert.state))
+        && rule
+            .days
+            .as_ref()
+            .map_or(true, |x| x.contains(&local_time.weekday()))
+        && rule
+            .times
+            .as_ref()
+            .map_or(true, |x| x.iter().any(|x| x.contains(local_time.time())))
+        && rule.min_score.map_or(true, |x| score >= x)
+        && rule.max_score.map_or(true, |x| score <= x)
+}
+
+/// Notifiers chosen by the first matching `alert_rules` entry, applying the rule's priority to the alert
+fn route(config: &Config, alert: &mut Alert) -> Vec<Box<dyn Notifier>> {
+    let rule = config.alert_rules.iter().find(|x| rule_matches(x, alert));
+    let mut notifiers = notifiers(config);
+    if let Some(rule) = rule {
+        if let Some(names) = &rule.notifiers {
+            notifiers.retain(|x| names.iter().any(|name| name == x.name()));
+        }
+        if rule.priority.is_some() {
+            alert.priority = rule.priority;
+        }
+    }
+    notifiers
+}
+
+/// Writes the preview JPEG the alert links to, cleared if that fails
+async fn save_preview(alert: &mut Alert) {
+    let Some(filename) = &alert.preview_filename else {
+        return;
+    };
+    let path = CONFIG.get().event_dir.join(filename);
+    if let Err(e) = modect_mp4::preview_jpeg(&alert.event, &path).await {
+        error!("failed to save event preview: {e:#}");
+        alert.preview_filename = None;
+    }
+}
+
+async fn send(mut alert: Alert, notifiers: Vec<Box<dyn Notifier>>) {
+    save_preview(&mut alert).await;
+    let results = join_all(notifiers.iter().map(|x| x.notify(&alert))).await;
+    for (notifier, result) in notifiers.iter().zip(results) {
+        if let Err(e) = result {
+            error!(
+                "{}: failed to send alert via {}: {e:#}",
+                alert.camera,
+                notifier.name()
+            );
+            ALERT_FAILURES
+                .with_label_values(&[&alert.camera, notifier.name()])
+                .inc();
+        }
+    }
+}
+
+/// Alerts held back during a camera's cooldown window
+struct Cooldown {
+    until: Instant,
+    /// Highest scoring alert held back by an aggregating cooldown
+    best: Option<Alert>,
+    /// Events during the window, including one whose alert opened it, not counting the ends of already alerted events
+    events: usize,
+    /// True once an aggregating cooldown held back an event, so a summary is due when the window ends
+    pending: bool,
+    /// True while a task is waiting to send the summary of an aggregating cooldown
+    flushing: bool,
+}
+
+/// Applies the camera's alert cooldown, returning the alert if it should be sent right away
+fn cooldown(config: &Config, alert: Alert) -> Option<Alert> {
+    let Some(cooldown_config) = config
+        .cameras
+        .get(&alert.camera)
+        .and_then(|x| x.motion_detection.as_ref())
+        .and_then(|x| x.alert_cooldown.as_ref())
+    else {
+        return Some(alert);
+    };
+    let window = Duration::from_secs(cooldown_config.window_secs);
+    let now = Instant::now();
+    let mut cooldowns = COOLDOWNS.lock().unwrap();
+    let cooldown = match cooldowns.get_mut(&alert.camera) {
+        Some(cooldown) if cooldown.until > now || cooldown.flushing => cooldown,
+        _ => {
+            cooldowns.insert(
+                alert.camera.clone(),
+                Cooldown {
+                    until: now + window,
+                    best: None,
+                    events: usize::from(alert.state != AlertState::CompletedAfterConfirm),
+                    pending: false,
+                    flushing: false,
+                },
+            );
+            return Some(alert);
+        }
+    };
+    let mode = match cooldown_config.mode {
+        CooldownMode::Suppress => "suppress",
+        CooldownMode::Aggregate => "aggregate",
+    };
+    ALERTS_SUPPRESSED
+        .with_label_values(&[&alert.camera, mode])
+        .inc();
+    if cooldown_config.mode == CooldownMode::Suppress {
+        return None;
+    }
+
+    if alert.state != AlertState::CompletedAfterConfirm {
+        cooldown.events += 1;
+        cooldown.pending = true;
+    }
+    if !cooldown.flushing {
+        cooldown.flushing = true;
+        tokio::spawn(flush_cooldown(alert.camera.clone(), window));
+    }
+    if cooldown
+        .best
+        .as_ref()
+        .map_or(true, |x| alert.event.total_score > x.event.total_score)
+    {
+        cooldown.best = Some(alert);
+    }
+    None
+}
+
+/// Takes the summary of a camera's ended aggregating cooldown window, None once a window passed without alerts
+fn take_summary(camera: &str, window: Duration) -> Option<Alert> {
+    let mut cooldowns = COOLDOWNS.lock().unwrap();
+    let cooldown = cooldowns.get_mut(camera)?;
+    let events = std::mem::take(&mut cooldown.events);
+    let pending = std::mem::take(&mut cooldown.pending);
+    let Some(mut alert) = cooldown.best.take().filter(|_| pending) else {
+        cooldown.flushing = false;
+        return None;
+    };
+    // the summary starts a new window, so a continuous storm results in one alert per window
+    cooldown.until = Instant::now() + window;
+    alert.aggregate = Some(Aggregate { events, window });
+    Some(alert)
+}
+
+/// Sends a summary of the alerts held back by a camera's aggregating cooldown whenever its window ends, until a window passes without alerts
+async fn flush_cooldown(camera: String, window: Duration) {
+    loop {
+        let Some(until) = COOLDOWNS.lock().unwrap().get(&camera).map(|x| x.until) else {
+            return;
+        };
+        tokio::time::sleep_until(until.into()).await;
+
+        let Some(mut alert) = take_summary(&camera, window) else {
+            return;
+        };
+        // routed again, the rules may have been reloaded during the window
+        let notifiers = route(&CONFIG.get(), &mut alert);
+        if !notifiers.is_empty() {
+            send(alert, notifiers).await;
+        }
+    }
+}
+
+/// Sends an alert through the notifiers chosen by the first matching `CONFIG.alert_rules` entry, returning once all of them finished.
+/// Rules and cooldowns are applied before the preview JPEG is written or notifiers encode any previews, so dropped alerts cost nothing.
+/// Returns false if no notifier was chosen or the alert was held back by a cooldown.
+pub async fn dispatch(mut alert: Alert) -> bool {
+    let config = CONFIG.get();
+    let notifiers = route(&config, &mut alert);
+    // alerts dropped by the rules don't start a cooldown
+    if notifiers.is_empty() {
+        return false;
+    }
+    let Some(alert) = cooldown(&config, alert) else {
+        return false;
+    };
+    send(alert, notifiers).await;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, TimeZone, Weekday};
+
+    use super::*;
+    use crate::config::{test_config, TimeWindow};
+
+    const CONFIG_YAML: &str = r"
+cameras: {}
+pushover:
+  user_key: user
+  token: token
+webhooks:
+  - url: http://localhost/hook
+";
+
+    /// An alert on Saturday 2026-10-17 at the given local time
+    fn alert(camera: &str, hour: u32, minute: u32, total_score: f64) -> Alert {
+        Alert {
+            time: Local
+                .with_ymd_and_hms(2026, 10, 17, hour, minute, 0)
+                .unwrap()
+                .with_timezone(&Utc),
+            camera: camera.to_string(),
+            state: AlertState::Completed,
+            event: Arc::new(MotionDetectionEvent {
+                start_stream_frame_number: 0,
+                end_stream_frame_number: 0,
+                frames: vec![],
+                total_score,
+                zones: vec![],
+                tracks: vec![],
+                rules: vec![],
+                profile: None,
+            }),
+            frame_rate: 10.0,
+            priority: None,
+            event_filename: None,
+            preview_filename: None,
+            aggregate: None,
+            labels: vec![],
+        }
+    }
+
+    const WINDOW: Duration = Duration::from_secs(600);
+
+    /// A config with one camera that has a 10 minute alert cooldown in `mode`
+    fn cooldown_config(camera: &str, mode: &str) -> Config {
+        test_config(&format!(
+            r"
+cameras:
+  {camera}:
+    rtsp: rtsp://camera/{camera}
+    mode: motion_detect
+    motion_detection:
+      width: 64
+      height: 48
+      change_minimum: 200.0
+      change_maximum: 100000.0
+      stddev_minimum: 100.0
+      minimum_frame_count: 25
+      minimum_total_change: 10000.0
+      followup_frame_count: 25
+      maximum_frame_wait: 0
+      mask_file: null
+      alert_cooldown:
+        window_secs: 600
+        mode: {mode}
+"
+        ))
+    }
+
+    fn rule() -> AlertRule {
+        AlertRule {
+            cameras: None,
+            states: None,
+            days: None,
+            times: None,
+            min_score: None,
+            max_score: None,
+            notifiers: None,
+            priority: None,
+        }
+    }
+
+    fn window(start: &str, end: &str) -> TimeWindow {
+        TimeWindow {
+            start: NaiveTime::parse_from_str(start, "%H:%M").unwrap(),
+            end: NaiveTime::parse_from_str(end, "%H:%M").unwrap(),
+        }
+    }
+
+    #[test]
+    fn empty_rule_matches_everything() {
+        assert!(rule_matches(&rule(), &alert("front", 12, 0, 1.0)));
+    }
+
+    #[test]
+    fn camera_and_state_filters() {
+        let rule = AlertRule {
+            cameras: Some(vec!["front".to_string()]),
+            states: Some(vec![AlertState::Confirmed]),
+            ..rule()
+        };
+        let mut front = alert("front", 12, 0, 1.0);
+        assert!(!rule_matches(&rule, &front));
+        front.state = AlertState::Confirmed;
+        assert!(rule_matches(&rule, &front));
+        let mut back = alert("back", 12, 0, 1.0);
+        back.state = AlertState::Confirmed;
+        assert!(!rule_matches(&rule, &back));
+    }
+
+    #[test]
+    fn day_filter() {
+        let weekend = AlertRule {
+            days: Some(vec![Weekday::Sat, Weekday::Sun]),
+            ..rule()
+        };
+        let weekdays = AlertRule {
+            days: Some(vec![Weekday::Mon, Weekday::Fri]),
+            ..rule()
+        };
+        assert!(rule_matches(&weekend, &alert("front", 12, 0, 1.0)));
+        assert!(!rule_matches(&weekdays, &alert("front", 12, 0, 1.0)));
+    }
+
+    #[test]
+    fn time_filter() {
+        let rule = AlertRule {
+            times: Some(vec![window("07:00", "09:00"), window("22:00", "02:00")]),
+            ..rule()
+        };
+        assert!(rule_matches(&rule, &alert("front", 8, 0, 1.0)));
+        assert!(rule_matches(&rule, &alert("front", 23, 30, 1.0)));
+        assert!(rule_matches(&rule, &alert("front", 1, 0, 1.0)));
+        assert!(!rule_matches(&rule, &alert("front", 12, 0, 1.0)));
+    }
+
+    #[test]
+    fn score_bounds_are_inclusive() {
+        let rule = AlertRule {
+            min_score: Some(2.0),
+            max_score: Some(5.0),
+            ..rule()
+        };
+        assert!(!rule_matches(&rule, &alert("front", 12, 0, 1.999)));
+        assert!(rule_matches(&rule, &alert("front", 12, 0, 2.0)));
+        assert!(rule_matches(&rule, &alert("front", 12, 0, 5.0)));
+        assert!(!rule_matches(&rule, &alert("front", 12, 0, 5.001)));
+    }
+
+    fn routed(config: &Config, alert: &mut Alert) -> Vec<&'static str> {
+        route(config, alert).iter().map(|x| x.name()).collect()
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let mut config = test_config(CONFIG_YAML);
+        config.alert_rules = vec![
+            AlertRule {
+                cameras: Some(vec!["front".to_string()]),
+                notifiers: Some(vec!["webhook".to_string()]),
+                priority: Some(PushoverPriority::High),
+                ..rule()
+            },
+            AlertRule {
+                notifiers: Some(vec![]),
+                priority: Some(PushoverPriority::Low),
+                ..rule()
+            },
+        ];
+        let mut front = alert("front", 12, 0, 1.0);
+        assert_eq!(routed(&config, &mut front), ["webhook"]);
+        assert_eq!(front.priority, Some(PushoverPriority::High));
+        assert!(routed(&config, &mut alert("back", 12, 0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn unmatched_alerts_go_everywhere() {
+        let mut config = test_config(CONFIG_YAML);
+        config.alert_rules = vec![AlertRule {
+            min_score: Some(10.0),
+            notifiers: Some(vec![]),
+            priority: Some(PushoverPriority::Low),
+            ..rule()
+        }];
+        let mut alert = alert("front", 12, 0, 1.0);
+        assert_eq!(routed(&config, &mut alert), ["pushover", "webhook"]);
+        assert_eq!(alert.priority, None);
+    }
+
+    // each cooldown test uses its own camera, as the cooldowns and counters are global
+
+    #[test]
+    fn suppress_cooldown() {
+        let config = cooldown_config("suppress_test", "suppress");
+        let suppressed = || {
+            ALERTS_SUPPRESSED
+                .with_label_values(&["suppress_test", "suppress"])
+                .get()
+        };
+        assert!(cooldown(&config, alert("suppress_test", 12, 0, 1.0)).is_some());
+        assert!(cooldown(&config, alert("suppress_test", 12, 1, 5.0)).is_none());
+        assert!(cooldown(&config, alert("suppress_test", 12, 2, 1.0)).is_none());
+        assert_eq!(suppressed(), 2);
+        let cooldowns = COOLDOWNS.lock().unwrap();
+        assert!(cooldowns["suppress_test"].best.is_none());
+        assert!(!cooldowns["suppress_test"].flushing);
+    }
+
+    #[test]
+    fn cameras_without_cooldown() {
+        let config = cooldown_config("other_test", "suppress");
+        for minute in 0..3 {
+            assert!(cooldown(&config, alert("uncooled_test", 12, minute, 1.0)).is_some());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aggregate_cooldown() {
+        let camera = "aggregate_test";
+        let config = cooldown_config(camera, "aggregate");
+        assert!(cooldown(&config, alert(camera, 12, 0, 1.0)).is_some());
+        for (minute, score) in [(1, 2.0), (2, 7.0), (3, 3.0)] {
+            assert!(cooldown(&config, alert(camera, 12, minute, score)).is_none());
+        }
+        assert_eq!(
+            ALERTS_SUPPRESSED
+                .with_label_values(&[camera, "aggregate"])
+                .get(),
+            3
+        );
+
+        // taken without yielding, the spawned flush task never runs and routes through `CONFIG`
+        let summary = take_summary(camera, WINDOW).unwrap();
+        assert_eq!(summary.time, alert(camera, 12, 2, 7.0).time);
+        assert_eq!(summary.event.total_score, 7.0);
+        assert_eq!(summary.aggregate.unwrap().events, 4);
+        assert_eq!(
+            summary.title(),
+            "4 events on aggregate_test in the last 10 minutes"
+        );
+
+        // the summary opened a new window, in which a single event is held back
+        assert!(cooldown(&config, alert(camera, 12, 20, 1.0)).is_none());
+        let summary = take_summary(camera, WINDOW).unwrap();
+        assert_eq!(summary.aggregate.unwrap().events, 1);
+        assert_eq!(
+            summary.title(),
+            "1 event on aggregate_test in the last 10 minutes"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cooldown_ignores_ends_of_alerted_events() {
+        let camera = "ended_test";
+        let config = cooldown_config(camera, "aggregate");
+        let ended = |minute| {
+            let mut alert = alert(camera, 12, minute, 5.0);
+            alert.state = AlertState::CompletedAfterConfirm;
+            alert
+        };
+        assert!(cooldown(&config, alert(camera, 12, 0, 1.0)).is_some());
+        assert!(cooldown(&config, ended(1)).is_none());
+        // nothing new happened in the window
+        assert!(take_summary(camera, WINDOW).is_none());
+
+        COOLDOWNS.lock().unwrap().remove(camera);
+        assert!(cooldown(&config, ended(20)).is_some());
+        assert!(cooldown(&config, alert(camera, 12, 21, 1.0)).is_none());
+        let summary = take_summary(camera, WINDOW).unwrap();
+        // the end of an alerted event opening the window isn't counted either
+        assert_eq!(summary.aggregate.unwrap().events, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flush_ends_after_a_quiet_window() {
+        let camera = "quiet_test";
+        let config = cooldown_config(camera, "aggregate");
+        let mut ended = alert(camera, 12, 1, 1.0);
+        ended.state = AlertState::CompletedAfterConfirm;
+        assert!(cooldown(&config, alert(camera, 12, 0, 1.0)).is_some());
+        // starts the flush task, which has nothing to summarize once the window ends
+        assert!(cooldown(&config, ended).is_none());
+        assert!(COOLDOWNS.lock().unwrap()[camera].flushing);
+
+        // the paused clock jumps to the end of the window
+        flush_cooldown(camera.to_string(), WINDOW).await;
+        assert!(!COOLDOWNS.lock().unwrap()[camera].flushing);
+    }
+}
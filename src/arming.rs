@@ -0,0 +1,169 @@
+use std::sync::RwLock;
+
+use anyhow::Result;
+use log::{error, info};
+
+use crate::{
+    config::{ArmingModeConfig, Config, CONFIG},
+    index,
+};
+
+const SETTING_KEY: &str = "arming_mode";
+
+lazy_static::lazy_static! {
+    /// Mode last set through the API, persisted in the index
+    static ref MODE: RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Loads the mode persisted by a previous run, before any camera starts
+pub async fn init() {
+    if CONFIG.get().arming.is_none() {
+        return;
+    }
+    if let Err(e) = load().await {
+        error!("failed to load arming mode, using the default: {e:#}");
+    }
+    if let Some(mode) = current() {
+        info!("arming mode: {mode}");
+    }
+}
+
+/// Restores the mode persisted by `set`
+async fn load() -> Result<()> {
+    *MODE.write().unwrap() = index::get_setting(SETTING_KEY).await?;
+    Ok(())
+}
+
+/// Name of the current mode, None if arming is not configured.
+/// Falls back to the default mode if the set mode was removed from the config.
+pub fn current() -> Option<String> {
+    resolve(&CONFIG.get(), MODE.read().unwrap().as_deref())
+}
+
+/// Name of the mode of `config` in effect when `mode` was set, the default if it is unset or no longer configured
+fn resolve(config: &Config, mode: Option<&str>) -> Option<String> {
+    let arming = config.arming.as_ref()?;
+    Some(match mode {
+        Some(mode) if arming.modes.contains_key(mode) => mode.to_string(),
+        _ => arming.default_mode.clone(),
+    })
+}
+
+/// True if the mode in effect doesn't restrict the cameras selected by `cameras`, or lists this one
+fn allows(
+    config: &Config,
+    mode: Option<&str>,
+    camera: &str,
+    cameras: impl FnOnce(&ArmingModeConfig) -> &Option<Vec<String>>,
+) -> bool {
+    let mode = resolve(config, mode).and_then(|mode| config.arming.as_ref()?.modes.get(&mode));
+    mode.map_or(true, |x| {
+        cameras(x)
+            .as_ref()
+            .map_or(true, |x| x.iter().any(|x| x == camera))
+    })
+}
+
+/// True if the camera sends alerts in the current mode
+pub fn alerts(camera: &str) -> bool {
+    let mode = MODE.read().unwrap().clone();
+    allows(&CONFIG.get(), mode.as_deref(), camera, |x| &x.alert)
+}
+
+/// True if the camera saves motion events in the current mode
+pub fn records_events(camera: &str) -> bool {
+    let mode = MODE.read().unwrap().clone();
+    allows(&CONFIG.get(), mode.as_deref(), camera, |x| &x.record_events)
+}
+
+/// Switches to a mode, which must exist in `CONFIG.arming`, and persists it
+pub async fn set(mode: String) -> Result<()> {
+    index::set_setting(SETTING_KEY, mode.clone()).await?;
+    info!("arming mode set to {mode}");
+    *MODE.write().unwrap() = Some(mode);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    const CONFIG_YAML: &str = r"
+cameras:
+  front:
+    rtsp: rtsp://camera/front
+    mode: record
+  back:
+    rtsp: rtsp://camera/back
+    mode: record
+arming:
+  default_mode: home
+  modes:
+    home:
+      alert: []
+      record_events: [ front ]
+    night:
+      alert: [ front ]
+    away: {}
+";
+
+    fn config() -> Config {
+        test_config(CONFIG_YAML)
+    }
+
+    #[test]
+    fn mode_fallback() {
+        let config = config();
+        assert_eq!(resolve(&config, None).as_deref(), Some("home"));
+        assert_eq!(resolve(&config, Some("away")).as_deref(), Some("away"));
+        // removed from the config since it was set
+        assert_eq!(resolve(&config, Some("vacation")).as_deref(), Some("home"));
+        let mut unarmed = config;
+        unarmed.arming = None;
+        assert_eq!(resolve(&unarmed, Some("away")), None);
+    }
+
+    #[test]
+    fn camera_gating() {
+        let mut unarmed = config();
+        unarmed.arming = None;
+        assert!(allows(&unarmed, Some("home"), "back", |x| &x.alert));
+
+        let config = config();
+        let alerts = |mode, camera| allows(&config, mode, camera, |x| &x.alert);
+        let records = |mode, camera| allows(&config, mode, camera, |x| &x.record_events);
+
+        // the default mode
+        assert!(!alerts(None, "front"));
+        assert!(records(None, "front"));
+        assert!(!records(None, "back"));
+
+        assert!(alerts(Some("night"), "front"));
+        assert!(!alerts(Some("night"), "back"));
+        assert!(records(Some("night"), "back"));
+
+        assert!(alerts(Some("away"), "back"));
+        assert!(records(Some("away"), "back"));
+    }
+
+    /// The only test opening the global index, which can be opened once per process
+    #[tokio::test]
+    async fn mode_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        index::open(&dir.path().join("index.db")).unwrap();
+        load().await.unwrap();
+        assert_eq!(*MODE.read().unwrap(), None);
+
+        set("night".to_string()).await.unwrap();
+        set("away".to_string()).await.unwrap();
+        // as after a restart
+        *MODE.write().unwrap() = None;
+        load().await.unwrap();
+        assert_eq!(MODE.read().unwrap().as_deref(), Some("away"));
+        assert_eq!(
+            index::get_setting(SETTING_KEY).await.unwrap().as_deref(),
+            Some("away")
+        );
+    }
+}
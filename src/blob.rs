@@ -0,0 +1,237 @@
+use image::{GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+
+fn default_pixel_threshold() -> u32 {
+    900
+}
+
+fn default_morphology_radius() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlobConfig {
+    /// Squared RGB distance above which a pixel changed with the `frame_diff` algorithm, the default is a change of 30 in one channel.
+    /// The `background` algorithm uses its foreground instead.
+    #[serde(default = "default_pixel_threshold")]
+    pub pixel_threshold: u32,
+    /// Changed pixels are eroded then dilated by this many pixels to drop noise before labelling, 0 to disable
+    #[serde(default = "default_morphology_radius")]
+    pub morphology_radius: u32,
+    /// Smallest object in pixels at the detection resolution, smaller blobs are ignored
+    #[serde(default)]
+    pub minimum_area: u32,
+    /// Largest object in pixels at the detection resolution, larger blobs (e.g. a whole frame lighting change) are ignored
+    pub maximum_area: Option<u32>,
+}
+
+impl BlobConfig {
+    pub fn accepts(&self, blob: &Blob) -> bool {
+        blob.area >= self.minimum_area && self.maximum_area.map_or(true, |x| blob.area <= x)
+    }
+}
+
+/// A connected region of changed pixels
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Blob {
+    /// Bounding box in pixels at the detection resolution
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Number of changed pixels
+    pub area: u32,
+    /// Average position of the changed pixels
+    pub centroid: [f64; 2],
+}
+
+impl Blob {
+    /// Intersection over union of the bounding boxes
+    pub fn iou(&self, other: &Blob) -> f64 {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if right <= left || bottom <= top {
+            return 0.0;
+        }
+        let intersection = ((right - left) * (bottom - top)) as f64;
+        let union = (self.width * self.height + other.width * other.height) as f64 - intersection;
+        intersection / union
+    }
+}
+
+/// One pass of a square erosion (`all`) or dilation along one axis, out of frame pixels are ignored
+fn morphology_pass(mask: &GrayImage, radius: u32, all: bool, vertical: bool) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let (lines, length) = if vertical {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    let mut out = GrayImage::new(width, height);
+    let at = |line: u32, i: u32| if vertical { (line, i) } else { (i, line) };
+    for line in 0..lines {
+        // number of set pixels in the window, which slides one pixel at a time
+        let mut set = 0u32;
+        for i in 0..radius.min(length) {
+            let (x, y) = at(line, i);
+            set += (mask.get_pixel(x, y).0[0] != 0) as u32;
+        }
+        for i in 0..length {
+            if i + radius < length {
+                let (x, y) = at(line, i + radius);
+                set += (mask.get_pixel(x, y).0[0] != 0) as u32;
+            }
+            if i > radius {
+                let (x, y) = at(line, i - radius - 1);
+                set -= (mask.get_pixel(x, y).0[0] != 0) as u32;
+            }
+            let window = (i + radius).min(length - 1) - i.saturating_sub(radius) + 1;
+            let value = if all { set == window } else { set > 0 };
+            let (x, y) = at(line, i);
+            out.put_pixel(x, y, Luma([if value { 255 } else { 0 }]));
+        }
+    }
+    out
+}
+
+/// Morphological opening, removes specks and thin lines smaller than the radius
+pub fn open(mask: &GrayImage, radius: u32) -> GrayImage {
+    if radius == 0 {
+        return mask.clone();
+    }
+    let eroded = morphology_pass(
+        &morphology_pass(mask, radius, true, false),
+        radius,
+        true,
+        true,
+    );
+    morphology_pass(
+        &morphology_pass(&eroded, radius, false, false),
+        radius,
+        false,
+        true,
+    )
+}
+
+/// 8-connected components of the non-zero pixels of `mask`
+pub fn label(mask: &GrayImage) -> Vec<Blob> {
+    let (width, height) = mask.dimensions();
+    let mut visited = vec![false; (width * height) as usize];
+    let mut blobs = vec![];
+    let mut stack = vec![];
+    for (start_x, start_y, pixel) in mask.enumerate_pixels() {
+        let start = (start_y * width + start_x) as usize;
+        if pixel.0[0] == 0 || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push((start_x, start_y));
+        let (mut left, mut top, mut right, mut bottom) = (start_x, start_y, start_x, start_y);
+        let (mut area, mut sum_x, mut sum_y) = (0u32, 0u64, 0u64);
+        while let Some((x, y)) = stack.pop() {
+            area += 1;
+            sum_x += x as u64;
+            sum_y += y as u64;
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x);
+            bottom = bottom.max(y);
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let index = (ny * width + nx) as usize;
+                    if !visited[index] && mask.get_pixel(nx, ny).0[0] != 0 {
+                        visited[index] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+        blobs.push(Blob {
+            x: left,
+            y: top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+            area,
+            centroid: [
+                sum_x as f64 / area as f64 + 0.5,
+                sum_y as f64 / area as f64 + 0.5,
+            ],
+        });
+    }
+    blobs
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::{label, open, Blob};
+
+    fn mask(width: u32, height: u32, set: &[(u32, u32)]) -> GrayImage {
+        let mut out = GrayImage::new(width, height);
+        for (x, y) in set {
+            out.put_pixel(*x, *y, Luma([255]));
+        }
+        out
+    }
+
+    fn set_pixels(mask: &GrayImage) -> Vec<(u32, u32)> {
+        mask.enumerate_pixels()
+            .filter(|x| x.2 .0[0] != 0)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn open_removes_specks() {
+        let square = (2..7)
+            .flat_map(|y| (2..7).map(move |x| (x, y)))
+            .collect::<Vec<_>>();
+        let mut pixels = square.clone();
+        // a speck and a line one pixel thick
+        pixels.push((10, 10));
+        pixels.extend((0..12).map(|y| (11, y)));
+        let opened = open(&mask(12, 12, &pixels), 1);
+        assert_eq!(set_pixels(&opened), square);
+        assert_eq!(open(&mask(12, 12, &pixels), 0), mask(12, 12, &pixels));
+    }
+
+    #[test]
+    fn open_keeps_squares_at_the_edge() {
+        let square = (0..3)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .collect::<Vec<_>>();
+        assert_eq!(set_pixels(&open(&mask(8, 8, &square), 1)), square);
+    }
+
+    #[test]
+    fn label_components() {
+        let mut pixels = vec![(0, 0), (1, 1)];
+        pixels.extend((4..6).flat_map(|y| (5..8).map(move |x| (x, y))));
+        assert_eq!(
+            label(&mask(10, 8, &pixels)),
+            [
+                // diagonal neighbours are connected
+                Blob {
+                    x: 0,
+                    y: 0,
+                    width: 2,
+                    height: 2,
+                    area: 2,
+                    centroid: [1.0, 1.0],
+                },
+                Blob {
+                    x: 5,
+                    y: 4,
+                    width: 3,
+                    height: 2,
+                    area: 6,
+                    centroid: [6.5, 5.0],
+                },
+            ]
+        );
+        assert!(label(&mask(4, 4, &[])).is_empty());
+    }
+}
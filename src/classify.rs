@@ -0,0 +1,252 @@
+use std::{
+    cmp::Ordering,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use futures::future::{BoxFuture, Shared};
+use image::{imageops::FilterType, RgbImage};
+use log::{error, info};
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::{Deserialize, Serialize};
+use tract_onnx::prelude::*;
+
+use crate::{
+    config::{ClassificationConfig, ClassifierConfig, CONFIG},
+    modect::MotionDetectionEvent,
+};
+
+lazy_static::lazy_static! {
+    /// Loaded on first use and whenever `classifier` changes
+    static ref CLASSIFIER: Mutex<Option<(ClassifierConfig, Arc<Classifier>)>> = Mutex::new(None);
+    static ref CLASSIFY_SECONDS: HistogramVec = register_histogram_vec!("rmr_classify_seconds", "time to classify an event", &["camera"]).unwrap();
+    static ref ALERTS_UNCLASSIFIED: IntCounterVec = register_int_counter_vec!("rmr_alerts_unclassified", "alerts dropped for lacking a required label", &["camera"]).unwrap();
+}
+
+/// A label the classifier found in an event, with its highest confidence over the classified frames
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Label {
+    pub label: String,
+    pub confidence: f32,
+}
+
+type Model = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+struct Classifier {
+    model: Model,
+    labels: Vec<String>,
+}
+
+/// One label per non-empty line, in the order of the model's outputs
+pub fn read_labels(config: &ClassifierConfig) -> Result<Vec<String>> {
+    let raw = std::fs::read_to_string(&config.labels)
+        .with_context(|| format!("failed to read labels '{}'", config.labels.display()))?;
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+impl Classifier {
+    fn load(config: &ClassifierConfig) -> Result<Self> {
+        let model = tract_onnx::onnx()
+            .model_for_path(&config.model)
+            .with_context(|| format!("failed to load model '{}'", config.model.display()))?
+            .with_input_fact(
+                0,
+                InferenceFact::dt_shape(
+                    f32::datum_type(),
+                    tvec!(
+                        1,
+                        3,
+                        config.input_height as usize,
+                        config.input_width as usize
+                    ),
+                ),
+            )?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(Self {
+            model,
+            labels: read_labels(config)?,
+        })
+    }
+
+    /// Confidence of each label for the frame
+    fn run(&self, config: &ClassifierConfig, frame: &RgbImage) -> Result<Vec<f32>> {
+        let (width, height) = (config.input_width, config.input_height);
+        let resized = image::imageops::resize(frame, width, height, FilterType::Triangle);
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn(
+            (1, 3, height as usize, width as usize),
+            |(_, c, y, x)| {
+                let value = resized.get_pixel(x as u32, y as u32).0[c] as f32 / 255.0;
+                (value - config.mean[c]) / config.std[c]
+            },
+        )
+        .into();
+        let outputs = self.model.run(tvec!(input.into()))?;
+        let mut scores: Vec<f32> = outputs[0].to_array_view::<f32>()?.iter().copied().collect();
+        if scores.len() != self.labels.len() {
+            bail!(
+                "model has {} outputs but there are {} labels",
+                scores.len(),
+                self.labels.len()
+            );
+        }
+        if config.softmax {
+            let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            scores.iter_mut().for_each(|x| *x = (*x - max).exp());
+            let sum: f32 = scores.iter().sum();
+            scores.iter_mut().for_each(|x| *x /= sum);
+        }
+        Ok(scores)
+    }
+}
+
+fn classifier(config: &ClassifierConfig) -> Result<Arc<Classifier>> {
+    let mut loaded = CLASSIFIER.lock().unwrap();
+    if let Some((loaded_config, classifier)) = &*loaded {
+        if loaded_config == config {
+            return Ok(classifier.clone());
+        }
+    }
+    info!("loading classifier '{}'", config.model.display());
+    let classifier = Arc::new(Classifier::load(config)?);
+    *loaded = Some((config.clone(), classifier.clone()));
+    Ok(classifier)
+}
+
+/// Labels at or above `min_confidence` in the event's most changed frames, most confident first
+fn classify_blocking(
+    classifier_config: &ClassifierConfig,
+    config: &ClassificationConfig,
+    event: &MotionDetectionEvent,
+) -> Result<Vec<Label>> {
+    let classifier = classifier(classifier_config)?;
+    let mut frames: Vec<_> = event.frames.iter().filter(|x| x.change > 0.0).collect();
+    frames.sort_by(|x, y| y.change.partial_cmp(&x.change).unwrap_or(Ordering::Equal));
+    let mut confidences = vec![0.0f32; classifier.labels.len()];
+    for frame in frames.into_iter().take(config.frames) {
+        for (confidence, score) in confidences
+            .iter_mut()
+            .zip(classifier.run(classifier_config, &frame.image)?)
+        {
+            *confidence = confidence.max(score);
+        }
+    }
+    let mut labels: Vec<Label> = classifier
+        .labels
+        .iter()
+        .zip(confidences)
+        .filter(|(_, confidence)| *confidence >= config.min_confidence)
+        .map(|(label, confidence)| Label {
+            label: label.clone(),
+            confidence,
+        })
+        .collect();
+    labels.sort_by(|x, y| {
+        y.confidence
+            .partial_cmp(&x.confidence)
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(labels)
+}
+
+/// Labels of one classification, awaited by everything that needs them
+pub type SharedLabels = Shared<BoxFuture<'static, Option<Vec<Label>>>>;
+
+/// Classifies the event if the camera is configured for it.
+/// None if it isn't or classification failed, so a broken model doesn't silence alerts.
+pub async fn classify(
+    camera: String,
+    config: Option<Arc<ClassificationConfig>>,
+    event: Arc<MotionDetectionEvent>,
+) -> Option<Vec<Label>> {
+    let (Some(config), Some(classifier_config)) = (config, CONFIG.get().classifier.clone()) else {
+        return None;
+    };
+    let timer = CLASSIFY_SECONDS.with_label_values(&[&camera]).start_timer();
+    let result =
+        tokio::task::spawn_blocking(move || classify_blocking(&classifier_config, &config, &event))
+            .await;
+    timer.observe_duration();
+    match result {
+        Ok(Ok(labels)) => Some(labels),
+        Ok(Err(e)) => {
+            error!("{camera}: failed to classify event: {e:#}");
+            None
+        }
+        Err(e) => {
+            error!("{camera}: classification panicked: {e}");
+            None
+        }
+    }
+}
+
+/// i.e. `person (92%), car (61%)`
+pub fn format_labels(labels: &[Label]) -> String {
+    labels
+        .iter()
+        .map(|x| format!("{} ({:.0}%)", x.label, x.confidence * 100.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// False if the camera requires labels the event wasn't classified with, counting the dropped alert
+pub fn allows_alert(
+    camera: &str,
+    config: Option<&ClassificationConfig>,
+    labels: Option<&[Label]>,
+) -> bool {
+    let (Some(config), Some(labels)) = (config, labels) else {
+        return true;
+    };
+    if config.require.is_empty() || labels.iter().any(|x| config.require.contains(&x.label)) {
+        return true;
+    }
+    ALERTS_UNCLASSIFIED.with_label_values(&[camera]).inc();
+    info!(
+        "{camera}: not alerting, none of {} found",
+        config.require.join(", ")
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(label: &str, confidence: f32) -> Label {
+        Label {
+            label: label.to_string(),
+            confidence,
+        }
+    }
+
+    #[test]
+    fn required_labels() {
+        let config: ClassificationConfig =
+            serde_yaml::from_str("require: [ person, car ]").unwrap();
+        let cat = [label("cat", 0.9)];
+        let car = [label("cat", 0.9), label("car", 0.6)];
+        assert!(!allows_alert("front", Some(&config), Some(&cat[..])));
+        assert!(allows_alert("front", Some(&config), Some(&car[..])));
+        // unclassified, or classification failed
+        assert!(allows_alert("front", None, Some(&cat[..])));
+        assert!(allows_alert("front", Some(&config), None));
+
+        let unrestricted: ClassificationConfig = serde_yaml::from_str("{}").unwrap();
+        assert!(allows_alert("front", Some(&unrestricted), Some(&[])));
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(
+            format_labels(&[label("person", 0.923), label("car", 0.61)]),
+            "person (92%), car (61%)"
+        );
+        assert_eq!(format_labels(&[]), "");
+    }
+}
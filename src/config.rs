@@ -1,10 +1,24 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
 
+use anyhow::{bail, Context};
+use chrono::{NaiveTime, Weekday};
+use image::GenericImageView;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::modect::RunningMotionDetectorConfig;
+use crate::{
+    alert::{AlertState, NOTIFIERS},
+    modect::RunningMotionDetectorConfig,
+    sun::Location,
+    zone::ZoneConfig,
+};
 
 fn default_ffmpeg_bin() -> String {
     "ffmpeg".to_string()
@@ -14,43 +28,120 @@ fn default_web_base() -> String {
     "/".to_string()
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_index_file() -> PathBuf {
+    "./rmr.sqlite".into()
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
 pub struct Config {
     pub prometheus_bind: Option<SocketAddr>,
     pub web_bind: SocketAddr,
     #[serde(default = "default_web_base")]
     pub web_base: String,
+    /// PEM certificate chain, `web_bind` serves HTTPS if set along with `tls_key`. Reloaded on change.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for `tls_cert`
+    pub tls_key: Option<PathBuf>,
+    /// if set, plain HTTP requests here are redirected to HTTPS on `web_bind`
+    pub http_redirect_bind: Option<SocketAddr>,
     pub cameras: IndexMap<String, CameraConfig>,
     #[serde(default = "default_ffmpeg_bin")]
     pub ffmpeg_bin: String,
     pub recording_dir: PathBuf,
     pub event_dir: PathBuf,
+    /// SQLite index of events and recordings (should be on local disk)
+    #[serde(default = "default_index_file")]
+    pub index_file: PathBuf,
     /// Live dir for HLS streaming (should be a ramdisk)
     pub live_dir: PathBuf,
     // if true, ffmpeg is forced to use TCP (useful on k8s)
     #[serde(default)]
     pub force_tcp: bool,
     pub pushover: Option<PushoverConfig>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub smtp: Option<SmtpConfig>,
+    /// The first matching rule decides which notifiers an alert is sent through and at what priority.
+    /// Alerts matching no rule are sent through every notifier.
+    #[serde(default)]
+    pub alert_rules: Vec<AlertRule>,
+    /// URL the web UI is reachable at by alert recipients, including `web_base`. Used to link events in alerts.
+    pub external_url: Option<Url>,
+    #[serde(default)]
+    pub retention: GlobalRetentionConfig,
+    /// If unset, the web UI and API are open to anyone that can reach `web_bind`
+    pub auth: Option<AuthConfig>,
+    /// If unset, every motion detecting camera always alerts and saves events
+    pub arming: Option<ArmingConfig>,
+    /// Image classification model for cameras with `motion_detection.classification`
+    pub classifier: Option<ClassifierConfig>,
+    /// Where the cameras are, for `day` and `night` detector profiles
+    pub location: Option<Location>,
+}
+
+fn default_retention_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RetentionConfig {
+    /// Recording segments older than this many seconds are deleted
+    pub max_age_secs: Option<u64>,
+    /// Oldest recording segments are deleted until a camera uses at most this many bytes
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GlobalRetentionConfig {
+    /// Defaults for every camera, overridden by `CameraConfig::retention`
+    #[serde(flatten)]
+    pub defaults: RetentionConfig,
+    /// If all recordings together exceed this many bytes, the oldest segments across all cameras are deleted...
+    pub high_watermark_bytes: Option<u64>,
+    /// ...until they use at most this many bytes (defaults to `high_watermark_bytes`)
+    pub low_watermark_bytes: Option<u64>,
+    /// Event clips are only deleted once they are older than this many seconds
+    pub event_max_age_secs: Option<u64>,
+    #[serde(default = "default_retention_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for GlobalRetentionConfig {
+    fn default() -> Self {
+        Self {
+            defaults: Default::default(),
+            high_watermark_bytes: None,
+            low_watermark_bytes: None,
+            event_max_age_secs: None,
+            interval_secs: default_retention_interval_secs(),
+        }
+    }
 }
 
 fn default_frame_rate() -> f64 {
     25.0
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct CameraConfig {
     pub rtsp: Url,
     pub mode: CameraMode,
     #[serde(default = "default_frame_rate")]
     pub frame_rate: f64,
     pub motion_detection: Option<MotionDetectionConfig>,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// email recipients for this camera's alerts, in addition to `smtp.to`
+    #[serde(default)]
+    pub email: Vec<String>,
 }
 
 fn default_pushover() -> Url {
     "https://api.pushover.net/1/messages.json".parse().unwrap()
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 #[repr(i32)]
 pub enum PushoverPriority {
@@ -63,7 +154,7 @@ pub enum PushoverPriority {
     Emergency = 2,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct PushoverConfig {
     #[serde(default = "default_pushover")]
     pub url: Url,
@@ -75,7 +166,191 @@ pub struct PushoverConfig {
     pub priority: PushoverPriority,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+fn default_session_ttl_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct AuthConfig {
+    pub users: IndexMap<String, UserConfig>,
+    /// if true, HTTP basic auth is accepted for API clients
+    #[serde(default)]
+    pub allow_basic: bool,
+    #[serde(default = "default_session_ttl_secs")]
+    pub session_ttl_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct UserConfig {
+    /// argon2 PHC string, as printed by `rmr hash-password`. Users without one can only use API tokens.
+    pub password_hash: Option<String>,
+    /// hex encoded SHA-256 digests of bearer tokens for API clients
+    #[serde(default)]
+    pub api_tokens: Vec<String>,
+    /// cameras this user can access, all if unset
+    pub cameras: Option<Vec<String>>,
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct WebhookConfig {
+    pub url: Url,
+    /// extra headers sent with every request, e.g. for authentication
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    /// if set, the body is signed with HMAC-SHA256 in the `X-Rmr-Signature` header as `sha256=<hex>`
+    pub secret: Option<String>,
+    /// retries after failed attempts, with exponential backoff
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "rmr".to_string()
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "rmr".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// topics are `<topic_prefix>/status` (retained availability) and `<topic_prefix>/<camera>/{motion,event,snapshot,alert}`
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    /// 0, 1 or 2
+    #[serde(default)]
+    pub qos: u8,
+    /// if set (usually `homeassistant`), Home Assistant discovery configs are published under this prefix
+    pub discovery_prefix: Option<String>,
+    /// if true, alerts are also published to `<topic_prefix>/<camera>/alert`, subject to alert rules like any other notifier.
+    /// Events and snapshots are published either way.
+    #[serde(default)]
+    pub alerts: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// plaintext, only for local relays
+    None,
+    #[default]
+    StartTls,
+    /// implicit TLS, usually on port 465
+    Tls,
+}
+
+fn default_smtp_preview_format() -> PreviewFormat {
+    PreviewFormat::Jpeg
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// defaults to the standard port for `security`
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// mailbox alerts are sent from, i.e. `rmr <rmr@example.com>`
+    pub from: String,
+    /// recipients of every camera's alerts
+    #[serde(default)]
+    pub to: Vec<String>,
+    #[serde(default = "default_smtp_preview_format")]
+    pub preview_format: PreviewFormat,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct ArmingConfig {
+    /// mode used until another is set through the API, the last set mode persists across restarts
+    pub default_mode: String,
+    pub modes: IndexMap<String, ArmingModeConfig>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct ArmingModeConfig {
+    /// cameras that send alerts, all if unset
+    pub alert: Option<Vec<String>>,
+    /// cameras that save motion events, all if unset
+    pub record_events: Option<Vec<String>>,
+}
+
+/// (De)serializes a local time of day as `HH:MM` or `HH:MM:SS`
+mod time_of_day {
+    use chrono::NaiveTime;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format("%H:%M:%S"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&raw, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(&raw, "%H:%M"))
+            .map_err(|_| D::Error::custom(format!("invalid time of day '{raw}', expected HH:MM")))
+    }
+}
+
+/// A daily window of local time. Wraps around midnight if `end` is before `start`, covers the whole day if they are equal.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TimeWindow {
+    #[serde(with = "time_of_day")]
+    pub start: NaiveTime,
+    #[serde(with = "time_of_day")]
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start == self.end || (self.start..self.end).contains(&time)
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Conditions are all optional and must all hold for the rule to match
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct AlertRule {
+    pub cameras: Option<Vec<String>>,
+    pub states: Option<Vec<AlertState>>,
+    /// day of week the alert happened on in the host's local timezone (`TZ`), i.e. `[sat, sun]`
+    pub days: Option<Vec<Weekday>>,
+    /// time of day windows in the host's local timezone (`TZ`), any of which must contain the alert time
+    pub times: Option<Vec<TimeWindow>>,
+    pub min_score: Option<f64>,
+    pub max_score: Option<f64>,
+    /// names of the notifiers to send through (`pushover`, `webhook`, `mqtt`, `email`), all if unset. Empty to drop the alert.
+    pub notifiers: Option<Vec<String>>,
+    /// overrides the Pushover priority, including the camera's `alert_priority`
+    pub priority: Option<PushoverPriority>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MotionDetectionConfig {
     pub width: u32,
     pub height: u32,
@@ -83,9 +358,188 @@ pub struct MotionDetectionConfig {
     pub config: RunningMotionDetectorConfig,
     #[serde(default)]
     pub alert_priority: Option<PushoverPriority>,
+    pub alert_cooldown: Option<AlertCooldownConfig>,
+    /// Labels events with `classifier`, optionally only alerting for some labels
+    pub classification: Option<ClassificationConfig>,
+    /// Alternative detector settings, the first profile whose condition holds is used instead of the settings above
+    #[serde(default)]
+    pub profiles: IndexMap<String, DetectorProfile>,
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
+/// Name the camera's own detector settings are reported as when no profile is active
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileCondition {
+    /// between sunrise and sunset at `location`
+    Day,
+    /// between sunset and sunrise at `location`
+    Night,
+    /// while the camera sends colorless frames, as IR cameras do at night
+    Grayscale,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DetectorProfile {
+    pub when: ProfileCondition,
+    /// Detector settings replacing the camera's while the profile is active, i.e. `change_minimum` or `zones`
+    #[serde(flatten)]
+    pub overrides: serde_yaml::Mapping,
+}
+
+impl MotionDetectionConfig {
+    /// The camera's detector settings with the profile's overrides applied
+    pub fn profile_config(
+        &self,
+        profile: &DetectorProfile,
+    ) -> anyhow::Result<RunningMotionDetectorConfig> {
+        let mut value = serde_yaml::to_value(&self.config)?;
+        let fields = value
+            .as_mapping_mut()
+            .context("detector config is not a mapping")?;
+        for (field, override_value) in &profile.overrides {
+            if !fields.contains_key(field) {
+                bail!(
+                    "unknown detector setting '{}'",
+                    field.as_str().unwrap_or_default()
+                );
+            }
+            fields.insert(field.clone(), override_value.clone());
+        }
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    /// Detector settings for the named profile, or the camera's own for None or a profile that no longer exists
+    pub fn detector_config(&self, profile: Option<&str>) -> RunningMotionDetectorConfig {
+        let Some(profile) = profile.and_then(|x| self.profiles.get(x)) else {
+            return self.config.clone();
+        };
+        // validated on load
+        self.profile_config(profile)
+            .unwrap_or_else(|_| self.config.clone())
+    }
+
+    /// Problems with the detector settings, and with those of each profile once applied
+    pub fn validate_detectors(&self, path: &str, has_location: bool) -> Vec<ConfigProblem> {
+        let size = (self.width, self.height);
+        let mut problems = vec![];
+        let mut problem =
+            |path: String, message: String| problems.push(ConfigProblem { path, message });
+        if self.width == 0 || self.height == 0 {
+            problem(
+                path.to_string(),
+                "width and height must be positive".to_string(),
+            );
+        }
+        for x in validate_detector(path, &self.config, size) {
+            problem(x.path, x.message);
+        }
+        for (name, profile) in &self.profiles {
+            let path = format!("{path}.profiles.{name}");
+            if name == DEFAULT_PROFILE {
+                problem(
+                    path.clone(),
+                    "name is reserved for the camera's own settings".to_string(),
+                );
+            }
+            if matches!(
+                profile.when,
+                ProfileCondition::Day | ProfileCondition::Night
+            ) && !has_location
+            {
+                problem(
+                    format!("{path}.when"),
+                    "needs location to be configured".to_string(),
+                );
+            }
+            match self.profile_config(profile) {
+                Err(e) => problem(path, format!("{e:#}")),
+                Ok(config) => {
+                    for x in validate_detector(&path, &config, size) {
+                        problem(x.path, x.message);
+                    }
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CooldownMode {
+    /// alerts during the window are dropped
+    #[default]
+    Suppress,
+    /// alerts during the window are summarized in one alert once it ends, with the highest scoring preview
+    Aggregate,
+}
+
+fn default_classifier_input_size() -> u32 {
+    224
+}
+
+fn default_classifier_mean() -> [f32; 3] {
+    [0.485, 0.456, 0.406]
+}
+
+fn default_classifier_std() -> [f32; 3] {
+    [0.229, 0.224, 0.225]
+}
+
+/// An ONNX image classifier taking a `[1, 3, height, width]` RGB tensor and giving one score per label, run on the CPU.
+/// The defaults fit ImageNet models.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClassifierConfig {
+    pub model: PathBuf,
+    /// one label per line, in the order of the model's outputs
+    pub labels: PathBuf,
+    #[serde(default = "default_classifier_input_size")]
+    pub input_width: u32,
+    #[serde(default = "default_classifier_input_size")]
+    pub input_height: u32,
+    /// per channel, subtracted from pixel values scaled to [0, 1]
+    #[serde(default = "default_classifier_mean")]
+    pub mean: [f32; 3],
+    /// per channel, divides pixel values after subtracting `mean`
+    #[serde(default = "default_classifier_std")]
+    pub std: [f32; 3],
+    /// set for models that output logits rather than probabilities
+    #[serde(default)]
+    pub softmax: bool,
+}
+
+fn default_classification_frames() -> usize {
+    3
+}
+
+fn default_classification_min_confidence() -> f32 {
+    0.5
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClassificationConfig {
+    /// number of most changed frames classified per event
+    #[serde(default = "default_classification_frames")]
+    pub frames: usize,
+    /// labels below this confidence are dropped
+    #[serde(default = "default_classification_min_confidence")]
+    pub min_confidence: f32,
+    /// only alert if one of these labels is found, i.e. `[person, car]`. Events are saved either way.
+    #[serde(default)]
+    pub require: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AlertCooldownConfig {
+    /// a window starts whenever an alert is sent
+    pub window_secs: u64,
+    #[serde(default)]
+    pub mode: CooldownMode,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum PreviewFormat {
     None,
@@ -104,8 +558,585 @@ pub enum CameraMode {
     MotionDetectRecord,
 }
 
+impl Config {
+    /// True if settings only read at startup (web server, prometheus, index) changed
+    pub fn restart_required(&self, new: &Config) -> bool {
+        self.prometheus_bind != new.prometheus_bind
+            || self.web_bind != new.web_bind
+            || self.web_base != new.web_base
+            || self.tls_cert != new.tls_cert
+            || self.tls_key != new.tls_key
+            || self.http_redirect_bind != new.http_redirect_bind
+            || self.mqtt != new.mqtt
+            || self.index_file != new.index_file
+            || self.live_dir != new.live_dir
+    }
+
+    /// True if global settings changed that every camera task needs to be restarted for
+    pub fn cameras_restart_required(&self, new: &Config) -> bool {
+        self.ffmpeg_bin != new.ffmpeg_bin
+            || self.recording_dir != new.recording_dir
+            || self.event_dir != new.event_dir
+            || self.force_tcp != new.force_tcp
+    }
+}
+
+impl CameraConfig {
+    /// True if the camera's ffmpeg needs to be restarted for the new config to take effect.
+    /// Otherwise, the new config can be pushed into the running motion detector.
+    pub fn restart_required(&self, new: &CameraConfig) -> bool {
+        self.rtsp != new.rtsp
+            || self.mode != new.mode
+            || self.motion_detection.is_some() != new.motion_detection.is_some()
+            || self.motion_detection.as_ref().map(|x| (x.width, x.height))
+                != new.motion_detection.as_ref().map(|x| (x.width, x.height))
+    }
+}
+
+/// Handle to the current config, swapped out on reload.
+/// Running tasks keep the config they got from `get` alive until they drop it.
+pub struct ConfigHandle(RwLock<Arc<Config>>);
+
+impl ConfigHandle {
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+/// A semantic mistake in the config, located by its YAML path
+pub struct ConfigProblem {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Config {
+    /// Finds every semantic problem in the config
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = vec![];
+        let mut problem =
+            |path: String, message: String| problems.push(ConfigProblem { path, message });
+
+        if !self.web_base.starts_with('/') || !self.web_base.ends_with('/') {
+            problem(
+                "web_base".to_string(),
+                "must start and end with '/'".to_string(),
+            );
+        }
+        if let Some(external_url) = &self.external_url {
+            if !external_url.path().ends_with('/') {
+                problem("external_url".to_string(), "must end with '/'".to_string());
+            }
+        }
+        for (i, webhook) in self.webhooks.iter().enumerate() {
+            for (name, value) in &webhook.headers {
+                if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                    || reqwest::header::HeaderValue::from_str(value).is_err()
+                {
+                    problem(
+                        format!("webhooks.{i}.headers.{name}"),
+                        "invalid HTTP header".to_string(),
+                    );
+                }
+            }
+        }
+        if let Some(mqtt) = &self.mqtt {
+            if mqtt.qos > 2 {
+                problem("mqtt.qos".to_string(), "must be 0, 1 or 2".to_string());
+            }
+            for name in self.cameras.keys() {
+                if name.contains(['+', '#']) {
+                    problem(
+                        format!("cameras.{name}"),
+                        "camera name must not contain MQTT wildcards".to_string(),
+                    );
+                }
+            }
+        }
+        if let Some(smtp) = &self.smtp {
+            let mailboxes = std::iter::once(("smtp.from".to_string(), &smtp.from))
+                .chain(
+                    smtp.to
+                        .iter()
+                        .enumerate()
+                        .map(|(i, x)| (format!("smtp.to.{i}"), x)),
+                )
+                .chain(self.cameras.iter().flat_map(|(name, camera)| {
+                    camera
+                        .email
+                        .iter()
+                        .enumerate()
+                        .map(move |(i, x)| (format!("cameras.{name}.email.{i}"), x))
+                }));
+            for (path, mailbox) in mailboxes {
+                if let Err(e) = mailbox.parse::<lettre::message::Mailbox>() {
+                    problem(path, format!("invalid mailbox '{mailbox}': {e}"));
+                }
+            }
+        } else {
+            for (name, camera) in &self.cameras {
+                if !camera.email.is_empty() {
+                    problem(
+                        format!("cameras.{name}.email"),
+                        "requires smtp to be configured".to_string(),
+                    );
+                }
+            }
+        }
+        if let Some(arming) = &self.arming {
+            if !arming.modes.contains_key(&arming.default_mode) {
+                problem(
+                    "arming.default_mode".to_string(),
+                    format!("unknown mode '{}'", arming.default_mode),
+                );
+            }
+            for (name, mode) in &arming.modes {
+                for (field, cameras) in [
+                    ("alert", &mode.alert),
+                    ("record_events", &mode.record_events),
+                ] {
+                    for camera in cameras.iter().flatten() {
+                        if !self.cameras.contains_key(camera) {
+                            problem(
+                                format!("arming.modes.{name}.{field}"),
+                                format!("unknown camera '{camera}'"),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        for (i, rule) in self.alert_rules.iter().enumerate() {
+            let path = format!("alert_rules.{i}");
+            for camera in rule.cameras.iter().flatten() {
+                if !self.cameras.contains_key(camera) {
+                    problem(
+                        format!("{path}.cameras"),
+                        format!("unknown camera '{camera}'"),
+                    );
+                }
+            }
+            for notifier in rule.notifiers.iter().flatten() {
+                if !NOTIFIERS.contains(&&**notifier) {
+                    problem(
+                        format!("{path}.notifiers"),
+                        format!(
+                            "unknown notifier '{notifier}', expected one of {}",
+                            NOTIFIERS.join(", ")
+                        ),
+                    );
+                }
+            }
+            if let (Some(min_score), Some(max_score)) = (rule.min_score, rule.max_score) {
+                if min_score > max_score {
+                    problem(
+                        format!("{path}.min_score"),
+                        format!("must not be greater than max_score ({max_score})"),
+                    );
+                }
+            }
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(tls_cert), Some(tls_key)) => {
+                for (name, path) in [("tls_cert", tls_cert), ("tls_key", tls_key)] {
+                    if let Err(e) = std::fs::metadata(path) {
+                        problem(
+                            name.to_string(),
+                            format!("failed to read '{}': {e}", path.display()),
+                        );
+                    }
+                }
+            }
+            (Some(_), None) => problem("tls_key".to_string(), "required with tls_cert".to_string()),
+            (None, Some(_)) => problem("tls_cert".to_string(), "required with tls_key".to_string()),
+            (None, None) => {
+                if self.http_redirect_bind.is_some() {
+                    problem(
+                        "http_redirect_bind".to_string(),
+                        "requires tls_cert and tls_key".to_string(),
+                    );
+                }
+            }
+        }
+        if Some(self.web_bind) == self.http_redirect_bind {
+            problem(
+                "http_redirect_bind".to_string(),
+                "must differ from web_bind".to_string(),
+            );
+        }
+        let dirs = [
+            ("recording_dir", &self.recording_dir),
+            ("event_dir", &self.event_dir),
+            ("live_dir", &self.live_dir),
+        ];
+        for (i, (name, dir)) in dirs.iter().enumerate() {
+            for (other_name, other_dir) in &dirs[i + 1..] {
+                if dir.starts_with(other_dir) || other_dir.starts_with(dir) {
+                    problem(
+                        name.to_string(),
+                        format!("must not be shared with or nested in {other_name}"),
+                    );
+                }
+            }
+        }
+        if let (Some(high), Some(low)) = (
+            self.retention.high_watermark_bytes,
+            self.retention.low_watermark_bytes,
+        ) {
+            if low > high {
+                problem(
+                    "retention.low_watermark_bytes".to_string(),
+                    format!("must not be greater than high_watermark_bytes ({high})"),
+                );
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            for (name, user) in &auth.users {
+                let path = format!("auth.users.{name}");
+                if name.contains(':') {
+                    problem(path.clone(), "username must not contain ':'".to_string());
+                }
+                if let Some(password_hash) = &user.password_hash {
+                    if let Err(e) = argon2::PasswordHash::new(password_hash) {
+                        problem(
+                            format!("{path}.password_hash"),
+                            format!("invalid hash: {e}"),
+                        );
+                    }
+                }
+                for (i, token) in user.api_tokens.iter().enumerate() {
+                    if token.len() != 64 || !token.chars().all(|x| x.is_ascii_hexdigit()) {
+                        problem(
+                            format!("{path}.api_tokens.{i}"),
+                            "must be a hex encoded SHA-256 digest".to_string(),
+                        );
+                    }
+                }
+                for camera in user.cameras.iter().flatten() {
+                    if !self.cameras.contains_key(camera) {
+                        problem(
+                            format!("{path}.cameras"),
+                            format!("unknown camera '{camera}'"),
+                        );
+                    }
+                }
+            }
+        }
+
+        // the model itself is only loaded once an event needs it, it can take a while
+        let classifier_labels = self.classifier.as_ref().map(crate::classify::read_labels);
+        if let Some(classifier) = &self.classifier {
+            if !classifier.model.is_file() {
+                problem(
+                    "classifier.model".to_string(),
+                    format!("no such file '{}'", classifier.model.display()),
+                );
+            }
+            if let Some(Err(e)) = &classifier_labels {
+                problem("classifier.labels".to_string(), format!("{e:#}"));
+            }
+            if classifier.input_width == 0 || classifier.input_height == 0 {
+                problem(
+                    "classifier".to_string(),
+                    "input_width and input_height must be positive".to_string(),
+                );
+            }
+        }
+
+        for (name, camera) in &self.cameras {
+            let path = format!("cameras.{name}");
+            // camera names are used as recording directory names and event filename prefixes
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                problem(
+                    path.clone(),
+                    "camera name must be usable as a directory name".to_string(),
+                );
+            }
+            if camera.frame_rate <= 0.0 {
+                problem(format!("{path}.frame_rate"), "must be positive".to_string());
+            }
+            let motion_detect = matches!(
+                camera.mode,
+                CameraMode::MotionDetect | CameraMode::MotionDetectRecord
+            );
+            let Some(motion_detection) = &camera.motion_detection else {
+                if motion_detect {
+                    problem(
+                        format!("{path}.motion_detection"),
+                        "required for motion detection modes".to_string(),
+                    );
+                }
+                continue;
+            };
+            let path = format!("{path}.motion_detection");
+            if motion_detection
+                .alert_cooldown
+                .as_ref()
+                .is_some_and(|x| x.window_secs == 0)
+            {
+                problem(
+                    format!("{path}.alert_cooldown.window_secs"),
+                    "must be positive".to_string(),
+                );
+            }
+            if let Some(classification) = &motion_detection.classification {
+                match &classifier_labels {
+                    None => problem(
+                        format!("{path}.classification"),
+                        "needs classifier to be configured".to_string(),
+                    ),
+                    Some(Ok(labels)) => {
+                        for label in &classification.require {
+                            if !labels.contains(label) {
+                                problem(
+                                    format!("{path}.classification.require"),
+                                    format!("unknown label '{label}'"),
+                                );
+                            }
+                        }
+                    }
+                    // reported once for the classifier
+                    Some(Err(_)) => (),
+                }
+                if classification.frames == 0 {
+                    problem(
+                        format!("{path}.classification.frames"),
+                        "must be positive".to_string(),
+                    );
+                }
+            }
+            for x in motion_detection.validate_detectors(&path, self.location.is_some()) {
+                problem(x.path, x.message);
+            }
+        }
+        problems
+    }
+}
+
+/// Problems with a camera's detector settings, or a profile's once applied
+fn validate_detector(
+    path: &str,
+    config: &RunningMotionDetectorConfig,
+    (width, height): (u32, u32),
+) -> Vec<ConfigProblem> {
+    let mut problems = vec![];
+    let mut problem =
+        |path: String, message: String| problems.push(ConfigProblem { path, message });
+
+    if config.change_minimum > config.change_maximum {
+        problem(
+            format!("{path}.change_minimum"),
+            format!(
+                "must not be greater than change_maximum ({})",
+                config.change_maximum
+            ),
+        );
+    }
+    if !(config.background.learning_rate > 0.0 && config.background.learning_rate <= 1.0) {
+        problem(
+            format!("{path}.background.learning_rate"),
+            "must be greater than 0 and at most 1".to_string(),
+        );
+    }
+    if config.background.deviations <= 0.0 {
+        problem(
+            format!("{path}.background.deviations"),
+            "must be positive".to_string(),
+        );
+    }
+    if let Some(adaptive) = &config.adaptive {
+        if adaptive.window_frames == 0 {
+            problem(
+                format!("{path}.adaptive.window_frames"),
+                "must be positive".to_string(),
+            );
+        }
+        for (field, multiplier) in [
+            ("change_multiplier", adaptive.change_multiplier),
+            ("stddev_multiplier", adaptive.stddev_multiplier),
+            ("total_change_multiplier", adaptive.total_change_multiplier),
+        ] {
+            if multiplier <= 0.0 {
+                problem(
+                    format!("{path}.adaptive.{field}"),
+                    "must be positive".to_string(),
+                );
+            }
+        }
+    }
+    if let Some(blobs) = &config.blobs {
+        if blobs.maximum_area.is_some_and(|x| x < blobs.minimum_area) {
+            problem(
+                format!("{path}.blobs.minimum_area"),
+                "must not be greater than maximum_area".to_string(),
+            );
+        }
+    }
+    if let Some(tracking) = &config.tracking {
+        if config.blobs.is_none() {
+            problem(
+                format!("{path}.tracking"),
+                "needs blobs to be configured".to_string(),
+            );
+        }
+        if tracking.max_distance <= 0.0 {
+            problem(
+                format!("{path}.tracking.max_distance"),
+                "must be positive".to_string(),
+            );
+        }
+        for (name, tripwire) in &tracking.tripwires {
+            if tripwire
+                .line
+                .iter()
+                .flatten()
+                .any(|x| !(0.0..=1.0).contains(x))
+            {
+                problem(
+                    format!("{path}.tracking.tripwires.{name}.line"),
+                    "coordinates must be between 0 and 1".to_string(),
+                );
+            }
+        }
+        for (name, entry) in &tracking.zone_entries {
+            if !config.zones.contains_key(&entry.zone) {
+                problem(
+                    format!("{path}.tracking.zone_entries.{name}.zone"),
+                    format!("unknown zone '{}'", entry.zone),
+                );
+            }
+        }
+    }
+    for (zone_name, zone) in &config.zones {
+        for (field, message) in crate::zone::validate(zone, width, height) {
+            problem(format!("{path}.zones.{zone_name}.{field}"), message);
+        }
+    }
+    if let Some(mask_file) = &config.mask_file {
+        // decoded rather than only probed, so that startup doesn't fail on a mask that passed validation
+        match image::open(mask_file) {
+            Err(e) => problem(
+                format!("{path}.mask_file"),
+                format!("failed to read mask '{mask_file}': {e}"),
+            ),
+            Ok(mask) if mask.dimensions() != (width, height) => problem(
+                format!("{path}.mask_file"),
+                format!(
+                    "mask is {}x{}, expected detection size {}x{}",
+                    mask.width(),
+                    mask.height(),
+                    width,
+                    height
+                ),
+            ),
+            Ok(_) => (),
+        }
+    }
+    problems
+}
+
+/// Zones per camera, as saved by the web zone editor
+type SavedZones = IndexMap<String, IndexMap<String, ZoneConfig>>;
+
+/// Reads `ZONES_PATH`, which doesn't exist until zones are first saved
+fn read_saved_zones() -> anyhow::Result<SavedZones> {
+    match std::fs::read_to_string(&*ZONES_PATH) {
+        Ok(raw) => serde_yaml::from_str(&raw)
+            .with_context(|| format!("failed to parse zones file '{}'", ZONES_PATH.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SavedZones::new()),
+        Err(e) => {
+            Err(e).with_context(|| format!("failed to read zones file '{}'", ZONES_PATH.display()))
+        }
+    }
+}
+
+/// Saved zones replace those in the config file, zones of cameras that are gone or don't detect motion are ignored
+fn apply_saved_zones(config: &mut Config, saved_zones: SavedZones) {
+    for (camera, zones) in saved_zones {
+        if let Some(motion_detection) = config
+            .cameras
+            .get_mut(&camera)
+            .and_then(|x| x.motion_detection.as_mut())
+        {
+            motion_detection.config.zones = zones;
+        }
+    }
+}
+
+/// Loads the config file and the saved zones, failing on parse errors (with their YAML path) or any semantic problem
+pub fn load() -> anyhow::Result<Config> {
+    let raw = std::fs::read_to_string(&*CONFIG_PATH)
+        .with_context(|| format!("failed to read config file '{}'", CONFIG_PATH.display()))?;
+    parse(&raw, read_saved_zones()?)
+}
+
+fn parse(raw: &str, saved_zones: SavedZones) -> anyhow::Result<Config> {
+    // serde_yaml errors carry the YAML path of the offending value
+    let mut config: Config = serde_yaml::from_str(raw).context("failed to parse config file")?;
+    apply_saved_zones(&mut config, saved_zones);
+    let problems = config.validate();
+    if !problems.is_empty() {
+        bail!(
+            "invalid config file:\n{}",
+            problems
+                .iter()
+                .map(|x| format!("  {x}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+    Ok(config)
+}
+
+/// Saves a camera's motion detection zones to `ZONES_PATH`, leaving the config file untouched. They are picked up by the config reload.
+/// The config with the new zones is validated before anything is written.
+pub fn save_zones(camera: &str, zones: &IndexMap<String, ZoneConfig>) -> anyhow::Result<()> {
+    let raw = std::fs::read_to_string(&*CONFIG_PATH)
+        .with_context(|| format!("failed to read config file '{}'", CONFIG_PATH.display()))?;
+    let mut saved_zones = read_saved_zones()?;
+    saved_zones.insert(camera.to_string(), zones.clone());
+    let config = parse(&raw, saved_zones.clone())?;
+    if config
+        .cameras
+        .get(camera)
+        .and_then(|x| x.motion_detection.as_ref())
+        .is_none()
+    {
+        bail!("camera '{camera}' has no motion_detection");
+    }
+
+    // written next to the zones file and renamed over it, so that a reload never sees a partial file
+    let temp_path = ZONES_PATH.with_extension("yaml.tmp");
+    std::fs::write(&temp_path, serde_yaml::to_string(&saved_zones)?)
+        .with_context(|| format!("failed to write '{}'", temp_path.display()))?;
+    std::fs::rename(&temp_path, &*ZONES_PATH)
+        .with_context(|| format!("failed to replace '{}'", ZONES_PATH.display()))?;
+    Ok(())
+}
+
+/// Re-reads the config file and swaps it in as `CONFIG`
+pub fn reload() -> anyhow::Result<Arc<Config>> {
+    let config = Arc::new(load()?);
+    *CONFIG.0.write().unwrap() = config.clone();
+    Ok(config)
+}
+
+/// Latest modification time of the config file and the zones file, used to detect changes
+pub fn modified() -> Option<SystemTime> {
+    [&*CONFIG_PATH, &*ZONES_PATH]
+        .into_iter()
+        .filter_map(|x| std::fs::metadata(x).and_then(|x| x.modified()).ok())
+        .max()
+}
+
 lazy_static::lazy_static! {
-    static ref CONFIG_PATH: PathBuf = {
+    pub static ref CONFIG_PATH: PathBuf = {
         let var = std::env::var("RMR_CONFIG").unwrap_or_default();
         if var.is_empty() {
             "./config.yaml".parse().unwrap()
@@ -113,7 +1144,268 @@ lazy_static::lazy_static! {
             var.parse().expect("invalid config path")
         }
     };
-    pub static ref CONFIG: Config = {
-        serde_yaml::from_str(&std::fs::read_to_string(&*CONFIG_PATH).expect("failed to read config file")).expect("failed to parse config file")
-    };
+    /// Zones saved by the web zone editor, `config.zones.yaml` for `config.yaml`
+    pub static ref ZONES_PATH: PathBuf = CONFIG_PATH.with_extension("zones.yaml");
+    pub static ref CONFIG: ConfigHandle = ConfigHandle(RwLock::new(Arc::new(load().expect("failed to load config"))));
+}
+
+/// Parses `extra_yaml` (which has to list the `cameras`) after the paths every config needs
+#[cfg(test)]
+pub(crate) fn test_config(extra_yaml: &str) -> Config {
+    serde_yaml::from_str(&format!(
+        "web_bind: 127.0.0.1:8080
+recording_dir: /rmr/recording
+event_dir: /rmr/events
+live_dir: /rmr/live
+{extra_yaml}"
+    ))
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_YAML: &str = r"
+cameras:
+  front:
+    rtsp: rtsp://camera/front
+    mode: motion_detect
+    motion_detection:
+      width: 64
+      height: 48
+      change_minimum: 200.0
+      change_maximum: 100000.0
+      stddev_minimum: 100.0
+      minimum_frame_count: 25
+      minimum_total_change: 10000.0
+      followup_frame_count: 25
+      maximum_frame_wait: 0
+      mask_file: null
+";
+
+    fn config() -> Config {
+        test_config(CONFIG_YAML)
+    }
+
+    fn motion_detection(config: &mut Config) -> &mut MotionDetectionConfig {
+        config.cameras["front"].motion_detection.as_mut().unwrap()
+    }
+
+    /// Paths of the problems `validate` finds
+    fn problems(config: &Config) -> Vec<String> {
+        config.validate().into_iter().map(|x| x.path).collect()
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(problems(&config()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn global_problems() {
+        let mut config = config();
+        config.web_base = "rmr".to_string();
+        config.event_dir = "/rmr/recording/events".into();
+        config.retention.high_watermark_bytes = Some(100);
+        config.retention.low_watermark_bytes = Some(200);
+        assert_eq!(
+            problems(&config),
+            ["web_base", "recording_dir", "retention.low_watermark_bytes"]
+        );
+    }
+
+    #[test]
+    fn camera_problems() {
+        let mut config = config();
+        config.cameras["front"].frame_rate = 0.0;
+        motion_detection(&mut config).width = 0;
+        motion_detection(&mut config).config.change_minimum = 1e6;
+        let mut back = config.cameras["front"].clone();
+        back.motion_detection = None;
+        config.cameras.insert("back".to_string(), back.clone());
+        config.cameras.insert("../back".to_string(), back);
+        assert_eq!(
+            problems(&config),
+            [
+                "cameras.front.frame_rate",
+                "cameras.front.motion_detection",
+                "cameras.front.motion_detection.change_minimum",
+                "cameras.back.frame_rate",
+                "cameras.back.motion_detection",
+                "cameras.../back",
+                "cameras.../back.frame_rate",
+                "cameras.../back.motion_detection",
+            ]
+        );
+    }
+
+    #[test]
+    fn mask_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config();
+        let mut mask_problems = |mask: &std::path::Path| {
+            motion_detection(&mut config).config.mask_file =
+                Some(mask.to_string_lossy().into_owned());
+            problems(&config)
+        };
+
+        let missing = dir.path().join("missing.png");
+        assert_eq!(
+            mask_problems(&missing),
+            ["cameras.front.motion_detection.mask_file"]
+        );
+        // a PNG header alone passes a dimension probe but doesn't decode
+        let truncated = dir.path().join("truncated.png");
+        let mut png = vec![];
+        image::GrayImage::new(64, 48)
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        std::fs::write(&truncated, &png[..png.len() / 2]).unwrap();
+        assert_eq!(
+            mask_problems(&truncated),
+            ["cameras.front.motion_detection.mask_file"]
+        );
+        let wrong_size = dir.path().join("wrong_size.png");
+        image::GrayImage::new(32, 48).save(&wrong_size).unwrap();
+        assert_eq!(
+            mask_problems(&wrong_size),
+            ["cameras.front.motion_detection.mask_file"]
+        );
+        let mask = dir.path().join("mask.png");
+        image::GrayImage::new(64, 48).save(&mask).unwrap();
+        assert_eq!(mask_problems(&mask), Vec::<String>::new());
+    }
+
+    #[test]
+    fn alert_rule_problems() {
+        let mut config = config();
+        config.alert_rules = serde_yaml::from_str(
+            r"
+- cameras: [ front, back ]
+  notifiers: [ pushover, pager ]
+  min_score: 2.0
+  max_score: 1.0
+- notifiers: []
+",
+        )
+        .unwrap();
+        assert_eq!(
+            problems(&config),
+            [
+                "alert_rules.0.cameras",
+                "alert_rules.0.notifiers",
+                "alert_rules.0.min_score",
+            ]
+        );
+    }
+
+    #[test]
+    fn arming_problems() {
+        let mut config = config();
+        config.arming = Some(
+            serde_yaml::from_str(
+                r"
+default_mode: vacation
+modes:
+  home:
+    alert: [ front ]
+    record_events: [ back ]
+  away: {}
+",
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            problems(&config),
+            ["arming.default_mode", "arming.modes.home.record_events"]
+        );
+    }
+
+    #[test]
+    fn saved_zones() {
+        let mut config = config();
+        motion_detection(&mut config).config.zones = serde_yaml::from_str("{ porch: {} }").unwrap();
+        let saved_zones: SavedZones = serde_yaml::from_str(
+            "{ front: { driveway: { include: [ [ [0, 0.5], [1, 0.5], [1, 1] ] ] } }, back: {} }",
+        )
+        .unwrap();
+        apply_saved_zones(&mut config, saved_zones);
+        let zones = &motion_detection(&mut config).config.zones;
+        assert_eq!(zones.keys().collect::<Vec<_>>(), ["driveway"]);
+        assert_eq!(zones["driveway"].include[0][1], [1.0, 0.5]);
+
+        apply_saved_zones(&mut config, serde_yaml::from_str("{ front: {} }").unwrap());
+        assert!(motion_detection(&mut config).config.zones.is_empty());
+    }
+
+    #[test]
+    fn profile_problems() {
+        let mut config = config();
+        motion_detection(&mut config).profiles = serde_yaml::from_str(
+            r"
+ir:
+  when: grayscale
+  change_minimum: 500.0
+dark:
+  when: night
+  change_maximum: 100.0
+default:
+  when: grayscale
+  stddev_maximum: 1.0
+",
+        )
+        .unwrap();
+        assert_eq!(
+            problems(&config),
+            [
+                "cameras.front.motion_detection.profiles.dark.when",
+                "cameras.front.motion_detection.profiles.dark.change_minimum",
+                "cameras.front.motion_detection.profiles.default",
+                "cameras.front.motion_detection.profiles.default",
+            ]
+        );
+
+        let motion_detection = motion_detection(&mut config);
+        assert_eq!(
+            motion_detection.detector_config(Some("ir")).change_minimum,
+            500.0
+        );
+        assert_eq!(motion_detection.detector_config(None).change_minimum, 200.0);
+        // removed by a reload
+        assert_eq!(
+            motion_detection
+                .detector_config(Some("gone"))
+                .change_minimum,
+            200.0
+        );
+    }
+
+    #[test]
+    fn time_window() {
+        let time = |x: &str| NaiveTime::parse_from_str(x, "%H:%M:%S").unwrap();
+        let window: TimeWindow = serde_yaml::from_str("{ start: '07:00', end: '22:00' }").unwrap();
+        assert!(!window.contains(time("06:59:59")));
+        assert!(window.contains(time("07:00:00")));
+        assert!(window.contains(time("21:59:59")));
+        assert!(!window.contains(time("22:00:00")));
+
+        let overnight: TimeWindow =
+            serde_yaml::from_str("{ start: '22:00', end: '06:30:00' }").unwrap();
+        assert!(overnight.contains(time("22:00:00")));
+        assert!(overnight.contains(time("23:59:59")));
+        assert!(overnight.contains(time("00:00:00")));
+        assert!(overnight.contains(time("06:29:59")));
+        assert!(!overnight.contains(time("06:30:00")));
+        assert!(!overnight.contains(time("12:00:00")));
+
+        let all_day: TimeWindow = serde_yaml::from_str("{ start: '00:00', end: '00:00' }").unwrap();
+        assert!(all_day.contains(time("00:00:00")));
+        assert!(all_day.contains(time("23:59:59")));
+
+        assert!(serde_yaml::from_str::<TimeWindow>("{ start: '7am', end: '22:00' }").is_err());
+    }
 }
@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use lettre::{
+    message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::{
+    alert::{Alert, Notifier},
+    classify,
+    config::{Config, SmtpConfig, SmtpSecurity, CONFIG},
+    preview,
+};
+
+/// Content-ID the HTML body refers to the inline preview by
+const PREVIEW_CONTENT_ID: &str = "preview";
+
+/// Escapes text from the config or the classifier's label file for the HTML body
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub struct Email {
+    config: SmtpConfig,
+}
+
+impl Email {
+    pub fn new(config: SmtpConfig) -> Self {
+        Self { config }
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>> {
+        let mut builder = match self.config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.config.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.config.host)?,
+        };
+        if let Some(port) = self.config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &self.config.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                self.config.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(builder.build())
+    }
+
+    /// Recipients of every camera plus those of the alerting camera
+    fn recipients(&self, config: &Config, camera: &str) -> Result<Vec<Mailbox>> {
+        let camera_recipients = config
+            .cameras
+            .get(camera)
+            .map(|x| &x.email[..])
+            .unwrap_or_default();
+        let mut out: Vec<Mailbox> = vec![];
+        for recipient in self.config.to.iter().chain(camera_recipients) {
+            let mailbox: Mailbox = recipient
+                .parse()
+                .with_context(|| format!("invalid recipient '{recipient}'"))?;
+            // the same address may be listed with and without a name
+            if !out.iter().any(|x| x.email == mailbox.email) {
+                out.push(mailbox);
+            }
+        }
+        Ok(out)
+    }
+
+    async fn alert_event(&self, alert: &Alert) -> Result<()> {
+        let recipients = self.recipients(&CONFIG.get(), &alert.camera)?;
+        if recipients.is_empty() {
+            return Ok(());
+        }
+        let event = &alert.event;
+
+        let mut html = format!(
+            r"<p>Total Score: {:.02}<br>Start Frame: {}<br>Total Frames: {}",
+            event.total_score,
+            event.start_stream_frame_number,
+            event.end_stream_frame_number - event.start_stream_frame_number
+        );
+        if !event.zones.is_empty() {
+            html += &format!("<br>Zones: {}", escape_html(&event.zones.join(", ")));
+        }
+        if !alert.labels.is_empty() {
+            html += &format!(
+                "<br>Seen: {}",
+                escape_html(&classify::format_labels(&alert.labels))
+            );
+        }
+        if let Some(event_url) = alert.event_url() {
+            html += &format!(r#"<br><a href="{event_url}">Event</a>"#);
+        }
+        html += "</p>";
+
+        let preview =
+            preview::encode(self.config.preview_format, &alert.event, alert.frame_rate).await;
+        if preview.is_some() {
+            html += &format!(r#"<p><img src="cid:{PREVIEW_CONTENT_ID}"></p>"#);
+        }
+        let mut body = MultiPart::related().singlepart(SinglePart::html(html));
+        if let Some(preview) = preview {
+            body = body.singlepart(
+                Attachment::new_inline(PREVIEW_CONTENT_ID.to_string())
+                    .body(preview.data, ContentType::parse(preview.content_type)?),
+            );
+        }
+
+        let mut message = Message::builder()
+            .from(self.config.from.parse().context("invalid sender")?)
+            .subject(alert.title())
+            .date(alert.time.into());
+        for recipient in recipients {
+            message = message.to(recipient);
+        }
+        let message = message.multipart(body)?;
+
+        self.transport()?.send(message).await?;
+        Ok(())
+    }
+}
+
+impl Notifier for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.alert_event(alert))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    const CONFIG_YAML: &str = r"
+cameras:
+  front:
+    rtsp: rtsp://camera/front
+    mode: record
+    email: [ porch@example.com, Home <home@example.com> ]
+  back:
+    rtsp: rtsp://camera/back
+    mode: record
+    email: [ not an address ]
+smtp:
+  host: localhost
+  from: rmr@example.com
+  to: [ home@example.com, admin@example.com ]
+";
+
+    fn recipients(camera: &str) -> Result<Vec<String>> {
+        let config = test_config(CONFIG_YAML);
+        let email = Email::new(config.smtp.clone().unwrap());
+        Ok(email
+            .recipients(&config, camera)?
+            .into_iter()
+            .map(|x| x.email.to_string())
+            .collect())
+    }
+
+    #[test]
+    fn camera_recipients() {
+        assert_eq!(
+            recipients("front").unwrap(),
+            ["home@example.com", "admin@example.com", "porch@example.com"]
+        );
+        assert_eq!(
+            recipients("side").unwrap(),
+            ["home@example.com", "admin@example.com"]
+        );
+        assert!(recipients("back").is_err());
+    }
+
+    #[test]
+    fn html_escaping() {
+        assert_eq!(
+            escape_html("<b>gate</b> & fence"),
+            "&lt;b&gt;gate&lt;/b&gt; &amp; fence"
+        );
+        assert_eq!(escape_html("&lt;"), "&amp;lt;");
+    }
+}
@@ -1,11 +1,94 @@
 use chrono::{DateTime, Utc};
+use log::error;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+use crate::{
+    classify::Label,
+    config::{Config, CONFIG},
+    modect::MotionDetectionEvent,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct EventMetadata {
     pub camera: String,
     pub when: DateTime<Utc>,
     pub total_score: f64,
     pub start_stream_frame_number: u64,
     pub end_stream_frame_number: u64,
+    /// Names of the zones that saw motion, highest scoring first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+    /// What the classifier saw, most confident first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<Label>,
+    /// Detector profile active for the event, unset for the camera's own settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+impl EventMetadata {
+    /// Without labels, which are only known once the classifier ran
+    pub fn new(camera: &str, when: DateTime<Utc>, event: &MotionDetectionEvent) -> Self {
+        Self {
+            camera: camera.to_string(),
+            when,
+            total_score: event.total_score,
+            start_stream_frame_number: event.start_stream_frame_number,
+            end_stream_frame_number: event.end_stream_frame_number,
+            zones: event.zones.clone(),
+            labels: vec![],
+            profile: event.profile.clone(),
+        }
+    }
+}
+
+/// An event clip in `CONFIG.event_dir` and its metadata sidecar
+#[derive(Serialize, Clone)]
+pub struct StoredEvent {
+    pub filename: String,
+    #[serde(flatten)]
+    pub metadata: EventMetadata,
+}
+
+/// Event files are named `<camera>_<time>.<ext>`, camera names may contain underscores
+pub fn event_camera<'a>(config: &'a Config, filename: &str) -> Option<&'a str> {
+    config
+        .cameras
+        .keys()
+        .filter(|name| {
+            filename.len() > name.len()
+                && filename.starts_with(name.as_str())
+                && filename.as_bytes()[name.len()] == b'_'
+        })
+        .max_by_key(|name| name.len())
+        .map(|x| x.as_str())
+}
+
+/// Reads the metadata of every event clip in `CONFIG.event_dir`, oldest first.
+/// Clips with a missing or malformed sidecar are skipped.
+pub async fn read_events() -> anyhow::Result<Vec<StoredEvent>> {
+    let config = CONFIG.get();
+    let mut read_dir = tokio::fs::read_dir(&config.event_dir).await?;
+    let mut entries = vec![];
+    while let Some(entry) = read_dir.next_entry().await? {
+        let filename = entry.file_name().to_string_lossy().into_owned();
+        if !filename.ends_with(".mp4") {
+            continue;
+        }
+        let metadata_file = config.event_dir.join(&filename).with_extension("json");
+        let metadata = async {
+            let raw = tokio::fs::read_to_string(&metadata_file).await?;
+            anyhow::Ok(serde_json::from_str::<EventMetadata>(&raw)?)
+        }
+        .await;
+        match metadata {
+            Ok(metadata) => entries.push(StoredEvent { filename, metadata }),
+            Err(e) => error!(
+                "skipping event '{filename}', failed to read metadata '{}': {e}",
+                metadata_file.display()
+            ),
+        }
+    }
+    entries.sort_by_key(|x| x.metadata.when);
+    Ok(entries)
 }
@@ -1,5 +1,9 @@
-use std::{path::PathBuf, process::Stdio};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
 
+use chrono::{DateTime, Utc};
 use image::RgbImage;
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -11,6 +15,22 @@ use tokio::{
 };
 use url::Url;
 
+/// `strftime` format of recording segment file stems
+pub const SEGMENT_FILENAME_FORMAT: &str = "%Y%m%d-%H%M%S%z";
+/// Length of recording segments, aligned to the wall clock
+pub const SEGMENT_SECONDS: i64 = 60;
+
+/// Start time of a recording segment, parsed from its filename
+pub fn segment_start(path: &Path) -> Option<DateTime<Utc>> {
+    if path.extension()? != "mp4" {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    DateTime::parse_from_str(stem, SEGMENT_FILENAME_FORMAT)
+        .ok()
+        .map(|x| x.with_timezone(&Utc))
+}
+
 pub struct FFmpegConfig {
     pub binary: String,
     pub rtsp_input: Url,
@@ -28,7 +48,7 @@ pub enum FFMpegError {
     Io(#[from] std::io::Error),
     #[error("probe parse failed: {0}")]
     ProbeParse(serde_json::Error),
-    #[error("no video stream in RTSP")]
+    #[error("no video stream in input")]
     NoVideoStream,
     #[error("expected video codec h265/hevc, got {0}")]
     UnsupportedVideoCodec(String),
@@ -95,12 +115,30 @@ enum FFProbeStreamData {
 }
 
 impl FFmpegConfig {
+    fn is_rtsp(&self) -> bool {
+        matches!(self.rtsp_input.scheme(), "rtsp" | "rtsps")
+    }
+
+    /// The input as given to ffmpeg, which doesn't decode percent escapes in `file:` URLs
+    fn input(&self) -> String {
+        match self.rtsp_input.to_file_path() {
+            Ok(path) if self.rtsp_input.scheme() == "file" => path.to_string_lossy().into_owned(),
+            _ => self.rtsp_input.to_string(),
+        }
+    }
+
     pub async fn run(&self) -> Result<(), FFMpegError> {
         let ffprobe = self.binary.replace("ffmpeg", "ffprobe");
         info!("Running '{ffprobe}' as ffprobe binary");
+        let input = self.input();
+        let mut ffprobe_args = vec![];
+        // ffprobe rejects RTSP options for other inputs, i.e. recordings being replayed
+        if self.is_rtsp() {
+            ffprobe_args.extend(["-rtsp_transport", "tcp"]);
+        }
         let ffprobe_out = Command::new(&ffprobe)
-            .args(["-rtsp_transport", "tcp"])
-            .arg(&self.rtsp_input.as_str())
+            .args(&ffprobe_args)
+            .arg(&input)
             .args(["-of", "json", "-show_streams"])
             .output()
             .await?;
@@ -129,10 +167,11 @@ impl FFmpegConfig {
         let dimension = format!("{}x{}", width_out, height_out);
 
         let mut ffmpeg_args = vec![];
-        if self.force_tcp {
+        if self.force_tcp && self.is_rtsp() {
             ffmpeg_args.extend(["-rtsp_transport", "tcp"]);
         }
-        ffmpeg_args.extend(["-i", &self.rtsp_input.as_ref()]);
+        ffmpeg_args.extend(["-i", &input]);
+        let segment_time = SEGMENT_SECONDS.to_string();
         let mut recording_format = self.recording_mp4_dir.clone();
         if let Some(recording_format) = &mut recording_format {
             if self.record_single_jpeg {
@@ -146,12 +185,12 @@ impl FFmpegConfig {
                     recording_format.to_str().unwrap(),
                 ]);
             } else {
-                recording_format.push("%Y%m%d-%H%M%S%z.mp4");
+                recording_format.push(format!("{SEGMENT_FILENAME_FORMAT}.mp4"));
                 ffmpeg_args.extend([
                     "-c:v",
                     "copy",
                     "-segment_time",
-                    "00:1:00",
+                    &segment_time,
                     "-f",
                     "segment",
                     "-reset_timestamps",
@@ -174,6 +213,7 @@ impl FFmpegConfig {
         info!("ffmpeg: {} {}", self.binary, ffmpeg_args.join(" "));
         let mut ffmpeg_process = Command::new(&self.binary)
             .args(&ffmpeg_args)
+            .kill_on_drop(true)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?;
@@ -191,8 +231,13 @@ impl FFmpegConfig {
         if let Some(send_images) = &self.send_images {
             let mut image_buf = vec![0u8; image_size as usize];
             loop {
-                if let Err(e) = stdout.read_exact(&mut image_buf).await {
-                    return Err(FFMpegError::ErrorReadingImage(e));
+                match stdout.read_exact(&mut image_buf).await {
+                    Ok(_) => (),
+                    // a file input ended, ffmpeg's exit status tells whether it should have, live streams are never expected to
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !self.is_rtsp() => {
+                        break
+                    }
+                    Err(e) => return Err(FFMpegError::ErrorReadingImage(e)),
                 }
                 if send_images
                     .send(RgbImage::from_raw(width_out, height_out, image_buf.clone()).unwrap())
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use image::RgbImage;
+use tokio::sync::{watch, Notify};
+
+use crate::blob::Blob;
+
+/// How long frames keep being captured after they were last requested
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Frames older than this are not served, a new one is waited for instead
+const MAX_FRAME_AGE: Duration = Duration::from_secs(1);
+/// How long a request waits for a new frame
+const FRAME_WAIT: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    static ref FRAMES: Mutex<HashMap<String, CapturedFrames>> = Mutex::new(HashMap::new());
+    static ref DEBUG_FRAMES: Mutex<HashMap<String, watch::Sender<Option<Arc<DebugFrame>>>>> = Mutex::new(HashMap::new());
+}
+
+/// What the motion detector saw in a frame, for tuning its thresholds
+pub struct DebugFrame {
+    /// Change from the previous frame, with pixels outside of every zone tinted blue
+    pub image: RgbImage,
+    pub change: f64,
+    pub stddev: f64,
+    pub state: &'static str,
+    pub change_minimum: f64,
+    pub change_maximum: f64,
+    pub stddev_minimum: f64,
+    pub blobs: Vec<Blob>,
+}
+
+/// Latest detection frames of a camera, only captured while requested since copying every frame is wasteful
+struct CapturedFrames {
+    requested: Instant,
+    captured: Option<Instant>,
+    previous: Option<Arc<RgbImage>>,
+    current: Option<Arc<RgbImage>>,
+    notify: Arc<Notify>,
+}
+
+/// True if someone recently asked for the camera's frames
+pub fn wanted(camera: &str) -> bool {
+    FRAMES
+        .lock()
+        .unwrap()
+        .get(camera)
+        .is_some_and(|x| x.requested.elapsed() < CAPTURE_TIMEOUT)
+}
+
+pub fn capture(camera: &str, frame: &RgbImage) {
+    let mut frames = FRAMES.lock().unwrap();
+    let Some(captured) = frames.get_mut(camera) else {
+        return;
+    };
+    captured.previous = captured.current.take();
+    captured.current = Some(Arc::new(frame.clone()));
+    captured.captured = Some(Instant::now());
+    captured.notify.notify_waiters();
+}
+
+/// The latest detection frame of a camera and the one before it, if any.
+/// None if the camera's monitor produced no frames in time.
+pub async fn latest(camera: &str) -> Option<(Option<Arc<RgbImage>>, Arc<RgbImage>)> {
+    let notify = {
+        let mut frames = FRAMES.lock().unwrap();
+        let captured = frames
+            .entry(camera.to_string())
+            .or_insert_with(|| CapturedFrames {
+                requested: Instant::now(),
+                captured: None,
+                previous: None,
+                current: None,
+                notify: Arc::new(Notify::new()),
+            });
+        captured.requested = Instant::now();
+        if captured
+            .captured
+            .is_some_and(|x| x.elapsed() < MAX_FRAME_AGE)
+        {
+            return Some((captured.previous.clone(), captured.current.clone()?));
+        }
+        captured.notify.clone()
+    };
+    tokio::time::timeout(FRAME_WAIT, notify.notified())
+        .await
+        .ok()?;
+    let frames = FRAMES.lock().unwrap();
+    let captured = frames.get(camera)?;
+    Some((captured.previous.clone(), captured.current.clone()?))
+}
+
+/// True while someone is subscribed to the camera's debug frames
+pub fn debug_wanted(camera: &str) -> bool {
+    DEBUG_FRAMES
+        .lock()
+        .unwrap()
+        .get(camera)
+        .is_some_and(|x| x.receiver_count() > 0)
+}
+
+pub fn publish_debug(camera: &str, frame: DebugFrame) {
+    if let Some(sender) = DEBUG_FRAMES.lock().unwrap().get(camera) {
+        sender.send_replace(Some(Arc::new(frame)));
+    }
+}
+
+/// Debug frames stop being rendered once every receiver is dropped
+pub fn subscribe_debug(camera: &str) -> watch::Receiver<Option<Arc<DebugFrame>>> {
+    DEBUG_FRAMES
+        .lock()
+        .unwrap()
+        .entry(camera.to_string())
+        .or_insert_with(|| watch::channel(None).0)
+        .subscribe()
+}
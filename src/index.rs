@@ -0,0 +1,512 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use log::{error, info};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+
+use crate::{
+    config::CONFIG,
+    event::{read_events, EventMetadata, StoredEvent},
+    ffmpeg::SEGMENT_SECONDS,
+    recording::{list_recording_files, stat_recording, Recording},
+};
+
+const SCHEMA: &str = r"
+CREATE TABLE IF NOT EXISTS events (
+    filename TEXT PRIMARY KEY NOT NULL,
+    camera TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    total_score REAL NOT NULL,
+    metadata TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_time ON events (time);
+CREATE INDEX IF NOT EXISTS events_camera_time ON events (camera, time);
+
+CREATE TABLE IF NOT EXISTS recordings (
+    camera TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    start INTEGER,
+    end INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    PRIMARY KEY (camera, filename)
+);
+CREATE INDEX IF NOT EXISTS recordings_camera_end ON recordings (camera, end);
+
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
+";
+
+/// Count of newest segments per camera that are re-read on every scan, since ffmpeg may still be writing them
+const GROWING_SEGMENTS: usize = 2;
+
+static INDEX: OnceLock<Mutex<Connection>> = OnceLock::new();
+
+/// Opens the index at `path`, creating its tables if needed; must be called before any other function of this module
+pub fn open(path: &Path) -> Result<()> {
+    let connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    if INDEX.set(Mutex::new(connection)).is_err() {
+        bail!("index is already open");
+    }
+    Ok(())
+}
+
+/// Runs a blocking closure against the index connection
+async fn with_index<T: Send + 'static>(
+    func: impl FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+) -> Result<T> {
+    let index = INDEX.get().ok_or_else(|| anyhow!("index is not open"))?;
+    Ok(tokio::task::spawn_blocking(move || func(&mut index.lock().unwrap())).await??)
+}
+
+fn to_millis(time: DateTime<Utc>) -> i64 {
+    time.timestamp_millis()
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+}
+
+fn insert_event_sync(connection: &Connection, event: &StoredEvent) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO events (filename, camera, time, total_score, metadata) VALUES (?, ?, ?, ?, ?)",
+        params![
+            event.filename,
+            event.metadata.camera,
+            to_millis(event.metadata.when),
+            event.metadata.total_score,
+            serde_json::to_string(&event.metadata).unwrap(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_recording_sync(
+    connection: &Connection,
+    camera: &str,
+    recording: &Recording,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO recordings (camera, filename, start, end, size) VALUES (?, ?, ?, ?, ?)",
+        params![
+            camera,
+            recording.filename,
+            recording.start.map(to_millis),
+            to_millis(recording.end),
+            recording.size as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+pub async fn insert_event(event: StoredEvent) -> Result<()> {
+    with_index(move |connection| insert_event_sync(connection, &event)).await
+}
+
+/// Reads runtime state persisted across restarts
+pub async fn get_setting(key: &'static str) -> Result<Option<String>> {
+    with_index(move |connection| {
+        connection
+            .query_row("SELECT value FROM settings WHERE key = ?", [key], |row| {
+                row.get(0)
+            })
+            .optional()
+    })
+    .await
+}
+
+pub async fn set_setting(key: &'static str, value: String) -> Result<()> {
+    with_index(move |connection| {
+        connection.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)",
+            params![key, value],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+pub async fn remove_event(filename: String) -> Result<()> {
+    with_index(move |connection| {
+        connection.execute("DELETE FROM events WHERE filename = ?", [filename])?;
+        Ok(())
+    })
+    .await
+}
+
+pub async fn remove_recording(camera: String, filename: String) -> Result<()> {
+    with_index(move |connection| {
+        connection.execute(
+            "DELETE FROM recordings WHERE camera = ? AND filename = ?",
+            [camera, filename],
+        )?;
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Default)]
+pub struct EventFilter {
+    pub camera: Option<String>,
+    /// Only events of one of these cameras
+    pub cameras: Option<Vec<String>>,
+    /// Only events at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only events before this time
+    pub to: Option<DateTime<Utc>>,
+    pub min_score: Option<f64>,
+}
+
+fn query_events_sync(
+    connection: &Connection,
+    filter: EventFilter,
+    offset: usize,
+    limit: Option<usize>,
+) -> rusqlite::Result<(usize, Vec<StoredEvent>)> {
+    let mut conditions = vec!["1"];
+    let mut values = vec![];
+    if let Some(camera) = filter.camera {
+        conditions.push("camera = ?");
+        values.push(Value::Text(camera));
+    }
+    let cameras_condition;
+    if let Some(cameras) = filter.cameras {
+        cameras_condition = format!("camera IN ({})", vec!["?"; cameras.len()].join(", "));
+        conditions.push(&cameras_condition);
+        values.extend(cameras.into_iter().map(Value::Text));
+    }
+    if let Some(from) = filter.from {
+        conditions.push("time >= ?");
+        values.push(Value::Integer(to_millis(from)));
+    }
+    if let Some(to) = filter.to {
+        conditions.push("time < ?");
+        values.push(Value::Integer(to_millis(to)));
+    }
+    if let Some(min_score) = filter.min_score {
+        conditions.push("total_score >= ?");
+        values.push(Value::Real(min_score));
+    }
+    let conditions = conditions.join(" AND ");
+
+    let total: i64 = connection.query_row(
+        &format!("SELECT COUNT(*) FROM events WHERE {conditions}"),
+        params_from_iter(values.iter()),
+        |row| row.get(0),
+    )?;
+
+    values.push(Value::Integer(limit.map(|x| x as i64).unwrap_or(-1)));
+    values.push(Value::Integer(offset as i64));
+    let mut statement = connection.prepare(&format!(
+        "SELECT filename, metadata FROM events WHERE {conditions} ORDER BY time DESC LIMIT ? OFFSET ?"
+    ))?;
+    let mut events = vec![];
+    let mut rows = statement.query(params_from_iter(values.iter()))?;
+    while let Some(row) = rows.next()? {
+        let filename: String = row.get(0)?;
+        let metadata: String = row.get(1)?;
+        match serde_json::from_str::<EventMetadata>(&metadata) {
+            Ok(metadata) => events.push(StoredEvent { filename, metadata }),
+            Err(e) => error!("malformed metadata for event '{filename}' in index: {e}"),
+        }
+    }
+    Ok((total as usize, events))
+}
+
+/// Returns the count of events matching `filter`, and the page of them selected by `offset`/`limit`, newest first
+pub async fn query_events(
+    filter: EventFilter,
+    offset: usize,
+    limit: Option<usize>,
+) -> Result<(usize, Vec<StoredEvent>)> {
+    with_index(move |connection| query_events_sync(connection, filter, offset, limit)).await
+}
+
+/// Recording segments of a camera, oldest first
+pub async fn query_recordings(camera: String) -> Result<Vec<Recording>> {
+    with_index(move |connection| {
+        let mut statement = connection.prepare(
+            "SELECT filename, start, end, size FROM recordings WHERE camera = ? ORDER BY end",
+        )?;
+        let recordings = statement
+            .query_map([camera], |row| {
+                Ok(Recording {
+                    filename: row.get(0)?,
+                    start: row.get::<_, Option<i64>>(1)?.map(from_millis),
+                    end: from_millis(row.get(2)?),
+                    size: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(recordings)
+    })
+    .await
+}
+
+/// Makes the events table hold exactly `events`, returning the counts of added and removed rows
+fn reconcile_events_sync(
+    connection: &mut Connection,
+    events: &[StoredEvent],
+) -> rusqlite::Result<(usize, usize)> {
+    let transaction = connection.transaction()?;
+    let mut existing = transaction
+        .prepare("SELECT filename FROM events")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<HashSet<_>>>()?;
+    let mut inserted = 0usize;
+    for event in events {
+        if !existing.remove(&event.filename) {
+            insert_event_sync(&transaction, event)?;
+            inserted += 1;
+        }
+    }
+    for filename in &existing {
+        transaction.execute("DELETE FROM events WHERE filename = ?", [filename])?;
+    }
+    transaction.commit()?;
+    Ok((inserted, existing.len()))
+}
+
+/// Brings the events table in line with the sidecars in `CONFIG.event_dir`
+async fn reconcile_events() -> Result<()> {
+    let events = read_events().await?;
+    let (inserted, removed) =
+        with_index(move |connection| reconcile_events_sync(connection, &events)).await?;
+    if inserted > 0 || removed > 0 {
+        info!("index: reconciled events, {inserted} added, {removed} removed");
+    }
+    Ok(())
+}
+
+/// Brings the recordings table of a camera in line with its recording directory
+async fn reconcile_recordings(camera: &str) -> Result<()> {
+    let mut filenames = list_recording_files(camera).await?;
+    filenames.sort();
+
+    let existing = {
+        let camera = camera.to_string();
+        with_index(move |connection| {
+            connection
+                .prepare("SELECT filename, size FROM recordings WHERE camera = ?")?
+                .query_map([camera], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+                })?
+                .collect::<rusqlite::Result<HashMap<_, _>>>()
+        })
+        .await?
+    };
+
+    let recording_dir = CONFIG.get().recording_dir.join(camera);
+    let growing_start = filenames.len().saturating_sub(GROWING_SEGMENTS);
+    let mut changed = vec![];
+    for (i, filename) in filenames.iter().enumerate() {
+        if existing.contains_key(filename) && i < growing_start {
+            continue;
+        }
+        match stat_recording(&recording_dir.join(filename)).await {
+            Ok(recording) => {
+                if existing.get(filename) != Some(&(recording.size as i64)) {
+                    changed.push(recording);
+                }
+            }
+            // deleted since listing
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let on_disk = filenames.into_iter().collect::<HashSet<_>>();
+    let removed = existing
+        .into_keys()
+        .filter(|x| !on_disk.contains(x))
+        .collect::<Vec<_>>();
+    if changed.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    let camera = camera.to_string();
+    with_index(move |connection| {
+        let transaction = connection.transaction()?;
+        for recording in &changed {
+            insert_recording_sync(&transaction, &camera, recording)?;
+        }
+        for filename in &removed {
+            transaction.execute(
+                "DELETE FROM recordings WHERE camera = ? AND filename = ?",
+                [&camera, filename],
+            )?;
+        }
+        transaction.commit()
+    })
+    .await
+}
+
+/// Reconciles the index with the event directory on startup, then keeps the recordings table up to date with new segments
+pub async fn run() {
+    if let Err(e) = reconcile_events().await {
+        error!("index: failed to reconcile events: {e:#}");
+    }
+    loop {
+        for name in CONFIG.get().cameras.keys() {
+            if let Err(e) = reconcile_recordings(name).await {
+                error!("index: failed to reconcile recordings for {name}: {e:#}");
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(SEGMENT_SECONDS as u64)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(filename: &str, camera: &str, minute: i64, total_score: f64) -> StoredEvent {
+        StoredEvent {
+            filename: filename.to_string(),
+            metadata: EventMetadata {
+                camera: camera.to_string(),
+                when: from_millis(minute * 60_000),
+                total_score,
+                start_stream_frame_number: 0,
+                end_stream_frame_number: 0,
+                zones: vec![],
+                labels: vec![],
+                profile: None,
+            },
+        }
+    }
+
+    fn index(events: &[StoredEvent]) -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        for event in events {
+            insert_event_sync(&connection, event).unwrap();
+        }
+        connection
+    }
+
+    fn query(
+        connection: &Connection,
+        filter: EventFilter,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> (usize, Vec<String>) {
+        let (total, events) = query_events_sync(connection, filter, offset, limit).unwrap();
+        (total, events.into_iter().map(|x| x.filename).collect())
+    }
+
+    fn events() -> Vec<StoredEvent> {
+        vec![
+            event("a.mp4", "front", 0, 10.0),
+            event("b.mp4", "back", 1, 30.0),
+            event("c.mp4", "front", 2, 20.0),
+            event("d.mp4", "front", 3, 40.0),
+        ]
+    }
+
+    #[test]
+    fn query_filters() {
+        let connection = index(&events());
+        assert_eq!(
+            query(&connection, Default::default(), 0, None),
+            (
+                4,
+                vec![
+                    "d.mp4".into(),
+                    "c.mp4".into(),
+                    "b.mp4".into(),
+                    "a.mp4".into()
+                ]
+            )
+        );
+        let camera = EventFilter {
+            camera: Some("front".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query(&connection, camera, 0, None),
+            (3, vec!["d.mp4".into(), "c.mp4".into(), "a.mp4".into()])
+        );
+        // `from` is inclusive, `to` exclusive
+        let range = EventFilter {
+            from: Some(from_millis(60_000)),
+            to: Some(from_millis(3 * 60_000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            query(&connection, range, 0, None),
+            (2, vec!["c.mp4".into(), "b.mp4".into()])
+        );
+        let score = EventFilter {
+            camera: Some("front".into()),
+            min_score: Some(20.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            query(&connection, score, 0, None),
+            (2, vec!["d.mp4".into(), "c.mp4".into()])
+        );
+        let allowed = EventFilter {
+            cameras: Some(vec!["back".into(), "side".into()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            query(&connection, allowed, 0, None),
+            (1, vec!["b.mp4".into()])
+        );
+        let none_allowed = EventFilter {
+            cameras: Some(vec![]),
+            ..Default::default()
+        };
+        assert_eq!(query(&connection, none_allowed, 0, None), (0, vec![]));
+    }
+
+    #[test]
+    fn query_pagination() {
+        let connection = index(&events());
+        assert_eq!(
+            query(&connection, Default::default(), 0, Some(2)),
+            (4, vec!["d.mp4".into(), "c.mp4".into()])
+        );
+        assert_eq!(
+            query(&connection, Default::default(), 2, Some(2)),
+            (4, vec!["b.mp4".into(), "a.mp4".into()])
+        );
+        assert_eq!(
+            query(&connection, Default::default(), 3, None),
+            (4, vec!["a.mp4".into()])
+        );
+        assert_eq!(
+            query(&connection, Default::default(), 4, Some(2)),
+            (4, vec![])
+        );
+    }
+
+    #[test]
+    fn reconcile() {
+        let events = events();
+        let mut connection = index(&events[..3]);
+        // a.mp4 was deleted and d.mp4 written while not running
+        assert_eq!(
+            reconcile_events_sync(&mut connection, &events[1..]).unwrap(),
+            (1, 1)
+        );
+        assert_eq!(
+            query(&connection, Default::default(), 0, None),
+            (3, vec!["d.mp4".into(), "c.mp4".into(), "b.mp4".into()])
+        );
+        assert_eq!(
+            reconcile_events_sync(&mut connection, &events[1..]).unwrap(),
+            (0, 0)
+        );
+    }
+}
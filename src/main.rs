@@ -1,38 +1,69 @@
 use axum::Router;
-use clap::Parser;
-use config::{CameraConfig, CameraMode, CONFIG};
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use config::{CameraConfig, CameraMode, CONFIG, DEFAULT_PROFILE};
+use futures::FutureExt;
 use image::RgbImage;
-use log::{debug, error, info, trace};
+use indexmap::IndexMap;
+use log::{debug, error, info, trace, warn};
 use modect::{MotionDetectionState, RunningMotionDetector};
 use prometheus::{
-    register_counter_vec, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
-    CounterVec, HistogramVec, IntCounterVec, IntGaugeVec,
+    register_counter_vec, register_gauge_vec, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge_vec, CounterVec, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec,
 };
 use std::{
     net::SocketAddr,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch},
+    task::JoinHandle,
 };
-use tokio::sync::mpsc;
 
 use crate::{
-    event::EventMetadata,
-    pushover::{alert_event, AlertState},
+    alert::{Alert, AlertState},
+    event::{EventMetadata, StoredEvent},
+    frames::DebugFrame,
+    profile::ProfileSelector,
 };
 
+mod alert;
+mod arming;
+mod blob;
+mod classify;
 mod config;
+mod email;
 mod event;
 mod ffmpeg;
+mod frames;
+mod index;
 mod modect;
 mod modect_mp4;
+mod mqtt;
 mod observable_buf;
+mod preview;
+mod profile;
 mod pushover;
+mod recording;
+mod replay;
+mod retention;
+mod status;
+mod sun;
+mod track;
 mod web;
+mod webhook;
+mod zone;
 
 lazy_static::lazy_static! {
     static ref FRAME_COUNTER: IntGaugeVec = register_int_gauge_vec!("rmr_frame_counter", "stream frame counter", &["camera"]).unwrap();
     static ref MODECT_CHANGE: CounterVec = register_counter_vec!("rmr_modect_change", "frame change value", &["camera"]).unwrap();
     static ref MODECT_STDDEV: CounterVec = register_counter_vec!("rmr_modect_stddev", "frame change std dev (estd)", &["camera"]).unwrap();
+    static ref MODECT_CHANGE_MINIMUM: GaugeVec = register_gauge_vec!("rmr_modect_change_minimum", "effective change_minimum, learned with adaptive thresholds", &["camera"]).unwrap();
+    static ref MODECT_STDDEV_MINIMUM: GaugeVec = register_gauge_vec!("rmr_modect_stddev_minimum", "effective stddev_minimum, learned with adaptive thresholds", &["camera"]).unwrap();
+    static ref MODECT_MINIMUM_TOTAL_CHANGE: GaugeVec = register_gauge_vec!("rmr_modect_minimum_total_change", "effective minimum_total_change, learned with adaptive thresholds", &["camera"]).unwrap();
     static ref MODECT_REJECT: CounterVec = register_counter_vec!("rmr_modect_reject", "count of events rejected by filter", &["camera"]).unwrap();
     static ref MODECT_REJECT_SCORE: HistogramVec = register_histogram_vec!("rmr_modect_reject_score", "rejection total scores", &["camera"]).unwrap();
     static ref MODECT_CONFIRM: CounterVec = register_counter_vec!("rmr_modect_confirm", "confirmation of events accepted by filter (before completion)", &["camera"]).unwrap();
@@ -40,6 +71,7 @@ lazy_static::lazy_static! {
     static ref MODECT_COMPLETE_SCORE: HistogramVec = register_histogram_vec!("rmr_modect_complete_score", "accepted total scores", &["camera"]).unwrap();
     static ref MODECT_LAST_REJECT: IntGaugeVec = register_int_gauge_vec!("rmr_modect_last_reject", "last rejection frame", &["camera"]).unwrap();
     static ref MODECT_LAST_COMPLETE: IntGaugeVec = register_int_gauge_vec!("rmr_modect_last_complete", "last accepted frame", &["camera"]).unwrap();
+    static ref MODECT_PROFILE: IntGaugeVec = register_int_gauge_vec!("rmr_modect_profile", "1 for the active detector profile", &["camera", "profile"]).unwrap();
     static ref MODECT_STATE: IntGaugeVec = register_int_gauge_vec!("rmr_modect_state", "current state", &["camera"]).unwrap();
     static ref MODECT_ALERT_LATENCY: CounterVec = register_counter_vec!("rmr_modect_alert_latency_ms", "ms latency of sending alerts, including encoding", &["camera"]).unwrap();
     static ref MODECT_ALERT_COUNT: IntCounterVec = register_int_counter_vec!("rmr_modect_alert_count", "count of alerts sent", &["camera"]).unwrap();
@@ -54,6 +86,18 @@ struct Args {
     /// Dumps a screenshot into the recording directory for each non-disabled camera
     #[clap(short, long)]
     snapshot: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Parses and validates the config file, printing every problem found
+    CheckConfig,
+    /// Reads a password from stdin and prints its hash for `auth.users.<name>.password_hash`
+    HashPassword,
+    /// Runs a recording through a camera's motion detector, printing per-frame values as CSV and which events it would report
+    Replay(replay::ReplayArgs),
 }
 
 #[tokio::main]
@@ -64,24 +108,70 @@ async fn main() {
         .parse_env(env_logger::Env::default().default_filter_or("info"))
         .init();
 
+    if let Some(Command::HashPassword) = &ARGS.command {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("failed to read password: {e}");
+            std::process::exit(1);
+        }
+        match web::auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{hash}"),
+            Err(e) => {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // validate before anything touches `CONFIG`, so that no camera task is spawned with a bad config
+    let checked = config::load();
+    if let Some(Command::CheckConfig) = &ARGS.command {
+        match checked {
+            Ok(config) => println!(
+                "{}: ok, {} camera(s)",
+                config::CONFIG_PATH.display(),
+                config.cameras.len()
+            ),
+            Err(e) => {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Err(e) = checked {
+        error!("{e:#}");
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Replay(args)) = &ARGS.command {
+        if let Err(e) = replay::run(args).await {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if ARGS.snapshot {
-        for (name, camera) in &CONFIG.cameras {
+        let config = CONFIG.get();
+        for (name, camera) in &config.cameras {
             if matches!(camera.mode, CameraMode::Disable) {
                 continue;
             }
-            let mut recording_dir = CONFIG.recording_dir.clone();
+            let mut recording_dir = config.recording_dir.clone();
             recording_dir.push(name);
             tokio::fs::create_dir_all(&recording_dir).await.unwrap();
 
             ffmpeg::FFmpegConfig {
-                binary: CONFIG.ffmpeg_bin.clone(),
+                binary: config.ffmpeg_bin.clone(),
                 rtsp_input: camera.rtsp.clone(),
                 recording_mp4_dir: Some(recording_dir),
                 send_images: None,
                 image_width: camera.motion_detection.as_ref().map(|x| x.width),
                 image_height: camera.motion_detection.as_ref().map(|x| x.height),
                 record_single_jpeg: true,
-                force_tcp: CONFIG.force_tcp,
+                force_tcp: config.force_tcp,
             }
             .run()
             .await
@@ -90,18 +180,37 @@ async fn main() {
         return;
     }
 
-    if let Some(prometheus_bind) = CONFIG.prometheus_bind {
+    if let Some(prometheus_bind) = CONFIG.get().prometheus_bind {
         prometheus_exporter::start(prometheus_bind).expect("failed to load prometheus_exporter");
     }
 
+    let index_file = CONFIG.get().index_file.clone();
+    if let Err(e) = index::open(&index_file) {
+        error!("failed to open index {}: {e:#}", index_file.display());
+        std::process::exit(1);
+    }
+    arming::init().await;
+    tokio::spawn(index::run());
+    tokio::spawn(retention::run());
+    tokio::spawn(mqtt::run());
+
     tokio::spawn(async move {
         async fn run() -> anyhow::Result<()> {
-            let server = axum::Server::bind(&CONFIG.web_bind);
-            info!("listening @ {}", CONFIG.web_bind);
-            let routes = Router::new().nest(&CONFIG.web_base, web::route());
-            server
-                .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
-                .await?;
+            let config = CONFIG.get();
+            let routes = Router::new().nest(&config.web_base, web::route());
+            let service = routes.into_make_service_with_connect_info::<SocketAddr>();
+            let (Some(tls_cert), Some(tls_key)) = (&config.tls_cert, &config.tls_key) else {
+                let server = axum::Server::bind(&config.web_bind);
+                info!("listening @ {}", config.web_bind);
+                server.serve(service).await?;
+                return Ok(());
+            };
+            let tls = RustlsConfig::from_pem_file(tls_cert, tls_key).await?;
+            info!("listening @ {} (HTTPS)", config.web_bind);
+            tokio::select! {
+                result = axum_server::bind_rustls(config.web_bind, tls.clone()).serve(service) => result?,
+                _ = web::tls::watch(tls, tls_cert.clone(), tls_key.clone()) => (),
+            }
             Ok(())
         }
         loop {
@@ -111,106 +220,276 @@ async fn main() {
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
     });
+    if CONFIG.get().http_redirect_bind.is_some() {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = web::tls::serve_redirect().await {
+                    error!("failed to start http redirect server: {:?}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
 
-    let mut tasks = vec![];
-
-    for (name, camera) in &CONFIG.cameras {
-        tasks.push(tokio::spawn(async move {
-            match camera.mode {
-                CameraMode::Disable => return,
-                CameraMode::Record => {
-                    let mut recording_dir = CONFIG.recording_dir.clone();
-                    recording_dir.push(name);
-                    tokio::fs::create_dir_all(&recording_dir).await.unwrap();
-
-                    loop {
-                        let out = ffmpeg::FFmpegConfig {
-                            binary: CONFIG.ffmpeg_bin.clone(),
-                            rtsp_input: camera.rtsp.clone(),
-                            recording_mp4_dir: Some(recording_dir.clone()),
-                            send_images: None,
-                            image_width: camera.motion_detection.as_ref().map(|x| x.width),
-                            image_height: camera.motion_detection.as_ref().map(|x| x.height),
-                            record_single_jpeg: false,
-                            force_tcp: CONFIG.force_tcp,
-                        }
-                        .run()
-                        .await;
-                        if let Err(e) = out {
-                            error!("ffmpeg failed for camera {name}: {e}");
-                        }
-                        tokio::time::sleep(Duration::from_secs(1)).await;
+    let mut cameras = IndexMap::new();
+    for (name, camera) in &CONFIG.get().cameras {
+        cameras.insert(name.clone(), CameraTask::spawn(name, camera));
+    }
+
+    let mut hangup = signal(SignalKind::hangup()).expect("failed to listen for SIGHUP");
+    let mut last_modified = config::modified();
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                info!("received SIGHUP, reloading config");
+            }
+            _ = tokio::time::sleep(CONFIG_POLL_INTERVAL) => {
+                for (name, task) in &mut cameras {
+                    if task.handle.is_finished() && task.camera.mode != CameraMode::Disable {
+                        error!("camera task for {name} exited, restarting");
+                        *task = CameraTask::spawn(name, &task.camera);
                     }
                 }
-                CameraMode::MotionDetect => {
-                    let sender = start_monitor(&name, camera).await;
-
-                    loop {
-                        let out = ffmpeg::FFmpegConfig {
-                            binary: CONFIG.ffmpeg_bin.clone(),
-                            rtsp_input: camera.rtsp.clone(),
-                            recording_mp4_dir: None,
-                            send_images: Some(sender.clone()),
-                            image_width: camera.motion_detection.as_ref().map(|x| x.width),
-                            image_height: camera.motion_detection.as_ref().map(|x| x.height),
-                            record_single_jpeg: false,
-                            force_tcp: CONFIG.force_tcp,
-                        }
-                        .run()
-                        .await;
-                        if let Err(e) = out {
-                            error!("ffmpeg failed for camera {name}: {e}");
-                        }
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
+                if config::modified() == last_modified {
+                    continue;
                 }
-                CameraMode::MotionDetectRecord => {
-                    let sender = start_monitor(&name, camera).await;
-                    let mut recording_dir = CONFIG.recording_dir.clone();
-                    recording_dir.push(name);
-                    tokio::fs::create_dir_all(&recording_dir).await.unwrap();
-
-                    loop {
-                        let out = ffmpeg::FFmpegConfig {
-                            binary: CONFIG.ffmpeg_bin.clone(),
-                            rtsp_input: camera.rtsp.clone(),
-                            recording_mp4_dir: Some(recording_dir.clone()),
-                            send_images: Some(sender.clone()),
-                            image_width: camera.motion_detection.as_ref().map(|x| x.width),
-                            image_height: camera.motion_detection.as_ref().map(|x| x.height),
-                            record_single_jpeg: false,
-                            force_tcp: CONFIG.force_tcp,
-                        }
-                        .run()
-                        .await;
-                        if let Err(e) = out {
-                            error!("ffmpeg failed for camera {name}: {e}");
-                        }
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
+                info!("config file changed, reloading config");
+            }
+        }
+        reload_config(&mut cameras, &mut last_modified);
+    }
+}
+
+/// How often the config file is checked for modifications
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+struct CameraTask {
+    camera: Arc<CameraConfig>,
+    handle: JoinHandle<()>,
+    /// Pushes config changes that don't need a restart into the running camera task
+    updates: watch::Sender<Arc<CameraConfig>>,
+}
+
+impl CameraTask {
+    fn spawn(name: &str, camera: &CameraConfig) -> Self {
+        let camera = Arc::new(camera.clone());
+        let (updates, receiver) = watch::channel(camera.clone());
+        Self {
+            camera: camera.clone(),
+            handle: tokio::spawn(run_camera(name.to_string(), camera, receiver)),
+            updates,
+        }
+    }
+}
+
+impl Drop for CameraTask {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Reloads the config and applies it to the camera tasks.
+/// `last_modified` is set to the modification time of the file that was read, so a change is only picked up once
+/// whether it arrives through SIGHUP or the file watcher, and a file that fails to load isn't retried until it changes again.
+fn reload_config(
+    cameras: &mut IndexMap<String, CameraTask>,
+    last_modified: &mut Option<SystemTime>,
+) {
+    let old = CONFIG.get();
+    let modified = config::modified();
+    let new = config::reload();
+    *last_modified = modified;
+    let new = match new {
+        Ok(new) => new,
+        Err(e) => {
+            error!("failed to reload config, keeping previous config: {e:#}");
+            return;
+        }
+    };
+    if old.restart_required(&new) {
+        warn!("web, prometheus and index settings only take effect after a restart");
+    }
+    let restart_all = old.cameras_restart_required(&new);
+
+    cameras.retain(|name, _| {
+        let keep = new.cameras.contains_key(name);
+        if !keep {
+            info!("stopping removed camera {name}");
+        }
+        keep
+    });
+    for (name, camera) in &new.cameras {
+        match cameras.get_mut(name.as_str()) {
+            None => {
+                info!("starting added camera {name}");
+                cameras.insert(name.clone(), CameraTask::spawn(name, camera));
+            }
+            Some(task) if restart_all || task.camera.restart_required(camera) => {
+                info!("restarting camera {name} for changed config");
+                *task = CameraTask::spawn(name, camera);
+            }
+            Some(task) => {
+                if *task.camera != *camera {
+                    info!("updating config of camera {name} in place");
+                }
+                task.camera = Arc::new(camera.clone());
+                task.updates.send_replace(task.camera.clone());
+            }
+        }
+    }
+}
+
+async fn run_camera(
+    name: String,
+    camera: Arc<CameraConfig>,
+    updates: watch::Receiver<Arc<CameraConfig>>,
+) {
+    match camera.mode {
+        CameraMode::Disable => (),
+        CameraMode::Record => {
+            let mut recording_dir = CONFIG.get().recording_dir.clone();
+            recording_dir.push(&name);
+            tokio::fs::create_dir_all(&recording_dir).await.unwrap();
+
+            loop {
+                status::ffmpeg_started(&name);
+                let out = ffmpeg::FFmpegConfig {
+                    binary: CONFIG.get().ffmpeg_bin.clone(),
+                    rtsp_input: camera.rtsp.clone(),
+                    recording_mp4_dir: Some(recording_dir.clone()),
+                    send_images: None,
+                    image_width: camera.motion_detection.as_ref().map(|x| x.width),
+                    image_height: camera.motion_detection.as_ref().map(|x| x.height),
+                    record_single_jpeg: false,
+                    force_tcp: CONFIG.get().force_tcp,
+                }
+                .run()
+                .await;
+                if let Err(e) = &out {
+                    error!("ffmpeg failed for camera {name}: {e}");
+                }
+                status::ffmpeg_stopped(&name, out.err().map(|e| e.to_string()));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        CameraMode::MotionDetect => {
+            let sender = start_monitor(&name, &camera, updates.clone()).await;
+
+            loop {
+                status::ffmpeg_started(&name);
+                let out = ffmpeg::FFmpegConfig {
+                    binary: CONFIG.get().ffmpeg_bin.clone(),
+                    rtsp_input: camera.rtsp.clone(),
+                    recording_mp4_dir: None,
+                    send_images: Some(sender.clone()),
+                    image_width: camera.motion_detection.as_ref().map(|x| x.width),
+                    image_height: camera.motion_detection.as_ref().map(|x| x.height),
+                    record_single_jpeg: false,
+                    force_tcp: CONFIG.get().force_tcp,
+                }
+                .run()
+                .await;
+                if let Err(e) = &out {
+                    error!("ffmpeg failed for camera {name}: {e}");
+                }
+                status::ffmpeg_stopped(&name, out.err().map(|e| e.to_string()));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        CameraMode::MotionDetectRecord => {
+            let sender = start_monitor(&name, &camera, updates.clone()).await;
+            let mut recording_dir = CONFIG.get().recording_dir.clone();
+            recording_dir.push(&name);
+            tokio::fs::create_dir_all(&recording_dir).await.unwrap();
+
+            loop {
+                status::ffmpeg_started(&name);
+                let out = ffmpeg::FFmpegConfig {
+                    binary: CONFIG.get().ffmpeg_bin.clone(),
+                    rtsp_input: camera.rtsp.clone(),
+                    recording_mp4_dir: Some(recording_dir.clone()),
+                    send_images: Some(sender.clone()),
+                    image_width: camera.motion_detection.as_ref().map(|x| x.width),
+                    image_height: camera.motion_detection.as_ref().map(|x| x.height),
+                    record_single_jpeg: false,
+                    force_tcp: CONFIG.get().force_tcp,
+                }
+                .run()
+                .await;
+                if let Err(e) = &out {
+                    error!("ffmpeg failed for camera {name}: {e}");
                 }
+                status::ffmpeg_stopped(&name, out.err().map(|e| e.to_string()));
+                tokio::time::sleep(Duration::from_secs(1)).await;
             }
-        }));
+        }
     }
-    let _ = futures::future::select_all(tasks).await;
 }
 
-async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbImage> {
-    let Some(motion_detection_config) = &camera.motion_detection else {
+async fn start_monitor(
+    name: &str,
+    camera: &CameraConfig,
+    mut updates: watch::Receiver<Arc<CameraConfig>>,
+) -> mpsc::Sender<RgbImage> {
+    // kept up to date with `updates`, since detector profiles are switched between updates
+    let Some(mut motion_detection_config) = camera.motion_detection.clone() else {
         panic!("missing motion detection configuration for motion detection camera");
     };
-    let motion_detect_dir = CONFIG.event_dir.clone();
+    let motion_detect_dir = CONFIG.get().event_dir.clone();
     tokio::fs::create_dir_all(&motion_detect_dir).await.unwrap();
 
     let (sender, mut receiver) = mpsc::channel::<RgbImage>(10);
     let mut motion_detector = RunningMotionDetector::new(motion_detection_config.config.clone());
 
-    let camera_alert_priority = motion_detection_config.alert_priority;
-    let frame_rate = camera.frame_rate;
+    let mut camera_alert_priority = motion_detection_config.alert_priority;
+    let mut classification = motion_detection_config.classification.clone().map(Arc::new);
+    let mut frame_rate = camera.frame_rate;
+    let mut profiles = ProfileSelector::default();
+    // labels of the confirmed event in progress, reused once it completes instead of classifying it again
+    let mut confirmed_labels: Option<classify::SharedLabels> = None;
+    // full resolution event clips are cut from the recording when there is one
+    let recording_dir = (camera.mode == CameraMode::MotionDetectRecord)
+        .then(|| CONFIG.get().recording_dir.join(name));
 
     let camera_name = name.to_string();
     tokio::spawn(async move {
         while let Some(new_frame) = receiver.recv().await {
+            if updates.has_changed().unwrap_or(false) {
+                let camera = updates.borrow_and_update().clone();
+                if let Some(new_config) = &camera.motion_detection {
+                    motion_detection_config = new_config.clone();
+                    let profile = profiles.active().map(str::to_string);
+                    let config = motion_detection_config.detector_config(profile.as_deref());
+                    motion_detector.set_profile(profile, config);
+                    camera_alert_priority = motion_detection_config.alert_priority;
+                    classification = motion_detection_config.classification.clone().map(Arc::new);
+                }
+                frame_rate = camera.frame_rate;
+            }
+            let previous_profile = profiles.active().unwrap_or(DEFAULT_PROFILE).to_string();
+            let location = CONFIG.get().location;
+            if let Some(profile) =
+                profiles.update(&motion_detection_config, location, &new_frame, Utc::now())
+            {
+                let name = profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+                info!("{camera_name}: using detector profile {name}");
+                MODECT_PROFILE
+                    .with_label_values(&[&camera_name, &previous_profile])
+                    .set(0);
+                MODECT_PROFILE
+                    .with_label_values(&[&camera_name, name])
+                    .set(1);
+                let config = motion_detection_config.detector_config(profile.as_deref());
+                motion_detector.set_profile(profile, config);
+            }
+            if frames::wanted(&camera_name) {
+                frames::capture(&camera_name, &new_frame);
+            }
+            let debug_image = if frames::debug_wanted(&camera_name) {
+                motion_detector.debug_image(&new_frame)
+            } else {
+                None
+            };
             let stats = motion_detector.frame_recv(new_frame);
             debug!(
                 "{camera_name}: f#{} score={:.02}, stddev = {:.02}",
@@ -225,10 +504,22 @@ async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbIma
             MODECT_STDDEV
                 .with_label_values(&[&camera_name])
                 .inc_by(stats.stddev);
+            let thresholds = motion_detector.thresholds();
+            MODECT_CHANGE_MINIMUM
+                .with_label_values(&[&camera_name])
+                .set(thresholds.change_minimum);
+            MODECT_STDDEV_MINIMUM
+                .with_label_values(&[&camera_name])
+                .set(thresholds.stddev_minimum);
+            MODECT_MINIMUM_TOTAL_CHANGE
+                .with_label_values(&[&camera_name])
+                .set(thresholds.minimum_total_change);
             for (time, state) in motion_detector.drain_pending_states() {
                 MODECT_STATE
                     .with_label_values(&[&camera_name])
                     .set(state.discriminant() as i64);
+                status::motion_state(&camera_name, state.name());
+                mqtt::motion_state(&camera_name, &state);
                 match state {
                     MotionDetectionState::Idle { frame_number } => {
                         trace!("{camera_name}: f#{frame_number} idle");
@@ -248,6 +539,13 @@ async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbIma
                             event.frames.len(),
                             event.total_score
                         );
+                        if !event.rules.is_empty() {
+                            info!(
+                                "{camera_name}: {} tracks, fired {}",
+                                event.tracks.len(),
+                                event.rules.join(", ")
+                            );
+                        }
                     }
                     MotionDetectionState::WaitAndSee {
                         start_frame_number,
@@ -281,19 +579,52 @@ async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbIma
                             event.total_score
                         );
 
+                        confirmed_labels = None;
                         let event = Arc::new(event);
+                        tokio::spawn(mqtt::event(
+                            AlertState::Confirmed,
+                            EventMetadata::new(&camera_name, time, &event),
+                            event.clone(),
+                        ));
+                        if !arming::alerts(&camera_name) {
+                            continue;
+                        }
+                        let labels = classify::classify(
+                            camera_name.clone(),
+                            classification.clone(),
+                            event.clone(),
+                        )
+                        .boxed()
+                        .shared();
+                        confirmed_labels = Some(labels.clone());
+                        let mut alert = Alert {
+                            time,
+                            camera: camera_name.clone(),
+                            state: AlertState::Confirmed,
+                            event,
+                            frame_rate,
+                            priority: camera_alert_priority,
+                            event_filename: None,
+                            preview_filename: None,
+                            aggregate: None,
+                            labels: vec![],
+                        };
                         let camera_name = camera_name.clone();
+                        let classification = classification.clone();
                         tokio::spawn(async move {
                             let start = Instant::now();
-                            alert_event(
-                                time,
-                                event,
-                                camera_alert_priority,
+                            let labels = labels.await;
+                            if !classify::allows_alert(
                                 &camera_name,
-                                frame_rate,
-                                AlertState::Confirmed,
-                            )
-                            .await;
+                                classification.as_deref(),
+                                labels.as_deref(),
+                            ) {
+                                return;
+                            }
+                            alert.labels = labels.unwrap_or_default();
+                            if !alert::dispatch(alert).await {
+                                return;
+                            }
                             let ms = start.elapsed().as_secs_f64() * 1000.0;
                             MODECT_ALERT_LATENCY
                                 .with_label_values(&[&camera_name])
@@ -321,35 +652,87 @@ async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbIma
                             event.frames.len(),
                             event.total_score
                         );
+                        if !event.rules.is_empty() {
+                            info!(
+                                "{camera_name}: {} tracks, fired {}",
+                                event.tracks.len(),
+                                event.rules.join(", ")
+                            );
+                        }
                         let event_path =
                             motion_detect_dir.join(&format!("{}_{}.mp4", camera_name, time));
 
-                        let metadata = EventMetadata {
-                            camera: camera_name.clone(),
-                            when: time,
-                            total_score: event.total_score,
-                            start_stream_frame_number: event.start_stream_frame_number,
-                            end_stream_frame_number: event.end_stream_frame_number,
+                        let mut metadata = EventMetadata::new(&camera_name, time, &event);
+                        let state = if was_confirmed_already {
+                            AlertState::CompletedAfterConfirm
+                        } else {
+                            AlertState::Completed
                         };
 
+                        let record_event = arming::records_events(&camera_name);
+                        let alert_event = arming::alerts(&camera_name);
                         let event = Arc::new(event);
-                        let event2 = event.clone();
+                        // shared by the alert and the saved event
+                        let labels = match confirmed_labels.take().filter(|_| was_confirmed_already)
+                        {
+                            Some(labels) => labels,
+                            None => classify::classify(
+                                camera_name.clone(),
+                                classification.clone(),
+                                event.clone(),
+                            )
+                            .boxed()
+                            .shared(),
+                        };
+                        tokio::spawn({
+                            let labels = labels.clone();
+                            let mut metadata = metadata.clone();
+                            let event = event.clone();
+                            async move {
+                                metadata.labels = labels.await.unwrap_or_default();
+                                mqtt::event(state, metadata, event).await;
+                            }
+                        });
+                        let preview_path = event_path.with_extension("jpg");
+                        let mut alert = Alert {
+                            time,
+                            camera: camera_name.clone(),
+                            state,
+                            event: event.clone(),
+                            frame_rate,
+                            priority: camera_alert_priority,
+                            event_filename: record_event
+                                .then(|| event_path.file_name())
+                                .flatten()
+                                .map(|x| x.to_string_lossy().into_owned()),
+                            preview_filename: record_event
+                                .then(|| preview_path.file_name())
+                                .flatten()
+                                .map(|x| x.to_string_lossy().into_owned()),
+                            aggregate: None,
+                            labels: vec![],
+                        };
                         let camera_name = camera_name.clone();
+                        let recording_dir = recording_dir.clone();
+                        let alert_labels = labels.clone();
+                        let classification = classification.clone();
                         tokio::spawn(async move {
                             let start = Instant::now();
-                            alert_event(
-                                time,
-                                event2,
-                                camera_alert_priority,
+                            if !alert_event {
+                                return;
+                            }
+                            let labels = alert_labels.await;
+                            if !classify::allows_alert(
                                 &camera_name,
-                                frame_rate,
-                                if was_confirmed_already {
-                                    AlertState::CompletedAfterConfirm
-                                } else {
-                                    AlertState::Completed
-                                },
-                            )
-                            .await;
+                                classification.as_deref(),
+                                labels.as_deref(),
+                            ) {
+                                return;
+                            }
+                            alert.labels = labels.unwrap_or_default();
+                            if !alert::dispatch(alert).await {
+                                return;
+                            }
                             let ms = start.elapsed().as_secs_f64() * 1000.0;
                             MODECT_ALERT_LATENCY
                                 .with_label_values(&[&camera_name])
@@ -358,6 +741,10 @@ async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbIma
                             info!("Alert sent in {ms:.02} ms");
                         });
                         tokio::spawn(async move {
+                            if !record_event {
+                                return;
+                            }
+                            metadata.labels = labels.await.unwrap_or_default();
                             if let Err(e) = tokio::fs::write(
                                 &event_path.with_extension("json"),
                                 serde_json::to_string(&metadata).unwrap(),
@@ -366,15 +753,54 @@ async fn start_monitor(name: &str, camera: &CameraConfig) -> mpsc::Sender<RgbIma
                             {
                                 error!("failed to save event metadata to disk: {e}");
                             }
-                            if let Err(e) =
-                                modect_mp4::modect_mp4(&event, frame_rate as u32, &event_path).await
-                            {
-                                error!("failed to save event to disk: {e:#}");
+                            let mut saved = false;
+                            if let Some(recording_dir) = &recording_dir {
+                                match modect_mp4::recording_mp4(&event, recording_dir, &event_path)
+                                    .await
+                                {
+                                    Ok(()) => saved = true,
+                                    Err(e) => error!("failed to cut event from recording, falling back to detection frames: {e:#}"),
+                                }
+                            }
+                            if !saved {
+                                match modect_mp4::modect_mp4(&event, frame_rate as u32, &event_path)
+                                    .await
+                                {
+                                    Ok(()) => saved = true,
+                                    Err(e) => error!("failed to save event to disk: {e:#}"),
+                                }
+                            }
+                            if saved {
+                                let filename = event_path
+                                    .file_name()
+                                    .unwrap()
+                                    .to_string_lossy()
+                                    .into_owned();
+                                if let Err(e) =
+                                    index::insert_event(StoredEvent { filename, metadata }).await
+                                {
+                                    error!("failed to index event: {e:#}");
+                                }
                             }
                         });
                     }
                 }
             }
+            if let Some(image) = debug_image {
+                frames::publish_debug(
+                    &camera_name,
+                    DebugFrame {
+                        image,
+                        change: stats.change,
+                        stddev: stats.stddev,
+                        state: status::get(&camera_name).motion_state.unwrap_or("idle"),
+                        change_minimum: thresholds.change_minimum,
+                        change_maximum: thresholds.change_maximum,
+                        stddev_minimum: thresholds.stddev_minimum,
+                        blobs: stats.blobs,
+                    },
+                );
+            }
         }
     });
     sender
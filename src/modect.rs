@@ -1,8 +1,19 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+use anyhow::Context;
 use chrono::{DateTime, Utc};
 use image::{GrayImage, RgbImage};
+use indexmap::IndexMap;
+use log::error;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::{
+    blob::{self, Blob, BlobConfig},
+    track::{Track, Tracker, TrackingConfig},
+    zone::ZoneConfig,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct RunningMotionDetectorConfig {
     pub change_minimum: f64,
     pub change_maximum: f64,
@@ -12,15 +23,167 @@ pub struct RunningMotionDetectorConfig {
     pub followup_frame_count: usize,
     pub maximum_frame_wait: usize,
     pub mask_file: Option<String>,
+    /// Number of frames before motion began to include at the start of each event
+    #[serde(default)]
+    pub pre_roll_frames: usize,
+    /// Named zones, each checked against the change thresholds on its own. Motion in any zone is motion for the camera.
+    /// If empty, the whole frame (minus `mask_file`) is a single unnamed zone.
+    #[serde(default)]
+    pub zones: IndexMap<String, ZoneConfig>,
+    #[serde(default)]
+    pub algorithm: DetectionAlgorithm,
+    /// Only used by the `background` algorithm
+    #[serde(default)]
+    pub background: BackgroundModelConfig,
+    /// Splits changed pixels into objects. When set, a zone only sees motion if an object within the area limits is centered in it.
+    pub blobs: Option<BlobConfig>,
+    /// Follows objects across frames for tripwires and zone entry rules, needs `blobs`
+    pub tracking: Option<TrackingConfig>,
+    /// Learns `change_minimum`, `stddev_minimum` and `minimum_total_change` from idle frames, the configured values become floors
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+fn default_adaptive_window_frames() -> usize {
+    3000
+}
+
+fn default_adaptive_warmup_frames() -> usize {
+    100
+}
+
+fn default_change_multiplier() -> f64 {
+    3.0
+}
+
+fn default_stddev_multiplier() -> f64 {
+    2.0
+}
+
+fn default_total_change_multiplier() -> f64 {
+    150.0
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AdaptiveConfig {
+    /// Idle frames the baseline is averaged over, so it follows slow changes like nightfall
+    #[serde(default = "default_adaptive_window_frames")]
+    pub window_frames: usize,
+    /// Idle frames seen before learned thresholds are used, the configured ones apply until then
+    #[serde(default = "default_adaptive_warmup_frames")]
+    pub warmup_frames: usize,
+    /// `change_minimum` as a multiple of the baseline change
+    #[serde(default = "default_change_multiplier")]
+    pub change_multiplier: f64,
+    /// `stddev_minimum` as a multiple of the baseline stddev
+    #[serde(default = "default_stddev_multiplier")]
+    pub stddev_multiplier: f64,
+    /// `minimum_total_change` as a multiple of the baseline change
+    #[serde(default = "default_total_change_multiplier")]
+    pub total_change_multiplier: f64,
+}
+
+/// Running averages of the stats of idle frames
+#[derive(Default)]
+struct Baseline {
+    frames: u64,
+    change: f64,
+    stddev: f64,
+}
+
+impl Baseline {
+    fn update(&mut self, diff: &MotionDetectionResult, window_frames: usize) {
+        self.frames += 1;
+        // a plain average until the window fills up
+        let rate = 1.0 / self.frames.min(window_frames as u64) as f64;
+        self.change += rate * (diff.average - self.change);
+        self.stddev += rate * (diff.std_dev_estimate - self.stddev);
+    }
+}
+
+/// Thresholds in effect for the current frame
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    pub change_minimum: f64,
+    pub change_maximum: f64,
+    pub stddev_minimum: f64,
+    pub minimum_total_change: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectionAlgorithm {
+    /// each frame is compared to the previous one, change is the average squared RGB distance between them
+    #[default]
+    FrameDiff,
+    /// each frame is compared to a running model of the background, change is the percentage of pixels in the foreground.
+    /// slow moving objects keep scoring and flicker is learned, at the cost of more CPU per frame.
+    Background,
+}
+
+fn default_learning_rate() -> f64 {
+    0.01
+}
+
+fn default_deviations() -> f64 {
+    3.0
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BackgroundModelConfig {
+    /// Weight of each new frame in the model. Higher adapts to lighting changes faster, but absorbs stopped objects sooner.
+    #[serde(default = "default_learning_rate")]
+    pub learning_rate: f64,
+    /// Pixels more than this many standard deviations away from the model are foreground
+    #[serde(default = "default_deviations")]
+    pub deviations: f64,
+}
+
+impl Default for BackgroundModelConfig {
+    fn default() -> Self {
+        Self {
+            learning_rate: default_learning_rate(),
+            deviations: default_deviations(),
+        }
+    }
+}
+
+/// A zone rasterized at the detection resolution
+struct Zone {
+    name: Option<String>,
+    mask: Option<GrayImage>,
+}
+
+impl Zone {
+    fn detects(&self, x: u32, y: u32) -> bool {
+        self.mask.as_ref().map_or(true, |mask| {
+            mask.get_pixel_checked(x, y).map_or(true, |x| x.0[0] == 0)
+        })
+    }
 }
 
 pub struct RunningMotionDetector {
     mask_image: Option<GrayImage>,
+    /// Rasterized on the first frame after startup or a zone change, since the frame size is only known then
+    zones: Option<Vec<Zone>>,
+    /// Score of each zone in the current detection, parallel to `zones`
+    zone_scores: Vec<f64>,
+    /// Only kept for the `background` algorithm
+    background: Option<BackgroundModel>,
+    tracker: Tracker,
+    /// Only updated with `adaptive`
+    baseline: Baseline,
     config: RunningMotionDetectorConfig,
-    last_frame: Option<RgbImage>,
+    /// Name of the profile `config` came from
+    profile: Option<String>,
+    /// Previous frame and when it was received
+    last_frame: Option<(DateTime<Utc>, RgbImage)>,
     frame_number: u64,
     motion_detector: MotionDetector,
     current_detection: Vec<MotionDetectionFrame>,
+    /// Ring buffer of the most recent idle frames preceding `last_frame`
+    recent_frames: VecDeque<MotionDetectionFrame>,
+    /// Pre-roll frames taken from `recent_frames` when the current detection started
+    detection_pre_roll: Vec<MotionDetectionFrame>,
     followup_frames: Vec<MotionDetectionFrame>,
     detection_start_frame: Option<u64>,
     detection_confirmed: bool,
@@ -30,9 +193,13 @@ pub struct RunningMotionDetector {
 
 #[derive(Clone)]
 pub struct MotionDetectionFrame {
+    /// Wall-clock time the frame was received
+    pub time: DateTime<Utc>,
     pub image: RgbImage,
     pub change: f64,
     pub stddev: f64,
+    /// Objects in the frame, empty unless `blobs` is configured
+    pub blobs: Vec<Blob>,
 }
 
 pub struct MotionDetectionEvent {
@@ -40,6 +207,33 @@ pub struct MotionDetectionEvent {
     pub end_stream_frame_number: u64,
     pub frames: Vec<MotionDetectionFrame>,
     pub total_score: f64,
+    /// Names of the zones that saw motion, highest scoring first
+    pub zones: Vec<String>,
+    /// Objects followed during the event, empty unless `tracking` is configured
+    pub tracks: Vec<Track>,
+    /// Names of the tripwires and zone entry rules that fired during the event
+    pub rules: Vec<String>,
+    /// Detector profile active when the event was reported, None for the camera's own settings
+    pub profile: Option<String>,
+}
+
+impl MotionDetectionEvent {
+    /// Wall-clock time of the first frame (including pre-roll)
+    pub fn start_time(&self) -> Option<DateTime<Utc>> {
+        self.frames.first().map(|x| x.time)
+    }
+
+    /// Wall-clock time of the last frame (including followup)
+    pub fn end_time(&self) -> Option<DateTime<Utc>> {
+        self.frames.last().map(|x| x.time)
+    }
+
+    /// The frame with the most change
+    pub fn best_frame(&self) -> Option<&MotionDetectionFrame> {
+        self.frames
+            .iter()
+            .max_by(|x, y| x.change.partial_cmp(&y.change).unwrap_or(Ordering::Less))
+    }
 }
 
 #[repr(u16)]
@@ -78,26 +272,53 @@ impl MotionDetectionState {
     pub fn discriminant(&self) -> u16 {
         unsafe { *(self as *const Self as *const u16) }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MotionDetectionState::Idle { .. } => "idle",
+            MotionDetectionState::WaitAndSee { .. } => "wait_and_see",
+            MotionDetectionState::Active { .. } => "active",
+            MotionDetectionState::Followup { .. } => "followup",
+            MotionDetectionState::Rejected { .. } => "rejected",
+            MotionDetectionState::Completed { .. } => "completed",
+            MotionDetectionState::ConfirmedInProgress { .. } => "confirmed_in_progress",
+        }
+    }
 }
 
 pub struct MotionDetectionStats {
     pub change: f64,
     pub stddev: f64,
     pub frame_number: u64,
+    pub blobs: Vec<Blob>,
 }
 
 impl RunningMotionDetector {
+    fn load_mask(config: &RunningMotionDetectorConfig) -> anyhow::Result<Option<GrayImage>> {
+        let Some(mask_file) = &config.mask_file else {
+            return Ok(None);
+        };
+        let mask =
+            image::open(mask_file).with_context(|| format!("failed to open mask '{mask_file}'"))?;
+        Ok(Some(mask.to_luma8()))
+    }
+
     pub fn new(config: RunningMotionDetectorConfig) -> Self {
         Self {
-            mask_image: config
-                .mask_file
-                .as_ref()
-                .map(|x| image::open(x).expect("failed to open mask").to_luma8()),
+            mask_image: Self::load_mask(&config).unwrap_or_else(|e| panic!("{e:#}")),
+            zones: None,
+            zone_scores: vec![],
+            background: None,
+            tracker: Tracker::default(),
+            baseline: Baseline::default(),
             config,
+            profile: None,
             last_frame: None,
             frame_number: 0,
             motion_detector: MotionDetector {},
             current_detection: vec![],
+            recent_frames: VecDeque::new(),
+            detection_pre_roll: vec![],
             followup_frames: vec![],
             current_detection_score: 0.0,
             pending_states: vec![],
@@ -106,75 +327,363 @@ impl RunningMotionDetector {
         }
     }
 
+    /// Swaps in a new config, keeping any in-progress detection
+    pub fn set_config(&mut self, config: RunningMotionDetectorConfig) {
+        if config == self.config {
+            return;
+        }
+        if config.mask_file != self.config.mask_file {
+            // a bad mask in a reloaded config must not take the camera down
+            match Self::load_mask(&config) {
+                Ok(mask_image) => self.mask_image = mask_image,
+                Err(e) => error!("{e:#}, keeping the previous mask"),
+            }
+            self.zones = None;
+        }
+        if config.zones != self.config.zones {
+            self.zones = None;
+        }
+        if config.algorithm != self.config.algorithm {
+            self.background = None;
+            self.baseline = Baseline::default();
+        }
+        if config.tracking != self.config.tracking {
+            self.tracker = Tracker::default();
+        }
+        while self.recent_frames.len() > config.pre_roll_frames {
+            self.recent_frames.pop_front();
+        }
+        self.config = config;
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Swaps in the config of a detector profile, keeping any in-progress detection
+    pub fn set_profile(&mut self, profile: Option<String>, config: RunningMotionDetectorConfig) {
+        self.profile = profile;
+        self.set_config(config);
+    }
+
     pub fn drain_pending_states<'a>(
         &'a mut self,
     ) -> impl Iterator<Item = (DateTime<Utc>, MotionDetectionState)> + 'a {
         self.pending_states.drain(..)
     }
 
+    /// Rasterizes the zones at the given frame size if needed
+    fn ensure_zones(&mut self, width: u32, height: u32) {
+        if self.zones.is_some() {
+            return;
+        }
+        let zones: Vec<Zone> = if self.config.zones.is_empty() {
+            vec![Zone {
+                name: None,
+                mask: self.mask_image.clone(),
+            }]
+        } else {
+            self.config
+                .zones
+                .iter()
+                .map(|(name, zone)| Zone {
+                    name: Some(name.clone()),
+                    mask: Some(zone.rasterize(width, height, self.mask_image.as_ref())),
+                })
+                .collect()
+        };
+        self.zone_scores = vec![0.0; zones.len()];
+        self.zones = Some(zones);
+    }
+
+    /// Starts a new background model if the algorithm needs one and there is none for the frame size
+    fn ensure_background(&mut self, frame: &RgbImage) {
+        if self.config.algorithm != DetectionAlgorithm::Background
+            || self.background.as_ref().is_some_and(|x| x.fits(frame))
+        {
+            return;
+        }
+        self.background = Some(BackgroundModel::new(frame));
+    }
+
+    /// The configured thresholds, or the learned ones with `adaptive` once warmed up
+    pub fn thresholds(&self) -> Thresholds {
+        let fixed = Thresholds {
+            change_minimum: self.config.change_minimum,
+            change_maximum: self.config.change_maximum,
+            stddev_minimum: self.config.stddev_minimum,
+            minimum_total_change: self.config.minimum_total_change,
+        };
+        let Some(adaptive) = &self.config.adaptive else {
+            return fixed;
+        };
+        if self.baseline.frames < adaptive.warmup_frames as u64 {
+            return fixed;
+        }
+        Thresholds {
+            change_minimum: fixed
+                .change_minimum
+                .max(self.baseline.change * adaptive.change_multiplier),
+            stddev_minimum: fixed
+                .stddev_minimum
+                .max(self.baseline.stddev * adaptive.stddev_multiplier),
+            minimum_total_change: fixed
+                .minimum_total_change
+                .max(self.baseline.change * adaptive.total_change_multiplier),
+            ..fixed
+        }
+    }
+
+    /// What the detector sees in `new_frame`, with pixels outside of every zone tinted blue: the change from the last frame,
+    /// or the foreground for the `background` algorithm. None for the first frame.
+    pub fn debug_image(&mut self, new_frame: &RgbImage) -> Option<RgbImage> {
+        self.ensure_zones(new_frame.width(), new_frame.height());
+        self.ensure_background(new_frame);
+        let mut out = match &self.background {
+            Some(background) => background.foreground_img(new_frame, &self.config.background),
+            None => {
+                let (_, last_frame) = self.last_frame.as_ref()?;
+                self.motion_detector.frame_diff_img(last_frame, new_frame)
+            }
+        };
+        let zones = self.zones.as_ref()?;
+        for (x, y, pixel) in out.enumerate_pixels_mut() {
+            if !zones.iter().any(|zone| zone.detects(x, y)) {
+                let [r, g, b] = pixel.0;
+                pixel.0 = [r / 2, g / 2, 96 + b / 2];
+            }
+        }
+        Some(out)
+    }
+
+    /// Objects in `new_frame` within the area limits, empty unless `blobs` is configured
+    fn find_blobs(&self, last_frame: &RgbImage, new_frame: &RgbImage) -> Vec<Blob> {
+        let Some(config) = &self.config.blobs else {
+            return vec![];
+        };
+        let mut changed = match &self.background {
+            Some(background) => background.foreground(new_frame, &self.config.background),
+            None => {
+                self.motion_detector
+                    .changed_pixels(last_frame, new_frame, config.pixel_threshold)
+            }
+        };
+        if let Some(zones) = &self.zones {
+            for (x, y, pixel) in changed.enumerate_pixels_mut() {
+                if !zones.iter().any(|zone| zone.detects(x, y)) {
+                    pixel.0 = [0];
+                }
+            }
+        }
+        blob::label(&blob::open(&changed, config.morphology_radius))
+            .into_iter()
+            .filter(|x| config.accepts(x))
+            .collect()
+    }
+
+    /// Whether tracking rules accept (true) or reject (false) the current detection, None to decide by score
+    fn tracking_verdict(&self) -> Option<bool> {
+        self.tracker.verdict(self.config.tracking.as_ref()?)
+    }
+
+    /// Names of the zones that saw motion in the current detection, highest scoring first
+    fn triggered_zones(&self) -> Vec<String> {
+        let Some(zones) = &self.zones else {
+            return vec![];
+        };
+        let mut triggered: Vec<(&String, f64)> = zones
+            .iter()
+            .zip(&self.zone_scores)
+            .filter(|(_, score)| **score > 0.0)
+            .filter_map(|(zone, score)| Some((zone.name.as_ref()?, *score)))
+            .collect();
+        triggered.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap_or(Ordering::Equal));
+        triggered.into_iter().map(|x| x.0.clone()).collect()
+    }
+
+    /// Snapshot of the in-progress detection, including pre-roll
+    fn current_event(&self) -> MotionDetectionEvent {
+        MotionDetectionEvent {
+            start_stream_frame_number: self.detection_start_frame.unwrap()
+                - self.detection_pre_roll.len() as u64,
+            end_stream_frame_number: self.frame_number,
+            frames: self
+                .detection_pre_roll
+                .iter()
+                .chain(self.current_detection.iter())
+                .cloned()
+                .collect(),
+            total_score: self.current_detection_score,
+            zones: self.triggered_zones(),
+            tracks: self.tracker.tracks(),
+            rules: self.tracker.fired(),
+            profile: self.profile.clone(),
+        }
+    }
+
+    /// Ends the in-progress detection (including followup frames and pre-roll)
+    fn finish_event(&mut self) -> MotionDetectionEvent {
+        self.current_detection.append(&mut self.followup_frames);
+        let pre_roll = self.detection_pre_roll.len() as u64;
+        let zones = self.triggered_zones();
+        self.zone_scores.iter_mut().for_each(|x| *x = 0.0);
+        let (tracks, rules) = self.tracker.take();
+        MotionDetectionEvent {
+            start_stream_frame_number: self.detection_start_frame.take().unwrap() - pre_roll,
+            end_stream_frame_number: self.frame_number - 1,
+            frames: self
+                .detection_pre_roll
+                .drain(..)
+                .chain(self.current_detection.drain(..))
+                .collect(),
+            total_score: self.current_detection_score,
+            zones,
+            tracks,
+            rules,
+            profile: self.profile.clone(),
+        }
+    }
+
     pub fn frame_recv(&mut self, new_frame: RgbImage) -> MotionDetectionStats {
-        let Some(last_frame) = self.last_frame.as_ref() else {
-            self.pending_states.push((Utc::now(), MotionDetectionState::Idle { frame_number: self.frame_number }));
-            self.last_frame = Some(new_frame);
+        self.frame_recv_at(new_frame, Utc::now())
+    }
+
+    /// `frame_recv` for a frame received at `now`, i.e. replayed from a recording
+    pub fn frame_recv_at(
+        &mut self,
+        new_frame: RgbImage,
+        now: DateTime<Utc>,
+    ) -> MotionDetectionStats {
+        self.ensure_zones(new_frame.width(), new_frame.height());
+        self.ensure_background(&new_frame);
+        let Some((last_frame_time, last_frame)) = self.last_frame.as_ref() else {
+            self.pending_states.push((
+                now,
+                MotionDetectionState::Idle {
+                    frame_number: self.frame_number,
+                },
+            ));
+            self.last_frame = Some((now, new_frame));
             self.frame_number += 1;
             return MotionDetectionStats {
                 change: 0.0,
                 stddev: 0.0,
                 frame_number: self.frame_number - 1,
+                blobs: vec![],
             };
         };
-        let diff =
-            self.motion_detector
-                .frame_diff(last_frame, &new_frame, self.mask_image.as_ref());
-        if diff.average > self.config.change_minimum
-            && diff.average < self.config.change_maximum
-            && diff.std_dev_estimate > self.config.stddev_minimum
-        {
+        let thresholds = self.thresholds();
+        // the frame before the one that ends a detection is its last followup frame, not pre-roll for the next one
+        let was_idle = self.current_detection.is_empty();
+        let diffs: Vec<MotionDetectionResult> = self
+            .zones
+            .iter()
+            .flatten()
+            .map(|zone| match &self.background {
+                Some(background) => {
+                    background.detect(&new_frame, zone.mask.as_ref(), &self.config.background)
+                }
+                None => self
+                    .motion_detector
+                    .frame_diff(last_frame, &new_frame, zone.mask.as_ref()),
+            })
+            .collect();
+        let blobs = self.find_blobs(last_frame, &new_frame);
+        if let Some(background) = &mut self.background {
+            background.update(&new_frame, &self.config.background);
+        }
+        if let Some(tracking) = &self.config.tracking {
+            self.tracker.update(
+                self.frame_number,
+                &blobs,
+                new_frame.dimensions(),
+                tracking,
+                &self.config.zones,
+            );
+        }
+        let triggered: Vec<bool> = diffs
+            .iter()
+            .zip(self.zones.iter().flatten())
+            .map(|(diff, zone)| {
+                diff.average > thresholds.change_minimum
+                    && diff.average < thresholds.change_maximum
+                    && diff.std_dev_estimate > thresholds.stddev_minimum
+                    && (self.config.blobs.is_none()
+                        || blobs
+                            .iter()
+                            .any(|x| zone.detects(x.centroid[0] as u32, x.centroid[1] as u32)))
+            })
+            .collect();
+        // the frame's change is that of the most changed triggered zone, or of the most changed zone if none triggered
+        let diff = diffs
+            .iter()
+            .zip(&triggered)
+            .max_by(|(x, x_triggered), (y, y_triggered)| {
+                x_triggered
+                    .cmp(y_triggered)
+                    .then(x.average.partial_cmp(&y.average).unwrap_or(Ordering::Less))
+            })
+            .map(|x| x.0.clone())
+            .unwrap();
+        if let Some(adaptive) = &self.config.adaptive {
+            if !triggered.contains(&true) && self.current_detection.is_empty() {
+                self.baseline.update(&diff, adaptive.window_frames);
+            }
+        }
+        if triggered.contains(&true) {
+            for ((score, diff), triggered) in
+                self.zone_scores.iter_mut().zip(&diffs).zip(&triggered)
+            {
+                if *triggered {
+                    *score += diff.average;
+                }
+            }
             if !self.followup_frames.is_empty() {
                 self.current_detection
                     .extend(self.followup_frames.drain(..));
             } else if self.current_detection.is_empty() {
+                self.detection_pre_roll.extend(self.recent_frames.drain(..));
                 self.current_detection.push(MotionDetectionFrame {
+                    time: *last_frame_time,
                     image: last_frame.clone(),
                     change: 0.0,
                     stddev: 0.0,
+                    blobs: vec![],
                 });
             }
             if self.detection_start_frame.is_none() {
                 self.detection_start_frame = Some(self.frame_number - 1);
             }
-            if self.current_detection_score >= self.config.minimum_total_change
-                && self.frame_number - self.detection_start_frame.unwrap()
-                    > (self.config.maximum_frame_wait
-                        + self.config.followup_frame_count
-                        + self.config.minimum_frame_count) as u64
-                && !self.detection_confirmed
-            {
+            let confirmed = self.tracking_verdict().unwrap_or(
+                self.current_detection_score >= thresholds.minimum_total_change
+                    && self.frame_number - self.detection_start_frame.unwrap()
+                        > (self.config.maximum_frame_wait
+                            + self.config.followup_frame_count
+                            + self.config.minimum_frame_count) as u64,
+            );
+            if confirmed && !self.detection_confirmed {
                 self.detection_confirmed = true;
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::ConfirmedInProgress {
-                        event: MotionDetectionEvent {
-                            start_stream_frame_number: self.detection_start_frame.unwrap(),
-                            end_stream_frame_number: self.frame_number,
-                            frames: self.current_detection.clone(),
-                            total_score: self.current_detection_score,
-                        },
+                        event: self.current_event(),
                     },
                 ));
             }
             self.current_detection.push(MotionDetectionFrame {
+                time: now,
                 image: new_frame.clone(),
                 change: diff.average,
                 stddev: diff.std_dev_estimate,
+                blobs: blobs.clone(),
             });
             self.current_detection_score += diff.average;
 
             if self.current_detection.len() <= self.config.minimum_frame_count
-                || self.current_detection_score < self.config.minimum_total_change
+                || self.current_detection_score < thresholds.minimum_total_change
             {
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::WaitAndSee {
                         start_frame_number: self.detection_start_frame.unwrap(),
                         current_frame_number: self.frame_number,
@@ -183,7 +692,7 @@ impl RunningMotionDetector {
                 ));
             } else {
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::Active {
                         start_frame_number: self.detection_start_frame.unwrap(),
                         current_frame_number: self.frame_number,
@@ -194,7 +703,7 @@ impl RunningMotionDetector {
         } else if !self.current_detection.is_empty() {
             if self.followup_frames.len() < self.config.followup_frame_count {
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::Followup {
                         start_frame_number: self.detection_start_frame.unwrap(),
                         current_frame_number: self.frame_number,
@@ -202,71 +711,205 @@ impl RunningMotionDetector {
                     },
                 ));
                 self.followup_frames.push(MotionDetectionFrame {
+                    time: now,
                     image: new_frame.clone(),
                     change: 0.0,
                     stddev: 0.0,
+                    blobs: vec![],
                 });
-            } else if self.current_detection.len() <= self.config.minimum_frame_count
-                || self.current_detection_score < self.config.minimum_total_change
-            {
-                self.current_detection
-                    .extend(self.followup_frames.drain(..));
-                self.pending_states.push((
-                    Utc::now(),
-                    MotionDetectionState::Rejected {
-                        event: MotionDetectionEvent {
-                            start_stream_frame_number: self.detection_start_frame.take().unwrap(),
-                            end_stream_frame_number: self.frame_number - 1,
-                            frames: self.current_detection.drain(..).collect(),
-                            total_score: self.current_detection_score,
-                        },
-                    },
-                ));
+            } else if !self.tracking_verdict().unwrap_or(
+                self.current_detection.len() > self.config.minimum_frame_count
+                    && self.current_detection_score >= thresholds.minimum_total_change,
+            ) {
+                let event = self.finish_event();
+                self.pending_states
+                    .push((now, MotionDetectionState::Rejected { event }));
                 self.current_detection_score = 0.0;
                 self.detection_confirmed = false;
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::Idle {
                         frame_number: self.frame_number,
                     },
                 ));
             } else {
-                self.current_detection
-                    .extend(self.followup_frames.drain(..));
+                let event = self.finish_event();
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::Completed {
                         was_confirmed_already: self.detection_confirmed,
-                        event: MotionDetectionEvent {
-                            start_stream_frame_number: self.detection_start_frame.take().unwrap(),
-                            end_stream_frame_number: self.frame_number - 1,
-                            frames: self.current_detection.drain(..).collect(),
-                            total_score: self.current_detection_score,
-                        },
+                        event,
                     },
                 ));
                 self.current_detection_score = 0.0;
                 self.detection_confirmed = false;
                 self.pending_states.push((
-                    Utc::now(),
+                    now,
                     MotionDetectionState::Idle {
                         frame_number: self.frame_number,
                     },
                 ));
             }
+        } else {
+            // rules fired outside of a detection don't carry over to the next one
+            self.tracker.take();
         }
         self.frame_number += 1;
-        self.last_frame = Some(new_frame);
+        let previous_frame = self.last_frame.replace((now, new_frame));
+        if self.config.pre_roll_frames > 0 && was_idle && self.current_detection.is_empty() {
+            if let Some((time, image)) = previous_frame {
+                if self.recent_frames.len() >= self.config.pre_roll_frames {
+                    self.recent_frames.pop_front();
+                }
+                self.recent_frames.push_back(MotionDetectionFrame {
+                    time,
+                    image,
+                    change: 0.0,
+                    stddev: 0.0,
+                    blobs: vec![],
+                });
+            }
+        }
         MotionDetectionStats {
             change: diff.average,
             stddev: diff.std_dev_estimate,
             frame_number: self.frame_number - 1,
+            blobs,
+        }
+    }
+}
+
+/// Floor on the per-pixel variance, so sensor noise in perfectly still areas isn't foreground
+const MIN_BACKGROUND_VARIANCE: f32 = 3.0 * 8.0 * 8.0;
+
+/// A running Gaussian per pixel: the average color and the average squared distance from it
+pub struct BackgroundModel {
+    width: u32,
+    height: u32,
+    mean: Vec<[f32; 3]>,
+    variance: Vec<f32>,
+}
+
+impl BackgroundModel {
+    pub fn new(frame: &RgbImage) -> Self {
+        Self {
+            width: frame.width(),
+            height: frame.height(),
+            mean: frame.pixels().map(|x| x.0.map(|c| c as f32)).collect(),
+            variance: vec![MIN_BACKGROUND_VARIANCE; frame.len() / 3],
+        }
+    }
+
+    /// True if the model was built for frames of this size
+    pub fn fits(&self, frame: &RgbImage) -> bool {
+        frame.dimensions() == (self.width, self.height)
+    }
+
+    /// Squared RGB distance of each pixel from the model's mean
+    fn distances<'a>(&'a self, frame: &'a RgbImage) -> impl Iterator<Item = f32> + 'a {
+        frame.pixels().zip(&self.mean).map(|(pixel, mean)| {
+            pixel
+                .0
+                .iter()
+                .zip(mean)
+                .map(|(c, mean)| (*c as f32 - mean).powi(2))
+                .sum::<f32>()
+        })
+    }
+
+    fn is_foreground(&self, index: usize, distance: f32, config: &BackgroundModelConfig) -> bool {
+        distance
+            > (config.deviations as f32).powi(2) * self.variance[index].max(MIN_BACKGROUND_VARIANCE)
+    }
+
+    /// `average` is the percentage of unmasked pixels in the foreground, `std_dev_estimate` is over the squared distances
+    /// from the model like `MotionDetector::frame_diff`, so `stddev_minimum` still rejects uniform lighting changes
+    pub fn detect(
+        &self,
+        frame: &RgbImage,
+        mask: Option<&GrayImage>,
+        config: &BackgroundModelConfig,
+    ) -> MotionDetectionResult {
+        assert!(self.fits(frame));
+        let mut foreground = 0u64;
+        let mut sum = 0.0f64;
+        let mut running_stddev = 0.0f64;
+        let mut pixel_ct = 0u64;
+        let mut mask_iter = mask.map(|x| x.pixels());
+        for (index, distance) in self.distances(frame).enumerate() {
+            let mask = mask_iter
+                .as_mut()
+                .and_then(|x| x.next())
+                .map(|x| x.0[0] == 0)
+                .unwrap_or(true);
+            if !mask {
+                continue;
+            }
+            if self.is_foreground(index, distance, config) {
+                foreground += 1;
+            }
+            if pixel_ct > 0 {
+                running_stddev += (distance as f64 - (sum / pixel_ct as f64)).powi(2);
+            }
+            pixel_ct += 1;
+            sum += distance as f64;
+        }
+        if pixel_ct == 0 {
+            return MotionDetectionResult::default();
+        }
+        MotionDetectionResult {
+            average: foreground as f64 * 100.0 / pixel_ct as f64,
+            std_dev_estimate: (running_stddev / pixel_ct as f64).sqrt(),
+        }
+    }
+
+    /// Foreground pixels as 255, background as 0
+    pub fn foreground(&self, frame: &RgbImage, config: &BackgroundModelConfig) -> GrayImage {
+        let mut out = GrayImage::new(self.width, self.height);
+        for ((index, distance), out) in self.distances(frame).enumerate().zip(out.pixels_mut()) {
+            if self.is_foreground(index, distance, config) {
+                out.0 = [255];
+            }
+        }
+        out
+    }
+
+    /// Foreground pixels in white, the background shaded by its distance from the model
+    pub fn foreground_img(&self, frame: &RgbImage, config: &BackgroundModelConfig) -> RgbImage {
+        let mut out = frame.clone();
+        for ((index, distance), out) in self.distances(frame).enumerate().zip(out.pixels_mut()) {
+            let x = if self.is_foreground(index, distance, config) {
+                255
+            } else {
+                distance.sqrt().min(127.0) as u8
+            };
+            out.0 = [x, x, x];
+        }
+        out
+    }
+
+    /// Blends the frame into the model. Only background pixels feed the variance, so it learns noise and flicker
+    /// but not objects, which fade into the mean at `learning_rate`.
+    pub fn update(&mut self, frame: &RgbImage, config: &BackgroundModelConfig) {
+        assert!(self.fits(frame));
+        let rate = config.learning_rate as f32;
+        for (index, pixel) in frame.pixels().enumerate() {
+            let mut distance = 0.0;
+            for (c, mean) in pixel.0.iter().zip(&mut self.mean[index]) {
+                let delta = *c as f32 - *mean;
+                distance += delta * delta;
+                *mean += rate * delta;
+            }
+            if !self.is_foreground(index, distance, config) {
+                self.variance[index] += rate * (distance - self.variance[index]);
+            }
         }
     }
 }
 
 pub struct MotionDetector {}
 
+#[derive(Clone, Default)]
 pub struct MotionDetectionResult {
     pub average: f64,
     pub std_dev_estimate: f64,
@@ -305,13 +948,38 @@ impl MotionDetector {
             pixel_ct += 1;
             sum += diff as u64;
         }
+        if pixel_ct == 0 {
+            // everything is masked out, e.g. a zone inside `mask_file`, so there is no change
+            return MotionDetectionResult::default();
+        }
         MotionDetectionResult {
             average: sum as f64 / pixel_ct as f64,
             std_dev_estimate: (running_stddev / pixel_ct as f64).sqrt(),
         }
     }
 
-    #[allow(dead_code)]
+    /// Pixels whose squared RGB distance is above `threshold` as 255, others as 0
+    pub fn changed_pixels(
+        &self,
+        frame1: &RgbImage,
+        frame2: &RgbImage,
+        threshold: u32,
+    ) -> GrayImage {
+        let mut out = GrayImage::new(frame1.width(), frame1.height());
+        for ((pixel1, pixel2), out) in frame1.pixels().zip(frame2.pixels()).zip(out.pixels_mut()) {
+            let diff = pixel1
+                .0
+                .iter()
+                .zip(pixel2.0.iter())
+                .map(|(c1, c2)| ((*c1 as i32) - (*c2 as i32)).pow(2))
+                .sum::<i32>() as u32;
+            if diff > threshold {
+                out.0 = [255];
+            }
+        }
+        out
+    }
+
     pub fn frame_diff_img(&self, frame1: &RgbImage, frame2: &RgbImage) -> RgbImage {
         let mut out = frame1.clone();
         for ((pixel1, pixel2), out) in frame1.pixels().zip(frame2.pixels()).zip(out.pixels_mut()) {
@@ -326,4 +994,501 @@ impl MotionDetector {
         }
         out
     }
+
+    /// The second frame with the change from the first overlaid in red
+    pub fn heatmap(&self, frame1: &RgbImage, frame2: &RgbImage) -> RgbImage {
+        let mut out = self.frame_diff_img(frame1, frame2);
+        for (out, pixel) in out.pixels_mut().zip(frame2.pixels()) {
+            let heat = out.0[0] as u16;
+            let fade = |x: u8| (x as u16 * (255 - heat) / 255) as u8;
+            out.0 = [
+                fade(pixel.0[0]) + heat as u8,
+                fade(pixel.0[1]),
+                fade(pixel.0[2]),
+            ];
+        }
+        out
+    }
+}
+
+/// Unit tests of the detector's parts, and golden-file tests of its state machine. Each scenario feeds synthetic
+/// frames and compares the states it reports against `tests/fixtures/modect/<scenario>.txt`. Run with `RMR_BLESS=1` to
+/// rewrite the fixtures after an intended change, and review their diff.
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write, path::PathBuf};
+
+    use chrono::{Duration, TimeZone};
+    use image::{Luma, Rgb};
+
+    use super::*;
+
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 48;
+    const FRAME_MILLIS: i64 = 40;
+
+    fn config() -> RunningMotionDetectorConfig {
+        serde_yaml::from_str(
+            "
+change_minimum: 20.0
+change_maximum: 2500.0
+stddev_minimum: 100.0
+minimum_frame_count: 5
+minimum_total_change: 500.0
+followup_frame_count: 4
+maximum_frame_wait: 0
+mask_file: null
+",
+        )
+        .unwrap()
+    }
+
+    fn adaptive_detector(adaptive_yaml: &str) -> RunningMotionDetector {
+        RunningMotionDetector::new(RunningMotionDetectorConfig {
+            adaptive: Some(serde_yaml::from_str(adaptive_yaml).unwrap()),
+            ..config()
+        })
+    }
+
+    fn idle(change: f64, stddev: f64) -> MotionDetectionResult {
+        MotionDetectionResult {
+            average: change,
+            std_dev_estimate: stddev,
+        }
+    }
+
+    fn gray(value: u8) -> RgbImage {
+        RgbImage::from_pixel(WIDTH, HEIGHT, Rgb([value; 3]))
+    }
+
+    /// A frame of gray 100 with a 16x12 square of gray `value`, 192 of its 3072 pixels
+    fn square(value: u8) -> RgbImage {
+        let mut frame = gray(100);
+        for y in 24..36 {
+            for x in 40..56 {
+                frame.put_pixel(x, y, Rgb([value; 3]));
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn background_foreground_percentage() {
+        let config = BackgroundModelConfig::default();
+        let model = BackgroundModel::new(&gray(100));
+        assert_eq!(model.detect(&gray(100), None, &config).average, 0.0);
+        assert_eq!(model.detect(&square(200), None, &config).average, 6.25);
+        // the square is in the right half, 192 of its 1536 pixels
+        let right_half = GrayImage::from_fn(WIDTH, HEIGHT, |x, _| {
+            Luma([if x < WIDTH / 2 { 255 } else { 0 }])
+        });
+        assert_eq!(
+            model
+                .detect(&square(200), Some(&right_half), &config)
+                .average,
+            12.5
+        );
+    }
+
+    #[test]
+    fn background_variance_floor() {
+        let config = BackgroundModelConfig::default();
+        let mut model = BackgroundModel::new(&gray(100));
+        for _ in 0..1000 {
+            model.update(&gray(100), &config);
+        }
+        // a still scene learns a variance of almost 0, 3 deviations of the floor are a squared distance of 1728:
+        // 20 levels per channel (1200) are noise, 30 (2700) are foreground
+        assert_eq!(model.detect(&gray(120), None, &config).average, 0.0);
+        assert_eq!(model.detect(&gray(130), None, &config).average, 100.0);
+    }
+
+    #[test]
+    fn background_absorbs_stopped_objects() {
+        let config = BackgroundModelConfig::default();
+        let mut model = BackgroundModel::new(&gray(100));
+        // 1% per frame, the square is absorbed after about 140 frames
+        for _ in 0..100 {
+            model.update(&square(200), &config);
+        }
+        assert_eq!(model.detect(&square(200), None, &config).average, 6.25);
+        for _ in 0..100 {
+            model.update(&square(200), &config);
+        }
+        assert_eq!(model.detect(&square(200), None, &config).average, 0.0);
+
+        let config = BackgroundModelConfig {
+            learning_rate: 0.05,
+            ..BackgroundModelConfig::default()
+        };
+        let mut model = BackgroundModel::new(&gray(100));
+        // 5% per frame, after about 28 frames
+        for _ in 0..20 {
+            model.update(&square(200), &config);
+        }
+        assert_eq!(model.detect(&square(200), None, &config).average, 6.25);
+        for _ in 0..20 {
+            model.update(&square(200), &config);
+        }
+        assert_eq!(model.detect(&square(200), None, &config).average, 0.0);
+    }
+
+    #[test]
+    fn baseline_window() {
+        let mut baseline = Baseline::default();
+        baseline.update(&idle(10.0, 100.0), 2);
+        assert_eq!((baseline.change, baseline.stddev), (10.0, 100.0));
+        // a plain average until the window is full
+        baseline.update(&idle(20.0, 200.0), 2);
+        assert_eq!((baseline.change, baseline.stddev), (15.0, 150.0));
+        // then each frame weighs 1 / window_frames
+        baseline.update(&idle(40.0, 350.0), 2);
+        assert_eq!((baseline.change, baseline.stddev), (27.5, 250.0));
+        assert_eq!(baseline.frames, 3);
+    }
+
+    #[test]
+    fn adaptive_thresholds() {
+        let mut detector = adaptive_detector("warmup_frames: 3");
+        let fixed = detector.thresholds();
+        assert_eq!(
+            (
+                fixed.change_minimum,
+                fixed.change_maximum,
+                fixed.stddev_minimum,
+                fixed.minimum_total_change
+            ),
+            (20.0, 2500.0, 100.0, 500.0)
+        );
+        for _ in 0..2 {
+            detector.baseline.update(&idle(10.0, 80.0), 3000);
+        }
+        // still warming up
+        assert_eq!(detector.thresholds().change_minimum, 20.0);
+        detector.baseline.update(&idle(10.0, 80.0), 3000);
+        let learned = detector.thresholds();
+        assert_eq!(
+            (
+                learned.change_minimum,
+                learned.change_maximum,
+                learned.stddev_minimum,
+                learned.minimum_total_change
+            ),
+            (30.0, 2500.0, 160.0, 1500.0)
+        );
+
+        let mut detector = adaptive_detector(
+            "
+warmup_frames: 1
+change_multiplier: 5.0
+stddev_multiplier: 1.5
+total_change_multiplier: 100.0
+",
+        );
+        detector.baseline.update(&idle(10.0, 80.0), 3000);
+        let learned = detector.thresholds();
+        assert_eq!(
+            (
+                learned.change_minimum,
+                learned.stddev_minimum,
+                learned.minimum_total_change
+            ),
+            (50.0, 120.0, 1000.0)
+        );
+    }
+
+    #[test]
+    fn adaptive_thresholds_floor() {
+        let mut detector = adaptive_detector("warmup_frames: 1");
+        // a very still scene can't lower the thresholds below the configured ones
+        detector.baseline.update(&idle(1.0, 10.0), 3000);
+        let thresholds = detector.thresholds();
+        assert_eq!(
+            (
+                thresholds.change_minimum,
+                thresholds.stddev_minimum,
+                thresholds.minimum_total_change
+            ),
+            (20.0, 100.0, 500.0)
+        );
+    }
+
+    #[test]
+    fn adaptive_baseline_idle_frames_only() {
+        let mut detector = adaptive_detector("warmup_frames: 10");
+        // the first frame has nothing to compare to
+        for _ in 0..11 {
+            detector.frame_recv(gray(100));
+        }
+        assert_eq!(detector.baseline.frames, 10);
+        // 2 frames of motion, 4 followup frames and the one that ends the event
+        detector.frame_recv(square(200));
+        for _ in 0..6 {
+            detector.frame_recv(gray(100));
+        }
+        assert_eq!(detector.baseline.frames, 10);
+        assert_eq!(detector.baseline.change, 0.0);
+        detector.frame_recv(gray(100));
+        assert_eq!(detector.baseline.frames, 11);
+    }
+
+    /// Deterministic noise in `-2..=2`, like a sensor's
+    fn noise(x: u32, y: u32, frame: u32) -> i32 {
+        let mut hash = x
+            .wrapping_mul(374761393)
+            .wrapping_add(y.wrapping_mul(668265263))
+            .wrapping_add(frame.wrapping_mul(2246822519));
+        hash = (hash ^ (hash >> 13)).wrapping_mul(1274126177);
+        (hash >> 16) as i32 % 5 - 2
+    }
+
+    /// A static scene of gradients, shifted by `offset` pixels
+    fn scene(frame: u32, offset: i32) -> RgbImage {
+        RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+            let sx = (x as i32 + offset).rem_euclid(WIDTH as i32) as u32;
+            let base = [
+                (sx * 3) % 160 + 40,
+                (y * 4) % 160 + 40,
+                ((sx + y) * 2) % 160 + 40,
+            ];
+            Rgb(base.map(|c| (c as i32 + noise(x, y, frame)).clamp(0, 255) as u8))
+        })
+    }
+
+    fn fill(image: &mut RgbImage, left: u32, top: u32, size: u32, color: [u8; 3]) {
+        for y in top..(top + size).min(HEIGHT) {
+            for x in left..(left + size).min(WIDTH) {
+                image.put_pixel(x, y, Rgb(color));
+            }
+        }
+    }
+
+    /// One line per reported state, `<frame>: <state> <details>`
+    fn run(config: RunningMotionDetectorConfig, frames: impl Iterator<Item = RgbImage>) -> String {
+        let start = Utc.timestamp_opt(0, 0).unwrap();
+        let mut detector = RunningMotionDetector::new(config);
+        let mut out = String::new();
+        for (i, frame) in frames.enumerate() {
+            detector.frame_recv_at(
+                frame,
+                start + Duration::milliseconds(FRAME_MILLIS * i as i64),
+            );
+            for (_, state) in detector.drain_pending_states() {
+                let details = match &state {
+                    MotionDetectionState::Idle { frame_number } => format!("frame={frame_number}"),
+                    MotionDetectionState::WaitAndSee {
+                        start_frame_number,
+                        current_frame_number,
+                        current_score,
+                    }
+                    | MotionDetectionState::Active {
+                        start_frame_number,
+                        current_frame_number,
+                        current_score,
+                    }
+                    | MotionDetectionState::Followup {
+                        start_frame_number,
+                        current_frame_number,
+                        current_score,
+                    } => format!(
+                        "start={start_frame_number} current={current_frame_number} score={current_score:.2}"
+                    ),
+                    MotionDetectionState::Rejected { event }
+                    | MotionDetectionState::Completed { event, .. }
+                    | MotionDetectionState::ConfirmedInProgress { event } => {
+                        let mut details = format!(
+                            "frames={}-{} count={} score={:.2}",
+                            event.start_stream_frame_number,
+                            event.end_stream_frame_number,
+                            event.frames.len(),
+                            event.total_score
+                        );
+                        if let MotionDetectionState::Completed {
+                            was_confirmed_already,
+                            ..
+                        } = &state
+                        {
+                            write!(details, " confirmed_already={was_confirmed_already}").unwrap();
+                        }
+                        details
+                    }
+                };
+                writeln!(out, "{i}: {} {details}", state.name()).unwrap();
+            }
+        }
+        out
+    }
+
+    fn check(name: &str, actual: String) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/modect")
+            .join(format!("{name}.txt"));
+        if std::env::var_os("RMR_BLESS").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!(
+                "failed to read '{}' ({e}), run with RMR_BLESS=1 to create it",
+                path.display()
+            )
+        });
+        assert!(
+            expected == actual,
+            "states differ from '{}', run with RMR_BLESS=1 to update it if intended\nexpected:\n{expected}\nactual:\n{actual}",
+            path.display()
+        );
+    }
+
+    /// A square moving right by 2 pixels a frame during `moving`, the scene is still otherwise
+    fn moving_square(frames: u32, moving: std::ops::Range<u32>) -> impl Iterator<Item = RgbImage> {
+        (0..frames).map(move |i| {
+            let mut frame = scene(i, 0);
+            let position = i.clamp(moving.start, moving.end) - moving.start;
+            fill(&mut frame, position * 2, 16, 12, [250, 250, 250]);
+            frame
+        })
+    }
+
+    #[test]
+    fn static_scene() {
+        check("static_scene", run(config(), (0..60).map(|i| scene(i, 0))));
+    }
+
+    #[test]
+    fn flicker() {
+        // a light toggling for a few frames is too short to be an event
+        let frames = (0..40).map(|i| {
+            let mut frame = scene(i, 0);
+            if (10..14).contains(&i) && i % 2 == 0 {
+                fill(&mut frame, 40, 8, 10, [255, 255, 200]);
+            }
+            frame
+        });
+        check("flicker", run(config(), frames));
+    }
+
+    #[test]
+    fn moving_square_completes() {
+        check("moving_square", run(config(), moving_square(60, 10..30)));
+    }
+
+    #[test]
+    fn moving_square_pausing() {
+        // pauses shorter than `followup_frame_count` continue the event
+        let frames = (0..70).map(|i| {
+            let mut frame = scene(i, 0);
+            let position = match i {
+                0..=10 => 0,
+                11..=20 => i - 10,
+                21..=23 => 10,
+                24..=34 => i - 13,
+                _ => 21,
+            };
+            fill(&mut frame, position * 2, 16, 12, [250, 250, 250]);
+            frame
+        });
+        check("moving_square_pausing", run(config(), frames));
+    }
+
+    #[test]
+    fn maximum_frame_wait() {
+        // confirmed once the event outlasts maximum_frame_wait + followup_frame_count + minimum_frame_count frames
+        check(
+            "maximum_frame_wait",
+            run(
+                RunningMotionDetectorConfig {
+                    maximum_frame_wait: 10,
+                    ..config()
+                },
+                moving_square(70, 10..40),
+            ),
+        );
+    }
+
+    #[test]
+    fn camera_shake() {
+        // the whole scene jumping around changes more than change_maximum, only settling back is seen and rejected
+        let frames = (0..40).map(|i| {
+            let offset = if (10..20).contains(&i) {
+                [3, -2, 4, -3][i as usize % 4]
+            } else {
+                0
+            };
+            scene(i, offset)
+        });
+        check("camera_shake", run(config(), frames));
+    }
+
+    #[test]
+    fn pre_roll() {
+        // the second event starts right after the first, its pre-roll mustn't reach back into the first one
+        let frames = (0..50).map(|i| {
+            let mut frame = scene(i, 0);
+            let position = match i {
+                0..=10 => 0,
+                11..=20 => i - 10,
+                21..=26 => 10,
+                27..=36 => i - 16,
+                _ => 20,
+            };
+            fill(&mut frame, position * 2, 16, 12, [250, 250, 250]);
+            frame
+        });
+        check(
+            "pre_roll",
+            run(
+                RunningMotionDetectorConfig {
+                    pre_roll_frames: 3,
+                    ..config()
+                },
+                frames,
+            ),
+        );
+    }
+
+    /// A 16x16 square sliding into view from the left edge, one pixel every 6 frames until 6 pixels of it are in
+    fn slow_square(frames: u32) -> impl Iterator<Item = RgbImage> {
+        (0..frames).map(|i| {
+            let mut frame = scene(i, 0);
+            if i >= 10 {
+                let visible = ((i - 10) / 6 + 1).min(6);
+                for y in 16..32 {
+                    for x in 0..visible {
+                        frame.put_pixel(x, y, Rgb([250, 250, 250]));
+                    }
+                }
+            }
+            frame
+        })
+    }
+
+    #[test]
+    fn background_slow_square() {
+        // frame_diff only sees the square on the frames it moves, each too short to be an event
+        let frame_diff = run(config(), slow_square(85));
+        assert!(frame_diff.contains("rejected") && !frame_diff.contains("completed"));
+        // the model sees all of it until it's absorbed after stopping
+        let background = RunningMotionDetectorConfig {
+            change_minimum: 0.5,
+            change_maximum: 50.0,
+            minimum_total_change: 20.0,
+            algorithm: DetectionAlgorithm::Background,
+            background: BackgroundModelConfig {
+                learning_rate: 0.05,
+                ..BackgroundModelConfig::default()
+            },
+            ..config()
+        };
+        check("background_slow_square", run(background, slow_square(85)));
+    }
+
+    #[test]
+    fn fully_masked_frame_diff() {
+        let mask = GrayImage::from_pixel(WIDTH, HEIGHT, Luma([255]));
+        let result = MotionDetector {}.frame_diff(&scene(0, 0), &scene(1, 5), Some(&mask));
+        assert_eq!(result.average, 0.0);
+        assert_eq!(result.std_dev_estimate, 0.0);
+    }
 }
@@ -1,12 +1,40 @@
-use std::{path::Path, process::Stdio};
+use std::{path::Path, process::Stdio, sync::Arc};
 
-use crate::{config::CONFIG, modect::MotionDetectionEvent};
+use crate::{
+    config::CONFIG,
+    ffmpeg::{segment_start, SEGMENT_SECONDS},
+    modect::MotionDetectionEvent,
+};
 use anyhow::{bail, Context, Result};
+use chrono::{Duration, TimeZone, Utc};
+use image::ImageFormat;
+use log::{error, info};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
 };
 
+/// Time to wait after a segment should have ended for ffmpeg to finalize it
+const SEGMENT_FINALIZE_GRACE_SECONDS: i64 = 5;
+
+async fn wait_ffmpeg(mut process: Child) -> Result<()> {
+    let stderr = process.stderr.take().unwrap();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            eprintln!("{line}");
+        }
+    });
+
+    let status = process.wait().await?;
+    if !status.success() {
+        bail!("ffmpeg failed with code: {status}");
+    }
+
+    Ok(())
+}
+
+/// Encodes the (detection resolution) frames of an event into an MP4
 pub async fn modect_mp4(
     event: &MotionDetectionEvent,
     frame_rate: u32,
@@ -22,8 +50,8 @@ pub async fn modect_mp4(
         first_frame.image.width(),
         first_frame.image.height()
     );
-    let mut process = Command::new(&CONFIG.ffmpeg_bin)
-        .args(&[
+    let mut process = Command::new(&CONFIG.get().ffmpeg_bin)
+        .args([
             "-f",
             "rawvideo",
             "-pixel_format",
@@ -32,28 +60,139 @@ pub async fn modect_mp4(
             &dimension,
             "-framerate",
             &frame_rate,
+            "-i",
+            "-",
             "-c:v",
             "h264",
             "-flags",
             "+cgop",
-            "-",
+            "-y",
         ])
         .arg(destination)
+        .stdin(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()?;
 
-    let stderr = process.stderr.take().unwrap();
-    tokio::spawn(async move {
-        let mut lines = BufReader::new(stderr).lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            eprintln!("{line}");
+    let mut stdin = process.stdin.take().unwrap();
+    for frame in &event.frames {
+        stdin.write_all(frame.image.as_raw()).await?;
+    }
+    drop(stdin);
+
+    wait_ffmpeg(process).await
+}
+
+/// Writes the frame of an event with the most change as a JPEG
+pub async fn preview_jpeg(event: &Arc<MotionDetectionEvent>, destination: &Path) -> Result<()> {
+    let event = event.clone();
+    let destination = destination.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let frame = event
+            .best_frame()
+            .context("missing single frame for event")?;
+        frame
+            .image
+            .save_with_format(&destination, ImageFormat::Jpeg)?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Cuts the wall-clock window of an event out of the full resolution recording segments in `recording_dir`, without re-encoding.
+/// Waits for the segment containing the end of the event to be finalized.
+pub async fn recording_mp4(
+    event: &MotionDetectionEvent,
+    recording_dir: &Path,
+    destination: &Path,
+) -> Result<()> {
+    let (Some(start), Some(end)) = (event.start_time(), event.end_time()) else {
+        bail!("missing frames for event");
+    };
+
+    let segment_length = Duration::seconds(SEGMENT_SECONDS);
+    let last_segment_end = Utc
+        .timestamp_opt(
+            end.timestamp() - end.timestamp().rem_euclid(SEGMENT_SECONDS),
+            0,
+        )
+        .single()
+        .context("invalid event end time")?
+        + segment_length;
+    let finalized_at = last_segment_end + Duration::seconds(SEGMENT_FINALIZE_GRACE_SECONDS);
+    if let Ok(wait) = (finalized_at - Utc::now()).to_std() {
+        tokio::time::sleep(wait).await;
+    }
+
+    let mut segments = vec![];
+    let mut read_dir = tokio::fs::read_dir(recording_dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        if let Some(segment_start) = segment_start(&path) {
+            segments.push((segment_start, path));
         }
-    });
+    }
+    segments.sort_by_key(|x| x.0);
 
-    let status = process.wait().await?;
-    if !status.success() {
-        bail!("ffmpeg failed with code: {status}");
+    let mut overlapping = vec![];
+    for (i, (segment_start, path)) in segments.iter().enumerate() {
+        let segment_end = segments
+            .get(i + 1)
+            .map(|x| x.0)
+            .unwrap_or(*segment_start + segment_length);
+        if *segment_start < end && segment_end > start {
+            overlapping.push((*segment_start, path));
+        }
     }
+    let Some((first_start, _)) = overlapping.first() else {
+        bail!("no recording segments overlap event");
+    };
 
-    Ok(())
+    let offset = (start - *first_start).max(Duration::zero());
+    let duration = end - start;
+
+    let mut concat_list = String::new();
+    for (_, path) in &overlapping {
+        let path = tokio::fs::canonicalize(path).await?;
+        concat_list.push_str(&format!(
+            "file '{}'\n",
+            path.to_string_lossy().replace('\'', r"'\''")
+        ));
+    }
+    let concat_path = destination.with_extension("concat.txt");
+    tokio::fs::write(&concat_path, concat_list).await?;
+    info!(
+        "cutting event from {} recording segment(s) into '{}'",
+        overlapping.len(),
+        destination.display()
+    );
+
+    let process = Command::new(&CONFIG.get().ffmpeg_bin)
+        .args([
+            "-f",
+            "concat",
+            "-safe",
+            "0",
+            "-ss",
+            &format!("{:.03}", offset.num_milliseconds() as f64 / 1000.0),
+            "-i",
+        ])
+        .arg(&concat_path)
+        .args([
+            "-t",
+            &format!("{:.03}", duration.num_milliseconds() as f64 / 1000.0),
+            "-c",
+            "copy",
+            "-y",
+        ])
+        .arg(destination)
+        .stderr(Stdio::piped())
+        .spawn();
+    let out = match process {
+        Ok(process) => wait_ffmpeg(process).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = tokio::fs::remove_file(&concat_path).await {
+        error!("failed to delete '{}': {e}", concat_path.display());
+    }
+    out
 }
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use image::ImageFormat;
+use log::{error, info};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    alert::{Alert, AlertState, Notifier},
+    config::{CameraMode, Config, MqttConfig, CONFIG},
+    event::EventMetadata,
+    modect::{MotionDetectionEvent, MotionDetectionState},
+};
+
+lazy_static::lazy_static! {
+    static ref CLIENT: Mutex<Option<AsyncClient>> = Mutex::new(None);
+    /// Last motion state published per camera, to only publish changes
+    static ref MOTION: Mutex<HashMap<String, bool>> = Mutex::new(HashMap::new());
+}
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+const MOTION_ON: &str = "ON";
+const MOTION_OFF: &str = "OFF";
+/// Snapshots are much larger than rumqttc's default limit
+const MAX_OUTGOING_PACKET_SIZE: usize = 16 * 1024 * 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+fn qos(config: &MqttConfig) -> QoS {
+    match config.qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+fn availability_topic(config: &MqttConfig) -> String {
+    format!("{}/status", config.topic_prefix)
+}
+
+fn camera_topic(config: &MqttConfig, camera: &str, name: &str) -> String {
+    format!("{}/{camera}/{name}", config.topic_prefix)
+}
+
+fn client() -> Option<AsyncClient> {
+    CLIENT.lock().unwrap().clone()
+}
+
+/// Publishes without waiting, dropping the message if the client's queue is full
+fn try_publish(config: &MqttConfig, topic: String, retain: bool, payload: impl Into<Vec<u8>>) {
+    let Some(client) = client() else {
+        return;
+    };
+    if let Err(e) = client.try_publish(&topic, qos(config), retain, payload) {
+        error!("mqtt: failed to publish to {topic}: {e}");
+    }
+}
+
+/// Retained topics and payloads announcing a camera: its motion state and, with `discovery_prefix`, Home Assistant discovery configs
+fn camera_announcements(config: &MqttConfig, name: &str, motion: bool) -> Vec<(String, String)> {
+    let mut out = vec![(
+        camera_topic(config, name, "motion"),
+        if motion { MOTION_ON } else { MOTION_OFF }.to_string(),
+    )];
+    let Some(discovery_prefix) = &config.discovery_prefix else {
+        return out;
+    };
+    let device = json!({
+        "identifiers": [format!("rmr_{name}")],
+        "name": name,
+        "manufacturer": "rmr",
+    });
+    let motion_config = json!({
+        "name": "Motion",
+        "unique_id": format!("rmr_{name}_motion"),
+        "device_class": "motion",
+        "state_topic": camera_topic(config, name, "motion"),
+        "payload_on": MOTION_ON,
+        "payload_off": MOTION_OFF,
+        "availability_topic": availability_topic(config),
+        "device": device,
+    });
+    out.push((
+        format!("{discovery_prefix}/binary_sensor/rmr_{name}/motion/config"),
+        motion_config.to_string(),
+    ));
+    let snapshot_config = json!({
+        "name": "Snapshot",
+        "unique_id": format!("rmr_{name}_snapshot"),
+        "topic": camera_topic(config, name, "snapshot"),
+        "availability_topic": availability_topic(config),
+        "device": device,
+    });
+    out.push((
+        format!("{discovery_prefix}/camera/rmr_{name}/snapshot/config"),
+        snapshot_config.to_string(),
+    ));
+    out
+}
+
+/// Publishes availability, then the announcements of each camera with its current motion state
+async fn announce(
+    client: AsyncClient,
+    config: &MqttConfig,
+    cameras: impl IntoIterator<Item = &str>,
+) -> Result<()> {
+    client
+        .publish(availability_topic(config), qos(config), true, ONLINE)
+        .await?;
+
+    for name in cameras {
+        let motion = MOTION.lock().unwrap().get(name).copied().unwrap_or(false);
+        for (topic, payload) in camera_announcements(config, name, motion) {
+            client.publish(topic, qos(config), true, payload).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Cameras that have a motion state to publish
+fn motion_cameras(config: &Config) -> impl Iterator<Item = &str> {
+    config.cameras.iter().filter_map(|(name, camera)| {
+        matches!(
+            camera.mode,
+            CameraMode::MotionDetect | CameraMode::MotionDetectRecord
+        )
+        .then_some(name.as_str())
+    })
+}
+
+/// Connects to the broker in `CONFIG.mqtt` and runs forever, reconnecting as needed
+pub async fn run() {
+    let Some(config) = CONFIG.get().mqtt.clone() else {
+        return;
+    };
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    let max_incoming_packet_size = options.max_packet_size();
+    options
+        .set_keep_alive(Duration::from_secs(30))
+        .set_max_packet_size(max_incoming_packet_size, MAX_OUTGOING_PACKET_SIZE)
+        .set_last_will(LastWill::new(
+            availability_topic(&config),
+            OFFLINE,
+            qos(&config),
+            true,
+        ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    *CLIENT.lock().unwrap() = Some(client.clone());
+
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("mqtt: connected to {}:{}", config.host, config.port);
+                // the event loop must keep polling for the publishes to go out
+                let client = client.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let cameras = CONFIG.get();
+                    if let Err(e) = announce(client, &config, motion_cameras(&cameras)).await {
+                        error!("mqtt: failed to announce: {e}");
+                    }
+                });
+            }
+            Ok(_) => (),
+            Err(e) => {
+                error!("mqtt: connection error: {e}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// On while a detection is active, off once it is over, None for states that don't change it
+fn motion(state: &MotionDetectionState) -> Option<bool> {
+    match state {
+        MotionDetectionState::Active { .. } | MotionDetectionState::Followup { .. } => Some(true),
+        MotionDetectionState::Idle { .. }
+        | MotionDetectionState::Rejected { .. }
+        | MotionDetectionState::Completed { .. } => Some(false),
+        MotionDetectionState::WaitAndSee { .. }
+        | MotionDetectionState::ConfirmedInProgress { .. } => None,
+    }
+}
+
+/// Publishes a camera's motion as on while a detection is active, off once it is over
+pub fn motion_state(camera: &str, state: &MotionDetectionState) {
+    let config = CONFIG.get();
+    let Some(config) = &config.mqtt else {
+        return;
+    };
+    let Some(motion) = motion(state) else {
+        return;
+    };
+    if MOTION.lock().unwrap().insert(camera.to_string(), motion) == Some(motion) {
+        return;
+    }
+    try_publish(
+        config,
+        camera_topic(config, camera, "motion"),
+        true,
+        if motion { MOTION_ON } else { MOTION_OFF },
+    );
+}
+
+/// Published to `<camera>/event` for every confirmed and completed event
+#[derive(Serialize)]
+struct MqttEvent {
+    state: AlertState,
+    #[serde(flatten)]
+    metadata: EventMetadata,
+}
+
+async fn publish_event(
+    config: &MqttConfig,
+    state: AlertState,
+    metadata: EventMetadata,
+    event: Arc<MotionDetectionEvent>,
+) -> Result<()> {
+    let client = client().context("not connected")?;
+    let camera = metadata.camera.clone();
+    client
+        .publish(
+            camera_topic(config, &camera, "event"),
+            qos(config),
+            false,
+            serde_json::to_vec(&MqttEvent { state, metadata })?,
+        )
+        .await?;
+
+    let snapshot = tokio::task::spawn_blocking(move || {
+        let frame = event
+            .best_frame()
+            .context("missing single frame for event")?;
+        let mut out = vec![];
+        frame
+            .image
+            .write_to(&mut Cursor::new(&mut out), ImageFormat::Jpeg)?;
+        anyhow::Ok(out)
+    })
+    .await??;
+    client
+        .publish(
+            camera_topic(config, &camera, "snapshot"),
+            qos(config),
+            true,
+            snapshot,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Publishes a confirmed or completed event and its best frame as the camera's snapshot.
+/// Unlike alerts, these don't depend on alert rules.
+pub async fn event(state: AlertState, metadata: EventMetadata, event: Arc<MotionDetectionEvent>) {
+    let config = CONFIG.get();
+    let Some(config) = &config.mqtt else {
+        return;
+    };
+    let camera = metadata.camera.clone();
+    if let Err(e) = publish_event(config, state, metadata, event).await {
+        error!("mqtt: {camera}: failed to publish event: {e:#}");
+    }
+}
+
+/// Published to `<camera>/alert` by the notifier
+#[derive(Serialize)]
+struct MqttAlert {
+    state: AlertState,
+    #[serde(flatten)]
+    metadata: EventMetadata,
+    event_url: Option<Url>,
+    preview_url: Option<Url>,
+    aggregated_events: Option<usize>,
+}
+
+/// Alert channel for `MqttConfig::alerts`
+pub struct Mqtt {
+    config: MqttConfig,
+}
+
+impl Mqtt {
+    pub fn new(config: MqttConfig) -> Self {
+        Self { config }
+    }
+
+    async fn alert_event(&self, alert: &Alert) -> Result<()> {
+        let client = client().context("not connected")?;
+        let payload = MqttAlert {
+            state: alert.state,
+            metadata: alert.metadata(),
+            event_url: alert.event_url(),
+            preview_url: alert.preview_url(),
+            aggregated_events: alert.aggregate.map(|x| x.events),
+        };
+        client
+            .publish(
+                camera_topic(&self.config, &alert.camera, "alert"),
+                qos(&self.config),
+                false,
+                serde_json::to_vec(&payload)?,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+impl Notifier for Mqtt {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.alert_event(alert))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> MqttConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn topics() {
+        let config = config("host: localhost\ntopic_prefix: home/rmr");
+        assert_eq!(availability_topic(&config), "home/rmr/status");
+        assert_eq!(
+            camera_topic(&config, "porch", "motion"),
+            "home/rmr/porch/motion"
+        );
+        assert_eq!(qos(&config), QoS::AtMostOnce);
+    }
+
+    #[test]
+    fn motion() {
+        let active = MotionDetectionState::Active {
+            start_frame_number: 0,
+            current_frame_number: 10,
+            current_score: 1.0,
+        };
+        let wait = MotionDetectionState::WaitAndSee {
+            start_frame_number: 0,
+            current_frame_number: 10,
+            current_score: 1.0,
+        };
+        assert_eq!(super::motion(&active), Some(true));
+        assert_eq!(
+            super::motion(&MotionDetectionState::Idle { frame_number: 0 }),
+            Some(false)
+        );
+        assert_eq!(super::motion(&wait), None);
+    }
+
+    #[test]
+    fn event_payload() {
+        let metadata: EventMetadata = serde_json::from_str(
+            r#"{"camera":"porch","when":"2026-10-17T12:00:00Z","total_score":1.5,"start_stream_frame_number":10,"end_stream_frame_number":20}"#,
+        )
+        .unwrap();
+        let payload = serde_json::to_value(MqttEvent {
+            state: AlertState::CompletedAfterConfirm,
+            metadata,
+        })
+        .unwrap();
+        assert_eq!(payload["state"], "completed_after_confirm");
+        assert_eq!(payload["camera"], "porch");
+        assert_eq!(payload["end_stream_frame_number"], 20);
+    }
+
+    #[test]
+    fn announcements_without_discovery() {
+        let config = config("host: localhost");
+        assert_eq!(
+            camera_announcements(&config, "porch", true),
+            vec![("rmr/porch/motion".to_string(), MOTION_ON.to_string())]
+        );
+    }
+
+    #[test]
+    fn announcements_with_discovery() {
+        let config = config("host: localhost\ndiscovery_prefix: homeassistant");
+        let announcements = camera_announcements(&config, "porch", false);
+        let topics = announcements.iter().map(|x| &*x.0).collect::<Vec<_>>();
+        assert_eq!(
+            topics,
+            [
+                "rmr/porch/motion",
+                "homeassistant/binary_sensor/rmr_porch/motion/config",
+                "homeassistant/camera/rmr_porch/snapshot/config",
+            ]
+        );
+        assert_eq!(announcements[0].1, MOTION_OFF);
+
+        let motion: serde_json::Value = serde_json::from_str(&announcements[1].1).unwrap();
+        assert_eq!(motion["state_topic"], "rmr/porch/motion");
+        assert_eq!(motion["availability_topic"], "rmr/status");
+        assert_eq!(motion["unique_id"], "rmr_porch_motion");
+        assert_eq!(motion["device"]["identifiers"][0], "rmr_porch");
+        let snapshot: serde_json::Value = serde_json::from_str(&announcements[2].1).unwrap();
+        assert_eq!(snapshot["topic"], "rmr/porch/snapshot");
+        assert_eq!(snapshot["device"], motion["device"]);
+    }
+
+    /// Needs a broker on localhost:1883, e.g. `mosquitto`
+    #[tokio::test]
+    #[ignore]
+    async fn announce_to_broker() {
+        let config =
+            config("host: localhost\ntopic_prefix: rmr_test\ndiscovery_prefix: rmr_test_ha");
+        let (client, mut eventloop) =
+            AsyncClient::new(MqttOptions::new("rmr_test", &config.host, config.port), 16);
+        client
+            .subscribe("rmr_test/#", QoS::AtMostOnce)
+            .await
+            .unwrap();
+        client
+            .subscribe("rmr_test_ha/#", QoS::AtMostOnce)
+            .await
+            .unwrap();
+        announce(client, &config, ["porch"]).await.unwrap();
+
+        let mut expected = camera_announcements(&config, "porch", false);
+        expected.push((availability_topic(&config), ONLINE.to_string()));
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut received = vec![];
+            while received.len() < expected.len() {
+                if let Event::Incoming(Packet::Publish(publish)) = eventloop.poll().await.unwrap() {
+                    received.push((
+                        publish.topic,
+                        String::from_utf8(publish.payload.to_vec()).unwrap(),
+                    ));
+                }
+            }
+            received
+        })
+        .await
+        .expect("timed out waiting for the announcements");
+        for announcement in expected {
+            assert!(received.contains(&announcement), "missing {announcement:?}");
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame, ImageFormat, RgbaImage,
+};
+use log::{error, info};
+use webp_animation::{Encoder, EncoderOptions, EncodingConfig, EncodingType, LossyEncodingConfig};
+
+use crate::{config::PreviewFormat, modect::MotionDetectionEvent, observable_buf::ObservableBuf};
+
+pub const MAX_PREVIEW_SIZE: usize = (1024 * 1024 * 5) / 2;
+#[allow(dead_code)]
+const MAX_WEBP_BYTES_PER_FRAME: usize = 8192;
+const TARGET_WEBP_BYTES_PER_FRAME: usize = 7000;
+const MAX_WEBP_FRAMES: usize = MAX_PREVIEW_SIZE / MAX_WEBP_BYTES_PER_FRAME;
+
+/// An encoded preview of an event, for attaching to alerts
+pub struct Preview {
+    pub data: Vec<u8>,
+    pub content_type: &'static str,
+    pub filename: &'static str,
+}
+
+fn encode_jpeg(event: &MotionDetectionEvent) -> Option<Preview> {
+    let best_frame = event.best_frame()?;
+    let mut data = vec![];
+    let mut cursor = Cursor::new(&mut data);
+    best_frame
+        .image
+        .write_to(&mut cursor, ImageFormat::Jpeg)
+        .expect("failed to encode jpeg");
+    Some(Preview {
+        data,
+        content_type: "image/jpeg",
+        filename: "event.jpeg",
+    })
+}
+
+fn encode_gif(event: &MotionDetectionEvent, frame_rate: f64) -> Preview {
+    let mut data = vec![];
+    let (buf, len_ref) = ObservableBuf::new(&mut data);
+    let mut encoder = GifEncoder::new(buf);
+    encoder.set_repeat(Repeat::Infinite).unwrap();
+    let mut acceptable_ending = 0usize;
+    for frame in &event.frames {
+        let image: RgbaImage = DynamicImage::ImageRgb8(frame.image.clone()).to_rgba8();
+        encoder
+            .encode_frame(Frame::from_parts(
+                image,
+                0,
+                0,
+                Delay::from_saturating_duration(Duration::from_secs_f64(1.0 / frame_rate)),
+            ))
+            .unwrap();
+        let len = len_ref.load(std::sync::atomic::Ordering::SeqCst);
+        if len > MAX_PREVIEW_SIZE {
+            break;
+        }
+        acceptable_ending = len;
+    }
+    drop(encoder);
+    data.truncate(acceptable_ending);
+    Preview {
+        data,
+        content_type: "image/gif",
+        filename: "event.gif",
+    }
+}
+
+async fn encode_webp(event: &Arc<MotionDetectionEvent>, frame_rate: f64) -> Option<Preview> {
+    let event = event.clone();
+    let data = tokio::task::spawn_blocking(move || {
+        let mut encoder = Encoder::new_with_options(
+            event.frames.first().unwrap().image.dimensions(),
+            EncoderOptions {
+                minimize_size: true,
+                encoding_config: Some(EncodingConfig {
+                    encoding_type: EncodingType::Lossy(LossyEncodingConfig {
+                        target_size: TARGET_WEBP_BYTES_PER_FRAME / 2,
+                        ..LossyEncodingConfig::new_from_picture_preset()
+                    }),
+                    quality: 25.0,
+                    method: 3,
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let ms_per_frame = 1000 / frame_rate as i32;
+        let mut frame_index = 0f64;
+        let mut last_frame_index = -1isize;
+        let mut encoded_frames = 0usize;
+        for encoded_frame_index in 0..MAX_WEBP_FRAMES {
+            let mut target_index = frame_index.round() as usize;
+            if target_index <= last_frame_index as usize {
+                target_index = last_frame_index as usize + 1;
+            }
+
+            let Some(frame) = event.frames.get(target_index) else {
+                break;
+            };
+
+            let image: RgbaImage = DynamicImage::ImageRgb8(frame.image.clone()).to_rgba8();
+            encoder
+                .add_frame(image.as_raw(), ms_per_frame * encoded_frame_index as i32)
+                .unwrap();
+
+            last_frame_index = target_index as isize;
+            frame_index += event.frames.len() as f64 / MAX_WEBP_FRAMES as f64;
+            encoded_frames += 1;
+        }
+        encoder
+            .finalize(encoded_frames as i32 * ms_per_frame)
+            .unwrap()
+            .to_vec()
+    })
+    .await
+    .unwrap();
+    //todo: ???
+    if data.len() > MAX_PREVIEW_SIZE {
+        error!(
+            "webp encoded too large! was {} bytes, expected <= {MAX_PREVIEW_SIZE}",
+            data.len()
+        );
+        return None;
+    }
+    Some(Preview {
+        data,
+        content_type: "image/webp",
+        filename: "event.webp",
+    })
+}
+
+/// Encodes a preview of an event in the given format, None if disabled or encoding failed
+pub async fn encode(
+    format: PreviewFormat,
+    event: &Arc<MotionDetectionEvent>,
+    frame_rate: f64,
+) -> Option<Preview> {
+    match format {
+        PreviewFormat::None => None,
+        PreviewFormat::Jpeg => encode_jpeg(event),
+        PreviewFormat::Gif => Some(encode_gif(event, frame_rate)),
+        PreviewFormat::Webp => match encode_webp(event, frame_rate).await {
+            Some(preview) => Some(preview),
+            None => {
+                info!("falling back from webp to gif due to encoding issue");
+                Some(encode_gif(event, frame_rate))
+            }
+        },
+    }
+}
@@ -0,0 +1,232 @@
+use chrono::{DateTime, Utc};
+use image::RgbImage;
+
+use crate::{
+    config::{MotionDetectionConfig, ProfileCondition},
+    sun::{self, Location},
+};
+
+/// Frames a different profile must keep matching before switching to it, so dusk or passing headlights don't flap between profiles
+const SWITCH_FRAMES: u32 = 50;
+
+/// Average channel difference under which a frame counts as grayscale, compression keeps IR frames from being exactly gray
+const GRAYSCALE_MAX_SATURATION: f64 = 3.0;
+
+/// Only every this many pixels are sampled for grayscale detection
+const GRAYSCALE_SAMPLE_STEP: usize = 7;
+
+pub fn is_grayscale(frame: &RgbImage) -> bool {
+    let mut total = 0u64;
+    let mut samples = 0u64;
+    for pixel in frame.pixels().step_by(GRAYSCALE_SAMPLE_STEP) {
+        let [r, g, b] = pixel.0;
+        total += (r.abs_diff(g) as u64)
+            .max(g.abs_diff(b) as u64)
+            .max(r.abs_diff(b) as u64);
+        samples += 1;
+    }
+    samples == 0 || (total as f64 / samples as f64) < GRAYSCALE_MAX_SATURATION
+}
+
+/// Tracks which of a camera's detector profiles is active
+#[derive(Default)]
+pub struct ProfileSelector {
+    started: bool,
+    /// None while the camera's own settings are used
+    active: Option<String>,
+    candidate: Option<String>,
+    candidate_frames: u32,
+}
+
+impl ProfileSelector {
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// The first profile whose condition holds
+    fn matching(
+        config: &MotionDetectionConfig,
+        location: Option<Location>,
+        frame: &RgbImage,
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        let mut grayscale = None;
+        config
+            .profiles
+            .iter()
+            .find(|(_, profile)| match profile.when {
+                ProfileCondition::Day => location.is_some_and(|x| sun::is_day(x, now)),
+                ProfileCondition::Night => location.is_some_and(|x| !sun::is_day(x, now)),
+                ProfileCondition::Grayscale => {
+                    *grayscale.get_or_insert_with(|| is_grayscale(frame))
+                }
+            })
+            .map(|(name, _)| name.clone())
+    }
+
+    /// Picks the profile for a frame, returning the new profile if it changed.
+    /// The first frame picks immediately, later changes have to hold for `SWITCH_FRAMES`.
+    /// `day` and `night` profiles never match without a `location`.
+    pub fn update(
+        &mut self,
+        config: &MotionDetectionConfig,
+        location: Option<Location>,
+        frame: &RgbImage,
+        now: DateTime<Utc>,
+    ) -> Option<Option<String>> {
+        let matching = Self::matching(config, location, frame, now);
+        if !self.started {
+            self.started = true;
+            self.active = matching.clone();
+            return Some(matching);
+        }
+        if matching == self.active {
+            self.candidate_frames = 0;
+            return None;
+        }
+        if matching != self.candidate {
+            self.candidate = matching;
+            self.candidate_frames = 0;
+        }
+        self.candidate_frames += 1;
+        if self.candidate_frames < SWITCH_FRAMES {
+            return None;
+        }
+        self.candidate_frames = 0;
+        self.active = self.candidate.clone();
+        Some(self.active.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use image::Rgb;
+
+    use super::*;
+
+    fn config() -> MotionDetectionConfig {
+        serde_yaml::from_str(
+            "
+width: 8
+height: 8
+change_minimum: 200.0
+change_maximum: 100000.0
+stddev_minimum: 100.0
+minimum_frame_count: 25
+minimum_total_change: 10000.0
+followup_frame_count: 25
+maximum_frame_wait: 0
+mask_file: null
+profiles:
+  dark:
+    when: night
+    change_minimum: 400.0
+  ir:
+    when: grayscale
+    change_minimum: 300.0
+",
+        )
+        .unwrap()
+    }
+
+    fn gray() -> RgbImage {
+        RgbImage::from_fn(8, 8, |x, y| {
+            let v = (x * 30 + y) as u8;
+            // compression noise
+            Rgb([v, v + (x % 2) as u8, v])
+        })
+    }
+
+    fn colour() -> RgbImage {
+        RgbImage::from_fn(8, 8, |x, _| Rgb([(x * 30) as u8, 120, 40]))
+    }
+
+    fn noon() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn grayscale() {
+        assert!(is_grayscale(&gray()));
+        assert!(!is_grayscale(&colour()));
+        assert!(is_grayscale(&RgbImage::new(0, 0)));
+    }
+
+    #[test]
+    fn first_frame_switches_immediately() {
+        let config = config();
+        let mut selector = ProfileSelector::default();
+        assert_eq!(
+            selector.update(&config, None, &gray(), noon()),
+            Some(Some("ir".to_string()))
+        );
+        assert_eq!(selector.active(), Some("ir"));
+
+        let mut selector = ProfileSelector::default();
+        assert_eq!(
+            selector.update(&config, None, &colour(), noon()),
+            Some(None)
+        );
+        assert_eq!(selector.active(), None);
+    }
+
+    #[test]
+    fn later_switches_need_consecutive_frames() {
+        let config = config();
+        let mut selector = ProfileSelector::default();
+        selector.update(&config, None, &colour(), noon());
+        // interrupted by a frame matching the active profile, which starts the count over
+        for _ in 0..SWITCH_FRAMES - 1 {
+            assert_eq!(selector.update(&config, None, &gray(), noon()), None);
+        }
+        assert_eq!(selector.update(&config, None, &colour(), noon()), None);
+        for _ in 0..SWITCH_FRAMES - 1 {
+            assert_eq!(selector.update(&config, None, &gray(), noon()), None);
+        }
+        assert_eq!(
+            selector.update(&config, None, &gray(), noon()),
+            Some(Some("ir".to_string()))
+        );
+        assert_eq!(selector.active(), Some("ir"));
+
+        for _ in 0..SWITCH_FRAMES - 1 {
+            assert_eq!(selector.update(&config, None, &colour(), noon()), None);
+        }
+        assert_eq!(
+            selector.update(&config, None, &colour(), noon()),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn night_needs_a_location() {
+        let config = config();
+        let london = Location {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        let midnight = Utc.with_ymd_and_hms(2024, 6, 21, 0, 0, 0).unwrap();
+        let mut selector = ProfileSelector::default();
+        assert_eq!(
+            selector.update(&config, Some(london), &colour(), midnight),
+            Some(Some("dark".to_string()))
+        );
+        // profiles are checked in order, `dark` comes before `ir`
+        let mut selector = ProfileSelector::default();
+        assert_eq!(
+            selector.update(&config, Some(london), &gray(), midnight),
+            Some(Some("dark".to_string()))
+        );
+        let mut selector = ProfileSelector::default();
+        assert_eq!(
+            selector.update(&config, None, &colour(), midnight),
+            Some(None)
+        );
+        let mut selector = ProfileSelector::default();
+        assert_eq!(
+            selector.update(&config, Some(london), &colour(), noon()),
+            Some(None)
+        );
+    }
+}
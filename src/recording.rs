@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{config::CONFIG, ffmpeg::segment_start};
+
+/// A recording segment in `CONFIG.recording_dir`
+#[derive(Serialize, Clone)]
+pub struct Recording {
+    pub filename: String,
+    pub size: u64,
+    /// Parsed from the segment filename
+    pub start: Option<DateTime<Utc>>,
+    /// Last modification of the segment
+    pub end: DateTime<Utc>,
+}
+
+/// Lists the filenames of a camera's recording segments, without reading their metadata
+pub async fn list_recording_files(camera: &str) -> std::io::Result<Vec<String>> {
+    let recording_dir = CONFIG.get().recording_dir.join(camera);
+    let mut out = vec![];
+    if tokio::fs::try_exists(&recording_dir).await? {
+        let mut read_dir = tokio::fs::read_dir(&recording_dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            if filename.ends_with(".mp4") {
+                out.push(filename);
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub async fn stat_recording(path: &Path) -> std::io::Result<Recording> {
+    let metadata = tokio::fs::metadata(path).await?;
+    Ok(Recording {
+        filename: path
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        size: metadata.len(),
+        start: segment_start(path),
+        end: metadata.modified()?.into(),
+    })
+}
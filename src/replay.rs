@@ -0,0 +1,275 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use url::Url;
+
+use crate::{
+    config::{MotionDetectionConfig, CONFIG, DEFAULT_PROFILE},
+    event::EventMetadata,
+    ffmpeg::{segment_start, FFmpegConfig},
+    modect::{MotionDetectionEvent, MotionDetectionState, RunningMotionDetector},
+    modect_mp4,
+    profile::ProfileSelector,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Recording segment or event clip to replay
+    file: PathBuf,
+    /// Camera whose motion detection settings and frame rate are used
+    #[clap(short, long)]
+    camera: String,
+    /// YAML file of motion detection settings to try instead of the camera's, in the format of `motion_detection`
+    #[clap(short, long)]
+    detector: Option<PathBuf>,
+    /// Detector profile to use throughout, instead of switching profiles like the live detector
+    #[clap(short, long)]
+    profile: Option<String>,
+    /// Directory to write the accepted and rejected events to as MP4s
+    #[clap(long)]
+    clips: Option<PathBuf>,
+}
+
+/// What the detector made of one motion event
+struct Outcome {
+    accepted: bool,
+    event: MotionDetectionEvent,
+}
+
+fn describe(event: &MotionDetectionEvent, frame_rate: f64) -> String {
+    let mut out = format!(
+        "frames {}-{} ({:.1}s-{:.1}s), score {:.02}",
+        event.start_stream_frame_number,
+        event.end_stream_frame_number,
+        event.start_stream_frame_number as f64 / frame_rate,
+        event.end_stream_frame_number as f64 / frame_rate,
+        event.total_score
+    );
+    if !event.zones.is_empty() {
+        out.push_str(&format!(", zones {}", event.zones.join(", ")));
+    }
+    if !event.rules.is_empty() {
+        out.push_str(&format!(", fired {}", event.rules.join(", ")));
+    }
+    out
+}
+
+/// When the recording starts, which `day` and `night` profiles go by. Recording segments are named after it. Event clips
+/// are named after when they were saved, so their start is worked out from the frame numbers in their metadata, or
+/// taken to be that time (at most a clip's length late) if the metadata is gone.
+fn start_time(path: &Path, frame_rate: f64) -> Result<DateTime<Utc>> {
+    if let Some(start) = segment_start(path) {
+        return Ok(start);
+    }
+    let metadata_path = path.with_extension("json");
+    match std::fs::read_to_string(&metadata_path) {
+        Ok(raw) => {
+            let metadata: EventMetadata = serde_json::from_str(&raw)
+                .with_context(|| format!("failed to parse '{}'", metadata_path.display()))?;
+            let frames = metadata.end_stream_frame_number - metadata.start_stream_frame_number + 1;
+            let length = Duration::microseconds((frames as f64 / frame_rate * 1_000_000.0) as i64);
+            return Ok(metadata.when - length);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read '{}'", metadata_path.display()))
+        }
+    }
+    // `<camera>_<time>.mp4`, camera names may contain underscores but the time doesn't
+    let saved = path
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .and_then(|x| x.rsplit_once('_'))
+        .and_then(|(_, time)| time.strip_suffix(" UTC"))
+        .and_then(|x| NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f").ok());
+    match saved {
+        Some(saved) => Ok(Utc.from_utc_datetime(&saved)),
+        None => bail!(
+            "can't tell when '{}' was recorded: it's not named like a recording segment or event clip, and has no event metadata next to it",
+            path.display()
+        ),
+    }
+}
+
+/// Runs a recording through a motion detector as fast as ffmpeg decodes it.
+/// Per-frame values are printed to stdout as CSV, state transitions and the summary to stderr.
+pub async fn run(args: &ReplayArgs) -> Result<()> {
+    let config = CONFIG.get();
+    let camera = config
+        .cameras
+        .get(&args.camera)
+        .with_context(|| format!("unknown camera '{}'", args.camera))?;
+    let motion_detection: MotionDetectionConfig = match &args.detector {
+        Some(path) => {
+            let raw = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read '{}'", path.display()))?;
+            let detector: MotionDetectionConfig = serde_yaml::from_str(&raw)
+                .with_context(|| format!("failed to parse '{}'", path.display()))?;
+            let problems = detector.validate_detectors("detector", config.location.is_some());
+            if !problems.is_empty() {
+                bail!(
+                    "invalid detector settings:\n{}",
+                    problems
+                        .iter()
+                        .map(|x| format!("  {x}"))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                );
+            }
+            detector
+        }
+        None => camera
+            .motion_detection
+            .clone()
+            .with_context(|| format!("camera '{}' has no motion_detection", args.camera))?,
+    };
+    if let Some(profile) = &args.profile {
+        if !motion_detection.profiles.contains_key(profile) {
+            bail!("unknown detector profile '{profile}'");
+        }
+    }
+    if let Some(clips) = &args.clips {
+        tokio::fs::create_dir_all(clips)
+            .await
+            .with_context(|| format!("failed to create '{}'", clips.display()))?;
+    }
+
+    let path = std::fs::canonicalize(&args.file)
+        .with_context(|| format!("failed to open '{}'", args.file.display()))?;
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+    let ffmpeg = FFmpegConfig {
+        binary: config.ffmpeg_bin.clone(),
+        rtsp_input: Url::from_file_path(&path)
+            .map_err(|_| anyhow!("invalid path '{}'", path.display()))?,
+        record_single_jpeg: false,
+        recording_mp4_dir: None,
+        send_images: Some(sender),
+        image_width: Some(motion_detection.width),
+        image_height: Some(motion_detection.height),
+        force_tcp: false,
+    };
+    let decoder = tokio::spawn(async move { ffmpeg.run().await });
+
+    let frame_rate = camera.frame_rate;
+    let start = start_time(&path, frame_rate)?;
+    let mut motion_detector = RunningMotionDetector::new(motion_detection.config.clone());
+    let mut profiles = ProfileSelector::default();
+    if let Some(profile) = &args.profile {
+        motion_detector.set_profile(
+            Some(profile.clone()),
+            motion_detection.detector_config(Some(profile)),
+        );
+    }
+    let mut frames = 0u64;
+    let mut state = "";
+    let mut outcomes = vec![];
+
+    println!("frame,seconds,change,stddev,blobs,state,profile");
+    while let Some(frame) = receiver.recv().await {
+        let seconds = frames as f64 / frame_rate;
+        let time = start + Duration::microseconds((seconds * 1_000_000.0) as i64);
+        frames += 1;
+        if args.profile.is_none() {
+            if let Some(profile) = profiles.update(&motion_detection, config.location, &frame, time)
+            {
+                eprintln!(
+                    "{seconds:.1}s: using detector profile {}",
+                    profile.as_deref().unwrap_or(DEFAULT_PROFILE)
+                );
+                let config = motion_detection.detector_config(profile.as_deref());
+                motion_detector.set_profile(profile, config);
+            }
+        }
+        let stats = motion_detector.frame_recv_at(frame, time);
+        for (_, new_state) in motion_detector.drain_pending_states() {
+            let name = new_state.name();
+            let event = match new_state {
+                MotionDetectionState::Rejected { event } => Some((false, event)),
+                MotionDetectionState::Completed { event, .. } => Some((true, event)),
+                _ => None,
+            };
+            if let Some((accepted, event)) = event {
+                eprintln!(
+                    "{seconds:.1}s: {} {}",
+                    if accepted { "accepted" } else { "rejected" },
+                    describe(&event, frame_rate)
+                );
+                outcomes.push(Outcome { accepted, event });
+            } else if name != state {
+                eprintln!("{seconds:.1}s: {name}");
+            }
+            state = name;
+        }
+        println!(
+            "{},{seconds:.3},{:.4},{:.4},{},{state},{}",
+            stats.frame_number,
+            stats.change,
+            stats.stddev,
+            stats.blobs.len(),
+            motion_detector.profile().unwrap_or(DEFAULT_PROFILE)
+        );
+    }
+    if let Err(e) = decoder.await? {
+        bail!("failed to decode '{}': {e}", path.display());
+    }
+
+    let accepted = outcomes.iter().filter(|x| x.accepted).count();
+    eprintln!(
+        "\n{frames} frames, {} events: {accepted} accepted, {} rejected",
+        outcomes.len(),
+        outcomes.len() - accepted
+    );
+    for (i, outcome) in outcomes.iter().enumerate() {
+        let verdict = if outcome.accepted {
+            "accepted"
+        } else {
+            "rejected"
+        };
+        eprintln!(
+            "  #{} {verdict}: {}",
+            i + 1,
+            describe(&outcome.event, frame_rate)
+        );
+        if let Some(clips) = &args.clips {
+            let clip = clips.join(format!("{}_{verdict}.mp4", i + 1));
+            modect_mp4::modect_mp4(&outcome.event, frame_rate as u32, &clip)
+                .await
+                .with_context(|| format!("failed to write '{}'", clip.display()))?;
+        }
+    }
+    if !matches!(state, "" | "idle" | "rejected" | "completed") {
+        eprintln!("  motion was still ongoing at the end of the recording");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_clip_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir
+            .path()
+            .join("front_door_2026-10-16 22:18:44.500 UTC.mp4");
+        assert_eq!(
+            start_time(&clip, 10.0).unwrap(),
+            Utc.with_ymd_and_hms(2026, 10, 16, 22, 18, 44).unwrap() + Duration::milliseconds(500)
+        );
+
+        // 50 frames at 10 fps
+        std::fs::write(
+            clip.with_extension("json"),
+            r#"{"camera":"front_door","when":"2026-10-16T22:18:44.500Z","total_score":1000.0,"start_stream_frame_number":100,"end_stream_frame_number":149}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            start_time(&clip, 10.0).unwrap(),
+            Utc.with_ymd_and_hms(2026, 10, 16, 22, 18, 39).unwrap() + Duration::milliseconds(500)
+        );
+
+        assert!(start_time(&dir.path().join("clip.mp4"), 10.0).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use log::{error, info};
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+
+use crate::{
+    config::{Config, CONFIG},
+    event::event_camera,
+    index,
+};
+
+lazy_static::lazy_static! {
+    static ref RECORDING_BYTES: IntGaugeVec = register_int_gauge_vec!("rmr_recording_bytes", "bytes used by recording segments", &["camera"]).unwrap();
+    static ref EVENT_BYTES: IntGaugeVec = register_int_gauge_vec!("rmr_event_bytes", "bytes used by event clips and metadata", &["camera"]).unwrap();
+    static ref RETENTION_DELETED: IntCounterVec = register_int_counter_vec!("rmr_retention_deleted", "count of recording segments deleted by retention", &["camera"]).unwrap();
+    static ref RETENTION_DELETED_EVENTS: IntCounterVec = register_int_counter_vec!("rmr_retention_deleted_events", "count of events deleted by retention", &["camera"]).unwrap();
+}
+
+/// File extensions that make up a single event, deleted together
+const EVENT_EXTENSIONS: &[&str] = &["mp4", "json", "jpg"];
+
+struct StoredFile {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Runs forever, periodically enforcing `CONFIG.retention` and updating the disk usage gauges
+pub async fn run() {
+    loop {
+        if let Err(e) = sweep(&CONFIG.get(), SystemTime::now()).await {
+            error!("retention sweep failed: {e:#}");
+        }
+        tokio::time::sleep(Duration::from_secs(
+            CONFIG.get().retention.interval_secs.max(1),
+        ))
+        .await;
+    }
+}
+
+/// Lists files in `dir` with the given extension, oldest first
+async fn list_files(dir: &Path, extension: &str) -> Result<Vec<StoredFile>> {
+    let mut out = vec![];
+    if !tokio::fs::try_exists(dir).await? {
+        return Ok(out);
+    }
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(extension) {
+            continue;
+        }
+        let metadata = entry.metadata().await?;
+        out.push(StoredFile {
+            path,
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        });
+    }
+    out.sort_by_key(|x| x.modified);
+    Ok(out)
+}
+
+/// Deletes a file, returning true on success
+async fn delete(path: &Path) -> bool {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => {
+            error!("failed to delete '{}': {e}", path.display());
+            false
+        }
+    }
+}
+
+async fn delete_segment(camera: &str, segment: &StoredFile, reason: &str) -> bool {
+    if !delete(&segment.path).await {
+        return false;
+    }
+    info!(
+        "{camera}: deleted recording '{}' ({reason})",
+        segment.path.display()
+    );
+    RETENTION_DELETED.with_label_values(&[camera]).inc();
+    if let Some(filename) = segment.path.file_name() {
+        let filename = filename.to_string_lossy().into_owned();
+        if let Err(e) = index::remove_recording(camera.to_string(), filename).await {
+            error!("failed to remove recording from index: {e:#}");
+        }
+    }
+    true
+}
+
+/// Deletes recordings and events of `config` that expired or don't fit the limits as of `now`
+async fn sweep(config: &Config, now: SystemTime) -> Result<()> {
+    let defaults = &config.retention.defaults;
+
+    let mut camera_bytes = IndexMap::<&str, u64>::new();
+    let mut deletable = vec![];
+    for (name, camera) in &config.cameras {
+        // one camera's unreadable directory shouldn't stop the others from being cleaned up
+        let mut segments = match list_files(&config.recording_dir.join(name), "mp4").await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("{name}: failed to list recordings: {e:#}");
+                continue;
+            }
+        };
+        // the newest segment is still being written by ffmpeg, so it is never deleted
+        let mut total = segments.pop().map(|x| x.size).unwrap_or_default();
+        total += segments.iter().map(|x| x.size).sum::<u64>();
+
+        let max_age = camera.retention.max_age_secs.or(defaults.max_age_secs);
+        let max_bytes = camera.retention.max_bytes.or(defaults.max_bytes);
+        // ages reaching back before the epoch expire nothing
+        let cutoff = max_age.and_then(|x| now.checked_sub(Duration::from_secs(x)));
+
+        let mut kept = vec![];
+        for segment in segments {
+            let expired = cutoff.map(|x| segment.modified < x).unwrap_or(false);
+            let over_quota = max_bytes.map(|x| total > x).unwrap_or(false);
+            let reason = if expired {
+                "max age"
+            } else if over_quota {
+                "max bytes"
+            } else {
+                kept.push(segment);
+                continue;
+            };
+            if delete_segment(name, &segment, reason).await {
+                total -= segment.size;
+            } else {
+                kept.push(segment);
+            }
+        }
+        camera_bytes.insert(name, total);
+        deletable.extend(kept.into_iter().map(|x| (name.as_str(), x)));
+    }
+
+    if let Some(high_watermark) = config.retention.high_watermark_bytes {
+        let low_watermark = config
+            .retention
+            .low_watermark_bytes
+            .unwrap_or(high_watermark)
+            .min(high_watermark);
+        let mut total = camera_bytes.values().sum::<u64>();
+        if total > high_watermark {
+            deletable.sort_by_key(|x| x.1.modified);
+            for (camera, segment) in &deletable {
+                if total <= low_watermark {
+                    break;
+                }
+                if delete_segment(camera, segment, "high watermark").await {
+                    total -= segment.size;
+                    *camera_bytes.get_mut(camera).unwrap() -= segment.size;
+                }
+            }
+        }
+    }
+
+    for (camera, bytes) in &camera_bytes {
+        RECORDING_BYTES
+            .with_label_values(&[camera])
+            .set(*bytes as i64);
+    }
+
+    sweep_events(config, now).await
+}
+
+async fn sweep_events(config: &Config, now: SystemTime) -> Result<()> {
+    let cutoff = config
+        .retention
+        .event_max_age_secs
+        .and_then(|x| now.checked_sub(Duration::from_secs(x)));
+
+    let mut event_bytes = config
+        .cameras
+        .keys()
+        .map(|x| (x.as_str(), 0u64))
+        .collect::<IndexMap<_, _>>();
+    for clip in list_files(&config.event_dir, "mp4").await? {
+        let camera = clip
+            .path
+            .file_name()
+            .and_then(|x| event_camera(config, &x.to_string_lossy()));
+        if cutoff.map(|x| clip.modified < x).unwrap_or(false) {
+            let mut deleted = true;
+            for extension in EVENT_EXTENSIONS {
+                deleted &= delete(&clip.path.with_extension(extension)).await;
+            }
+            if deleted {
+                info!("deleted event '{}' (event max age)", clip.path.display());
+                if let Some(filename) = clip.path.file_name() {
+                    let filename = filename.to_string_lossy().into_owned();
+                    if let Err(e) = index::remove_event(filename).await {
+                        error!("failed to remove event from index: {e:#}");
+                    }
+                }
+                if let Some(camera) = camera {
+                    RETENTION_DELETED_EVENTS.with_label_values(&[camera]).inc();
+                }
+                continue;
+            }
+        }
+        let Some(camera) = camera else {
+            continue;
+        };
+        let mut size = clip.size;
+        for extension in &EVENT_EXTENSIONS[1..] {
+            if let Ok(metadata) = tokio::fs::metadata(clip.path.with_extension(extension)).await {
+                size += metadata.len();
+            }
+        }
+        *event_bytes.get_mut(camera).unwrap() += size;
+    }
+
+    for (camera, bytes) in &event_bytes {
+        EVENT_BYTES.with_label_values(&[camera]).set(*bytes as i64);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+
+    use filetime::FileTime;
+    use tempfile::TempDir;
+
+    use super::sweep;
+    use crate::config::{test_config, Config};
+
+    fn config(dir: &Path, retention: &str) -> Config {
+        let mut config = test_config(&format!(
+            "
+cameras:
+  front:
+    rtsp: rtsp://front/
+    mode: record
+  front_door:
+    rtsp: rtsp://front_door/
+    mode: record
+retention:
+{retention}
+"
+        ));
+        config.recording_dir = dir.join("recordings");
+        config.event_dir = dir.join("events");
+        config.live_dir = dir.join("live");
+        config
+    }
+
+    /// Writes a file of `size` bytes last modified `age` seconds before `now`
+    fn file(path: PathBuf, size: usize, age: u64, now: SystemTime) -> PathBuf {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, vec![0u8; size]).unwrap();
+        let modified = FileTime::from_system_time(now - Duration::from_secs(age));
+        filetime::set_file_mtime(&path, modified).unwrap();
+        path
+    }
+
+    fn segment(dir: &TempDir, camera: &str, age: u64, size: usize, now: SystemTime) -> PathBuf {
+        file(
+            dir.path()
+                .join("recordings")
+                .join(camera)
+                .join(format!("{age}.mp4")),
+            size,
+            age,
+            now,
+        )
+    }
+
+    /// Names of the remaining segments of a camera, oldest first
+    fn remaining(dir: &TempDir, camera: &str) -> Vec<String> {
+        let mut out = std::fs::read_dir(dir.path().join("recordings").join(camera))
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .map(|x| {
+                x.file_stem()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .parse::<u64>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        out.sort_unstable_by(|x, y| y.cmp(x));
+        out.into_iter().map(|x| x.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn max_age() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for age in [7200, 3601, 3599, 60] {
+            segment(&dir, "front", age, 10, now);
+        }
+        // the newest segment is kept even when expired, ffmpeg may still be writing it
+        segment(&dir, "front_door", 7200, 10, now);
+        sweep(&config(dir.path(), "  max_age_secs: 3600"), now)
+            .await
+            .unwrap();
+        assert_eq!(remaining(&dir, "front"), ["3599", "60"]);
+        assert_eq!(remaining(&dir, "front_door"), ["7200"]);
+    }
+
+    #[tokio::test]
+    async fn max_bytes_deletes_oldest_first() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for age in [40, 30, 20, 10] {
+            segment(&dir, "front", age, 100, now);
+        }
+        segment(&dir, "front_door", 10, 100, now);
+        sweep(&config(dir.path(), "  max_bytes: 250"), now)
+            .await
+            .unwrap();
+        assert_eq!(remaining(&dir, "front"), ["20", "10"]);
+        assert_eq!(remaining(&dir, "front_door"), ["10"]);
+    }
+
+    #[tokio::test]
+    async fn watermarks_delete_oldest_across_cameras() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for age in [50, 30, 0] {
+            segment(&dir, "front", age, 100, now);
+        }
+        for age in [40, 20, 1] {
+            segment(&dir, "front_door", age, 100, now);
+        }
+        let retention = "  high_watermark_bytes: 500\n  low_watermark_bytes: 300";
+        sweep(&config(dir.path(), retention), now).await.unwrap();
+        assert_eq!(remaining(&dir, "front"), ["0"]);
+        assert_eq!(remaining(&dir, "front_door"), ["20", "1"]);
+
+        // under the high watermark nothing is deleted, even above the low one
+        segment(&dir, "front", 10, 100, now);
+        sweep(&config(dir.path(), retention), now).await.unwrap();
+        assert_eq!(remaining(&dir, "front"), ["10", "0"]);
+    }
+
+    #[tokio::test]
+    async fn ages_before_the_epoch() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        segment(&dir, "front", 7200, 10, now);
+        segment(&dir, "front", 60, 10, now);
+        let event = file(dir.path().join("events/front_1.mp4"), 10, 7200, now);
+        let retention = format!("  max_age_secs: {0}\n  event_max_age_secs: {0}", u64::MAX);
+        sweep(&config(dir.path(), &retention), now).await.unwrap();
+        assert_eq!(remaining(&dir, "front"), ["7200", "60"]);
+        assert!(event.exists());
+    }
+
+    #[tokio::test]
+    async fn event_max_age() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        let old = file(dir.path().join("events/front_door_1.mp4"), 10, 7200, now);
+        let old_metadata = file(old.with_extension("json"), 10, 7200, now);
+        let new = file(dir.path().join("events/front_2.mp4"), 10, 60, now);
+        sweep(&config(dir.path(), "  event_max_age_secs: 3600"), now)
+            .await
+            .unwrap();
+        assert!(!old.exists());
+        assert!(!old_metadata.exists());
+        assert!(new.exists());
+    }
+}
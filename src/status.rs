@@ -0,0 +1,63 @@
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use serde::Serialize;
+
+lazy_static::lazy_static! {
+    static ref STATUS: RwLock<IndexMap<String, CameraStatus>> = RwLock::new(IndexMap::new());
+}
+
+/// Live status of a camera's ffmpeg and motion detector
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct CameraStatus {
+    /// True while ffmpeg is running for the camera
+    pub online: bool,
+    pub online_since: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_time: Option<DateTime<Utc>>,
+    /// Current `MotionDetectionState` for motion detection cameras
+    pub motion_state: Option<&'static str>,
+}
+
+pub fn get(camera: &str) -> CameraStatus {
+    STATUS
+        .read()
+        .unwrap()
+        .get(camera)
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn update(camera: &str, func: impl FnOnce(&mut CameraStatus)) {
+    let mut status = STATUS.write().unwrap();
+    if let Some(status) = status.get_mut(camera) {
+        func(status);
+    } else {
+        let mut new_status = CameraStatus::default();
+        func(&mut new_status);
+        status.insert(camera.to_string(), new_status);
+    }
+}
+
+pub fn ffmpeg_started(camera: &str) {
+    update(camera, |status| {
+        status.online = true;
+        status.online_since = Some(Utc::now());
+    });
+}
+
+pub fn ffmpeg_stopped(camera: &str, error: Option<String>) {
+    update(camera, |status| {
+        status.online = false;
+        status.online_since = None;
+        if let Some(error) = error {
+            status.last_error = Some(error);
+            status.last_error_time = Some(Utc::now());
+        }
+    });
+}
+
+pub fn motion_state(camera: &str, state: &'static str) {
+    update(camera, |status| status.motion_state = Some(state));
+}
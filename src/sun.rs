@@ -0,0 +1,102 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    /// degrees, north is positive
+    pub latitude: f64,
+    /// degrees, east is positive
+    pub longitude: f64,
+}
+
+/// Elevation of the sun's center at sunrise and sunset, accounting for refraction and the sun's radius
+const HORIZON_DEGREES: f64 = -0.833;
+
+/// Elevation of the sun above the horizon in degrees, from the low precision formulas of the Astronomical Almanac (good to ~0.01° for 1950-2050)
+pub fn elevation(location: Location, time: DateTime<Utc>) -> f64 {
+    // days since J2000.0
+    let days = time.timestamp() as f64 / 86400.0 + 2440587.5 - 2451545.0;
+    let mean_longitude = (280.460 + 0.9856474 * days).rem_euclid(360.0);
+    let mean_anomaly = (357.528 + 0.9856003 * days).rem_euclid(360.0).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.0000004 * days).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let sidereal_degrees =
+        (280.46061837 + 360.98564736629 * days + location.longitude).rem_euclid(360.0);
+    let hour_angle = sidereal_degrees.to_radians() - right_ascension;
+
+    let latitude = location.latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+/// Whether `time` is between sunrise and sunset at `location`
+pub fn is_day(location: Location, time: DateTime<Utc>) -> bool {
+    elevation(location, time) > HORIZON_DEGREES
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    /// Night shortly before `sunrise`, day after it until shortly before `sunset`
+    fn check(location: Location, sunrise: DateTime<Utc>, sunset: DateTime<Utc>) {
+        let margin = Duration::minutes(5);
+        assert!(!is_day(location, sunrise - margin), "{sunrise}");
+        assert!(is_day(location, sunrise + margin), "{sunrise}");
+        assert!(is_day(location, sunset - margin), "{sunset}");
+        assert!(!is_day(location, sunset + margin), "{sunset}");
+    }
+
+    // published sunrise and sunset times, converted to UTC
+
+    #[test]
+    fn london_summer_solstice() {
+        let london = Location {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        // 04:43 and 21:21 BST
+        check(london, utc(2024, 6, 21, 3, 43), utc(2024, 6, 21, 20, 21));
+        // the sun culminates at 90° - latitude + declination
+        let noon = elevation(london, utc(2024, 6, 21, 12, 2));
+        assert!((noon - (90.0 - 51.5074 + 23.44)).abs() < 0.2, "{noon}");
+    }
+
+    #[test]
+    fn new_york_winter_solstice() {
+        let new_york = Location {
+            latitude: 40.7128,
+            longitude: -74.006,
+        };
+        // 07:16 and 16:32 EST
+        check(
+            new_york,
+            utc(2024, 12, 21, 12, 16),
+            utc(2024, 12, 21, 21, 32),
+        );
+    }
+
+    #[test]
+    fn sydney_southern_summer() {
+        let sydney = Location {
+            latitude: -33.8688,
+            longitude: 151.2093,
+        };
+        // 05:41 and 20:05 AEDT on 2024-12-21, the night before is in the UTC day before
+        check(sydney, utc(2024, 12, 20, 18, 41), utc(2024, 12, 21, 9, 5));
+    }
+}
@@ -0,0 +1,544 @@
+use std::cmp::Ordering;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blob::Blob,
+    zone::{Point, ZoneConfig},
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// the event is accepted regardless of its score, and once any confirm rule is configured events without one are rejected
+    #[default]
+    Confirm,
+    /// the event is rejected regardless of its score
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossingDirection {
+    #[default]
+    Any,
+    LeftToRight,
+    RightToLeft,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TripwireConfig {
+    /// Left and right are as seen from the first point looking towards the second
+    pub line: [Point; 2],
+    #[serde(default)]
+    pub direction: CrossingDirection,
+    #[serde(default)]
+    pub action: RuleAction,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Heading {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ZoneEntryConfig {
+    /// Name of a zone in `zones`
+    pub zone: String,
+    /// Only entries while moving mostly in this direction count
+    pub heading: Option<Heading>,
+    #[serde(default)]
+    pub action: RuleAction,
+}
+
+fn default_max_distance() -> f64 {
+    0.1
+}
+
+fn default_max_missed_frames() -> usize {
+    5
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrackingConfig {
+    /// How far a blob may be from a track, as a fraction of the frame diagonal, to continue it when their boxes don't overlap
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f64,
+    /// Frames a track survives without a matching blob
+    #[serde(default = "default_max_missed_frames")]
+    pub max_missed_frames: usize,
+    #[serde(default)]
+    pub tripwires: IndexMap<String, TripwireConfig>,
+    #[serde(default)]
+    pub zone_entries: IndexMap<String, ZoneEntryConfig>,
+}
+
+impl TrackingConfig {
+    fn has_confirm_rules(&self) -> bool {
+        self.tripwires
+            .values()
+            .map(|x| x.action)
+            .chain(self.zone_entries.values().map(|x| x.action))
+            .any(|x| x == RuleAction::Confirm)
+    }
+}
+
+/// Position of a tracked object in one frame, in normalized coordinates like zones
+#[derive(Serialize, Clone, Debug)]
+pub struct TrackPoint {
+    pub frame_number: u64,
+    pub position: Point,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Track {
+    pub id: u64,
+    pub path: Vec<TrackPoint>,
+}
+
+struct ActiveTrack {
+    track: Track,
+    blob: Blob,
+    missed: usize,
+    /// Rules already fired by this track, each fires once per track
+    fired: Vec<String>,
+}
+
+/// Which side of the line from `a` to `b` `point` is on, negative is left
+fn side(a: Point, b: Point, point: Point) -> f64 {
+    (b[0] - a[0]) * (point[1] - a[1]) - (b[1] - a[1]) * (point[0] - a[0])
+}
+
+/// Rules fired by a track moving from `from` to `to`
+fn fired_rules<'a>(
+    from: Point,
+    to: Point,
+    aspect: f64,
+    config: &'a TrackingConfig,
+    zones: &IndexMap<String, ZoneConfig>,
+) -> Vec<(&'a String, RuleAction)> {
+    let mut fired = vec![];
+    for (name, tripwire) in &config.tripwires {
+        let [a, b] = tripwire.line;
+        let (side_from, side_to) = (side(a, b, from), side(a, b, to));
+        // the movement must cross the line itself, not just its extension
+        let crossed = side_from * side_to < 0.0 && side(from, to, a) * side(from, to, b) <= 0.0;
+        let direction_matches = match tripwire.direction {
+            CrossingDirection::Any => true,
+            CrossingDirection::LeftToRight => side_from < 0.0,
+            CrossingDirection::RightToLeft => side_from > 0.0,
+        };
+        if crossed && direction_matches {
+            fired.push((name, tripwire.action));
+        }
+    }
+    for (name, entry) in &config.zone_entries {
+        let Some(zone) = zones.get(&entry.zone) else {
+            continue;
+        };
+        if zone.contains(from[0], from[1]) || !zone.contains(to[0], to[1]) {
+            continue;
+        }
+        let (dx, dy) = ((to[0] - from[0]) * aspect, to[1] - from[1]);
+        let heading = match (dx.abs() > dy.abs(), dx > 0.0, dy > 0.0) {
+            (true, true, _) => Heading::Right,
+            (true, false, _) => Heading::Left,
+            (false, _, true) => Heading::Down,
+            (false, _, false) => Heading::Up,
+        };
+        if entry.heading.map_or(true, |x| x == heading) {
+            fired.push((name, entry.action));
+        }
+    }
+    fired
+}
+
+/// Follows blobs across frames by bounding box overlap, falling back to centroid distance
+#[derive(Default)]
+pub struct Tracker {
+    next_id: u64,
+    active: Vec<ActiveTrack>,
+    /// Tracks that ended since the last `take`
+    ended: Vec<Track>,
+    /// Rules fired since the last `take`, in order
+    fired: Vec<(String, RuleAction)>,
+}
+
+impl Tracker {
+    pub fn update(
+        &mut self,
+        frame_number: u64,
+        blobs: &[Blob],
+        (width, height): (u32, u32),
+        config: &TrackingConfig,
+        zones: &IndexMap<String, ZoneConfig>,
+    ) {
+        let diagonal = (width as f64).hypot(height as f64);
+        let normalize = |blob: &Blob| {
+            [
+                blob.centroid[0] / width as f64,
+                blob.centroid[1] / height as f64,
+            ]
+        };
+
+        let mut pairs = vec![];
+        for (track_index, track) in self.active.iter().enumerate() {
+            for (blob_index, blob) in blobs.iter().enumerate() {
+                let iou = track.blob.iou(blob);
+                let distance = (track.blob.centroid[0] - blob.centroid[0])
+                    .hypot(track.blob.centroid[1] - blob.centroid[1])
+                    / diagonal;
+                if iou > 0.0 || distance <= config.max_distance {
+                    pairs.push((iou, distance, track_index, blob_index));
+                }
+            }
+        }
+        // greedily match the most overlapping pairs first, then the closest
+        pairs.sort_by(|x, y| {
+            y.0.partial_cmp(&x.0)
+                .unwrap_or(Ordering::Equal)
+                .then(x.1.partial_cmp(&y.1).unwrap_or(Ordering::Equal))
+        });
+        let mut track_matched = vec![false; self.active.len()];
+        let mut blob_matched = vec![false; blobs.len()];
+        for (_, _, track_index, blob_index) in pairs {
+            if track_matched[track_index] || blob_matched[blob_index] {
+                continue;
+            }
+            track_matched[track_index] = true;
+            blob_matched[blob_index] = true;
+            let blob = &blobs[blob_index];
+            let track = &mut self.active[track_index];
+            let from = track.track.path.last().unwrap().position;
+            let to = normalize(blob);
+            for (name, action) in fired_rules(from, to, width as f64 / height as f64, config, zones)
+            {
+                if !track.fired.contains(name) {
+                    track.fired.push(name.clone());
+                    self.fired.push((name.clone(), action));
+                }
+            }
+            track.track.path.push(TrackPoint {
+                frame_number,
+                position: to,
+            });
+            track.blob = blob.clone();
+            track.missed = 0;
+        }
+
+        for (track, matched) in self.active.iter_mut().zip(track_matched) {
+            if !matched {
+                track.missed += 1;
+            }
+        }
+        let (active, ended): (Vec<_>, Vec<_>) = std::mem::take(&mut self.active)
+            .into_iter()
+            .partition(|x| x.missed <= config.max_missed_frames);
+        self.active = active;
+        self.ended.extend(ended.into_iter().map(|x| x.track));
+
+        for (blob, _) in blobs.iter().zip(blob_matched).filter(|x| !x.1) {
+            self.active.push(ActiveTrack {
+                track: Track {
+                    id: self.next_id,
+                    path: vec![TrackPoint {
+                        frame_number,
+                        position: normalize(blob),
+                    }],
+                },
+                blob: blob.clone(),
+                missed: 0,
+                fired: vec![],
+            });
+            self.next_id += 1;
+        }
+    }
+
+    /// Whether the fired rules accept (true) or reject (false) the current detection, None to decide by score
+    pub fn verdict(&self, config: &TrackingConfig) -> Option<bool> {
+        if self.fired.iter().any(|x| x.1 == RuleAction::Reject) {
+            Some(false)
+        } else if self.fired.iter().any(|x| x.1 == RuleAction::Confirm) {
+            Some(true)
+        } else if config.has_confirm_rules() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Tracks seen since the last `take`, including ongoing ones
+    pub fn tracks(&self) -> Vec<Track> {
+        self.ended
+            .iter()
+            .cloned()
+            .chain(self.active.iter().map(|x| x.track.clone()))
+            .collect()
+    }
+
+    /// Names of the rules fired since the last `take`
+    pub fn fired(&self) -> Vec<String> {
+        self.fired.iter().map(|x| x.0.clone()).collect()
+    }
+
+    /// Tracks and fired rules since the last call. Ongoing tracks continue, starting from their latest position.
+    pub fn take(&mut self) -> (Vec<Track>, Vec<String>) {
+        let out = (self.tracks(), self.fired());
+        self.ended.clear();
+        self.fired.clear();
+        for track in &mut self.active {
+            track.track.path.drain(..track.track.path.len() - 1);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use crate::{blob::Blob, zone::ZoneConfig};
+
+    use super::{fired_rules, RuleAction, Track, Tracker, TrackingConfig};
+
+    const SIZE: (u32, u32) = (100, 100);
+
+    fn config(yaml: &str) -> TrackingConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn fired(config: &TrackingConfig, from: [f64; 2], to: [f64; 2]) -> Vec<&str> {
+        let zones = IndexMap::from([(
+            "right".to_string(),
+            ZoneConfig {
+                include: vec![vec![[0.5, 0.0], [1.0, 0.0], [1.0, 1.0], [0.5, 1.0]]],
+                exclude: vec![],
+            },
+        )]);
+        fired_rules(from, to, 1.0, config, &zones)
+            .into_iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn tripwire_directions() {
+        let config = config(
+            "
+tripwires:
+  any:
+    line: [ [0.5, 0.0], [0.5, 1.0] ]
+  left_to_right:
+    line: [ [0.5, 0.0], [0.5, 1.0] ]
+    direction: left_to_right
+  right_to_left:
+    line: [ [0.5, 0.0], [0.5, 1.0] ]
+    direction: right_to_left
+    action: reject
+",
+        );
+        // looking down the line, its left is the right of the frame
+        assert_eq!(
+            fired(&config, [0.7, 0.5], [0.3, 0.5]),
+            ["any", "left_to_right"]
+        );
+        assert_eq!(
+            fired(&config, [0.3, 0.5], [0.7, 0.5]),
+            ["any", "right_to_left"]
+        );
+        assert!(fired(&config, [0.3, 0.5], [0.4, 0.5]).is_empty());
+        assert_eq!(
+            fired_rules([0.3, 0.5], [0.7, 0.5], 1.0, &config, &IndexMap::new())[1].1,
+            RuleAction::Reject
+        );
+    }
+
+    #[test]
+    fn tripwire_extension_not_crossed() {
+        let config = config(
+            "
+tripwires:
+  short:
+    line: [ [0.5, 0.0], [0.5, 0.2] ]
+",
+        );
+        assert!(fired(&config, [0.7, 0.5], [0.3, 0.5]).is_empty());
+        assert_eq!(fired(&config, [0.7, 0.1], [0.3, 0.1]), ["short"]);
+    }
+
+    #[test]
+    fn zone_entry_heading() {
+        let config = config(
+            "
+zone_entries:
+  any:
+    zone: right
+  rightwards:
+    zone: right
+    heading: right
+  downwards:
+    zone: right
+    heading: down
+",
+        );
+        assert_eq!(
+            fired(&config, [0.4, 0.5], [0.6, 0.55]),
+            ["any", "rightwards"]
+        );
+        // leaving or moving within the zone is not an entry
+        assert!(fired(&config, [0.6, 0.5], [0.4, 0.5]).is_empty());
+        assert!(fired(&config, [0.6, 0.5], [0.8, 0.5]).is_empty());
+    }
+
+    /// A 10x10 box at `x`, `y`, the centroid is independent of it to test overlap and distance separately
+    fn blob(x: u32, y: u32, centroid: [f64; 2]) -> Blob {
+        Blob {
+            x,
+            y,
+            width: 10,
+            height: 10,
+            area: 100,
+            centroid,
+        }
+    }
+
+    /// Ids and positions of the tracks
+    fn paths(tracks: &[Track]) -> Vec<(u64, Vec<[f64; 2]>)> {
+        tracks
+            .iter()
+            .map(|x| (x.id, x.path.iter().map(|x| x.position).collect()))
+            .collect()
+    }
+
+    fn update(tracker: &mut Tracker, frame_number: u64, blobs: &[Blob], config: &TrackingConfig) {
+        tracker.update(frame_number, blobs, SIZE, config, &IndexMap::new());
+    }
+
+    #[test]
+    fn overlap_before_distance() {
+        let config = config("max_distance: 0.1");
+        let mut tracker = Tracker::default();
+        update(
+            &mut tracker,
+            0,
+            &[blob(0, 0, [5.0, 5.0]), blob(50, 0, [55.0, 5.0])],
+            &config,
+        );
+        // overlaps the first track's box, but is within max_distance of the second's centroid only
+        update(&mut tracker, 1, &[blob(5, 0, [50.0, 5.0])], &config);
+        assert_eq!(
+            paths(&tracker.tracks()),
+            [
+                (0, vec![[0.05, 0.05], [0.5, 0.05]]),
+                (1, vec![[0.55, 0.05]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn distance_fallback() {
+        let config = config("max_distance: 0.1");
+        let mut tracker = Tracker::default();
+        update(&mut tracker, 0, &[blob(0, 0, [5.0, 5.0])], &config);
+        // no overlap, 0.085 of the diagonal away
+        update(&mut tracker, 1, &[blob(12, 0, [17.0, 5.0])], &config);
+        // 0.2 of the diagonal away
+        update(&mut tracker, 2, &[blob(40, 0, [45.0, 5.0])], &config);
+        assert_eq!(
+            paths(&tracker.tracks()),
+            [
+                (0, vec![[0.05, 0.05], [0.17, 0.05]]),
+                (1, vec![[0.45, 0.05]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_expire_after_max_missed_frames() {
+        let config = config("max_missed_frames: 2");
+        let mut tracker = Tracker::default();
+        let a = blob(0, 0, [5.0, 5.0]);
+        update(&mut tracker, 0, &[a.clone()], &config);
+        update(&mut tracker, 1, &[], &config);
+        update(&mut tracker, 2, &[], &config);
+        // still active after missing two frames
+        update(&mut tracker, 3, &[a.clone()], &config);
+        assert_eq!(paths(&tracker.tracks()).len(), 1);
+
+        for frame_number in 4..7 {
+            update(&mut tracker, frame_number, &[], &config);
+        }
+        update(&mut tracker, 7, &[a], &config);
+        let (tracks, _) = tracker.take();
+        assert_eq!(
+            paths(&tracks),
+            [
+                (0, vec![[0.05, 0.05], [0.05, 0.05]]),
+                (1, vec![[0.05, 0.05]]),
+            ]
+        );
+        // the ended track is gone after `take`, the new one continues
+        assert_eq!(paths(&tracker.tracks()), [(1, vec![[0.05, 0.05]])]);
+    }
+
+    #[test]
+    fn take_keeps_latest_point_of_ongoing_tracks() {
+        let config = config("{}");
+        let mut tracker = Tracker::default();
+        update(&mut tracker, 0, &[blob(0, 0, [5.0, 5.0])], &config);
+        update(&mut tracker, 1, &[blob(2, 0, [7.0, 5.0])], &config);
+        let (tracks, _) = tracker.take();
+        assert_eq!(paths(&tracks), [(0, vec![[0.05, 0.05], [0.07, 0.05]])]);
+
+        update(&mut tracker, 2, &[blob(4, 0, [9.0, 5.0])], &config);
+        let tracks = tracker.tracks();
+        assert_eq!(paths(&tracks), [(0, vec![[0.07, 0.05], [0.09, 0.05]])]);
+        assert_eq!(tracks[0].path[0].frame_number, 1);
+    }
+
+    #[test]
+    fn verdict() {
+        let reject_only = config(
+            "
+tripwires:
+  fence:
+    line: [ [0.0, 0.5], [1.0, 0.5] ]
+    action: reject
+",
+        );
+        // no confirm rule, so the score decides
+        assert_eq!(Tracker::default().verdict(&reject_only), None);
+
+        let config = config(
+            "
+tripwires:
+  door:
+    line: [ [0.5, 0.0], [0.5, 1.0] ]
+  fence:
+    line: [ [0.0, 0.5], [1.0, 0.5] ]
+    action: reject
+",
+        );
+        let mut tracker = Tracker::default();
+        // a confirm rule is configured, but hasn't fired
+        assert_eq!(tracker.verdict(&config), Some(false));
+
+        update(&mut tracker, 0, &[blob(40, 40, [45.0, 45.0])], &config);
+        update(&mut tracker, 1, &[blob(52, 40, [57.0, 45.0])], &config);
+        assert_eq!(tracker.verdict(&config), Some(true));
+        // each rule fires once per track
+        update(&mut tracker, 2, &[blob(40, 40, [45.0, 45.0])], &config);
+        update(&mut tracker, 3, &[blob(52, 40, [57.0, 45.0])], &config);
+        assert_eq!(tracker.fired(), ["door"]);
+        // reject wins
+        update(&mut tracker, 4, &[blob(52, 52, [57.0, 57.0])], &config);
+        assert_eq!(tracker.verdict(&config), Some(false));
+        assert_eq!(tracker.take().1, ["door", "fence"]);
+        assert_eq!(tracker.verdict(&config), Some(false));
+    }
+}
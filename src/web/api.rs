@@ -0,0 +1,224 @@
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing, Extension, Json, Router,
+};
+use axum_util::errors::{ApiError, ApiResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arming,
+    config::{CameraConfig, CameraMode, CONFIG},
+    event::StoredEvent,
+    index::{self, EventFilter},
+    recording::Recording,
+    status::{self, CameraStatus},
+};
+
+use super::auth::AuthUser;
+
+const DEFAULT_EVENT_LIMIT: usize = 100;
+const MAX_EVENT_LIMIT: usize = 1000;
+
+pub fn route() -> Router {
+    Router::new()
+        .route("/cameras", routing::get(list_cameras))
+        .route("/cameras/:name", routing::get(get_camera))
+        .route("/cameras/:name/recordings", routing::get(list_recordings))
+        .route("/events", routing::get(list_events))
+        .route("/arming", routing::get(get_arming).put(set_arming))
+}
+
+#[derive(Serialize)]
+struct ApiCamera {
+    name: String,
+    mode: CameraMode,
+    frame_rate: f64,
+    motion_detection: bool,
+    #[serde(flatten)]
+    status: CameraStatus,
+}
+
+impl ApiCamera {
+    fn new(name: &str, camera: &CameraConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            mode: camera.mode,
+            frame_rate: camera.frame_rate,
+            motion_detection: matches!(
+                camera.mode,
+                CameraMode::MotionDetect | CameraMode::MotionDetectRecord
+            ),
+            status: status::get(name),
+        }
+    }
+}
+
+async fn list_cameras(Extension(user): Extension<AuthUser>) -> Json<Vec<ApiCamera>> {
+    let config = CONFIG.get();
+    Json(
+        config
+            .cameras
+            .iter()
+            .filter(|(name, camera)| {
+                camera.mode != CameraMode::Disable && user.can_access(&config, name)
+            })
+            .map(|(name, camera)| ApiCamera::new(name, camera))
+            .collect(),
+    )
+}
+
+async fn get_camera(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Json<ApiCamera>> {
+    let config = CONFIG.get();
+    let camera = user.camera(&config, &name)?;
+    Ok(Json(ApiCamera::new(&name, camera)))
+}
+
+#[derive(Serialize)]
+struct ApiRecording {
+    #[serde(flatten)]
+    recording: Recording,
+    url: String,
+}
+
+async fn list_recordings(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Json<Vec<ApiRecording>>> {
+    let config = CONFIG.get();
+    user.camera(&config, &name)?;
+    let recordings = index::query_recordings(name.clone())
+        .await
+        .map_err(ApiError::Other)?;
+    Ok(Json(
+        recordings
+            .into_iter()
+            .map(|recording| ApiRecording {
+                url: format!(
+                    "{}camera/{name}/video/{}",
+                    config.web_base, recording.filename
+                ),
+                recording,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct EventQuery {
+    camera: Option<String>,
+    /// Only events at or after this time
+    from: Option<DateTime<Utc>>,
+    /// Only events before this time
+    to: Option<DateTime<Utc>>,
+    min_score: Option<f64>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ApiEvent {
+    #[serde(flatten)]
+    event: StoredEvent,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct ApiEventPage {
+    /// Count of events matching the filters, before pagination
+    total: usize,
+    offset: usize,
+    limit: usize,
+    events: Vec<ApiEvent>,
+}
+
+/// Matching events, newest first
+async fn list_events(
+    Query(query): Query<EventQuery>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Json<ApiEventPage>> {
+    let config = CONFIG.get();
+    let limit = query.limit.unwrap_or(DEFAULT_EVENT_LIMIT);
+    if limit > MAX_EVENT_LIMIT {
+        return Err(ApiError::BadRequest(format!(
+            "limit must be at most {MAX_EVENT_LIMIT}"
+        )));
+    }
+    let filter = EventFilter {
+        camera: query.camera,
+        cameras: user.allowed_cameras(&config),
+        from: query.from,
+        to: query.to,
+        min_score: query.min_score,
+    };
+    let (total, events) = index::query_events(filter, query.offset, Some(limit))
+        .await
+        .map_err(ApiError::Other)?;
+
+    Ok(Json(ApiEventPage {
+        total,
+        offset: query.offset,
+        limit,
+        events: events
+            .into_iter()
+            .map(|event| ApiEvent {
+                url: format!("{}events/{}", config.web_base, event.filename),
+                event,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct ApiArming {
+    mode: String,
+    modes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SetArming {
+    mode: String,
+}
+
+async fn get_arming() -> ApiResult<Json<ApiArming>> {
+    let config = CONFIG.get();
+    let (Some(arming), Some(mode)) = (&config.arming, arming::current()) else {
+        return Err(ApiError::NotFound);
+    };
+    Ok(Json(ApiArming {
+        mode,
+        modes: arming.modes.keys().cloned().collect(),
+    }))
+}
+
+/// Switches the arming mode, only allowed with `auth` configured and for users with access to every camera
+async fn set_arming(
+    Extension(user): Extension<AuthUser>,
+    Json(body): Json<SetArming>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let Some(arming) = &config.arming else {
+        return Err(ApiError::NotFound);
+    };
+    if !user.is_admin(&config) {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "arming requires auth and access to every camera",
+        )
+            .into_response());
+    }
+    if !arming.modes.contains_key(&body.mode) {
+        return Err(ApiError::BadRequest(format!(
+            "unknown mode '{}'",
+            body.mode
+        )));
+    }
+    arming::set(body.mode).await.map_err(ApiError::Other)?;
+    Ok(get_arming().await?.into_response())
+}
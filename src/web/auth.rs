@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use axum::{
+    body::{BoxBody, Bytes, Full, HttpBody},
+    extract::{ConnectInfo, Form},
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization, Cookie, HeaderMapExt,
+    },
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_util::errors::{ApiError, ApiResult};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::{AuthConfig, CameraConfig, CameraMode, Config, CONFIG};
+
+const SESSION_COOKIE: &str = "rmr_session";
+/// Failed logins a client IP or username gets before it has to back off
+const FREE_LOGIN_FAILURES: u32 = 5;
+/// Longest backoff between login attempts after repeated failures
+const MAX_LOGIN_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+struct Session {
+    user: String,
+    expires: Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<String, Session>> = Mutex::new(HashMap::new());
+    static ref LOGIN_THROTTLE: Mutex<LoginThrottle> = Mutex::new(LoginThrottle::default());
+    /// Verified against for unknown users, so that failing takes as long as for known ones
+    static ref DUMMY_PASSWORD_HASH: String = hash_password("rmr dummy password").unwrap();
+}
+
+/// What failed logins are counted against
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum LoginKey {
+    Ip(IpAddr),
+    User(String),
+}
+
+/// Exponential backoff against password guessing, per client IP and per username
+#[derive(Default)]
+struct LoginThrottle {
+    /// Count of consecutive failed logins and the time of the last one
+    failures: HashMap<LoginKey, (u32, Instant)>,
+}
+
+impl LoginThrottle {
+    fn backoff(failures: u32) -> Duration {
+        match failures.checked_sub(FREE_LOGIN_FAILURES) {
+            None => Duration::ZERO,
+            Some(excess) => Duration::from_secs(1 << excess.min(10)).min(MAX_LOGIN_BACKOFF),
+        }
+    }
+
+    /// How long until all of `keys` may try again, None if they may now
+    fn retry_after(&self, keys: &[LoginKey], now: Instant) -> Option<Duration> {
+        keys.iter()
+            .filter_map(|key| self.failures.get(key))
+            .map(|(count, last)| (*last + Self::backoff(*count)).saturating_duration_since(now))
+            .filter(|x| !x.is_zero())
+            .max()
+    }
+
+    fn failed(&mut self, keys: &[LoginKey], now: Instant) {
+        // failure counts are forgotten once a key has been quiet for longer than any backoff
+        self.failures
+            .retain(|_, (_, last)| now.saturating_duration_since(*last) < MAX_LOGIN_BACKOFF);
+        for key in keys {
+            let (count, last) = self.failures.entry(key.clone()).or_insert((0, now));
+            *count += 1;
+            *last = now;
+        }
+    }
+
+    fn succeeded(&mut self, keys: &[LoginKey]) {
+        for key in keys {
+            self.failures.remove(key);
+        }
+    }
+}
+
+/// The user a request is authenticated as, inserted into the request extensions by `require_auth`
+#[derive(Clone)]
+pub struct AuthUser {
+    /// None if auth is disabled
+    name: Option<String>,
+}
+
+impl AuthUser {
+    /// Cameras of `config` this user can access, None if all of them
+    pub fn allowed_cameras(&self, config: &Config) -> Option<Vec<String>> {
+        let name = self.name.as_ref()?;
+        let Some(user) = config.auth.as_ref().and_then(|auth| auth.users.get(name)) else {
+            // removed from the config since the request was authenticated
+            return Some(vec![]);
+        };
+        user.cameras.clone()
+    }
+
+    /// True for authenticated users with access to every camera of `config`, who may change settings
+    pub fn is_admin(&self, config: &Config) -> bool {
+        self.name.is_some() && self.allowed_cameras(config).is_none()
+    }
+
+    pub fn can_access(&self, config: &Config, camera: &str) -> bool {
+        self.allowed_cameras(config)
+            .map(|cameras| cameras.iter().any(|x| x == camera))
+            .unwrap_or(true)
+    }
+
+    /// Looks up a camera of `config` that is enabled and accessible to this user
+    pub fn camera<'a>(&self, config: &'a Config, name: &str) -> ApiResult<&'a CameraConfig> {
+        match config.cameras.get(name) {
+            Some(camera) if camera.mode != CameraMode::Disable && self.can_access(config, name) => {
+                Ok(camera)
+            }
+            _ => Err(ApiError::NotFound),
+        }
+    }
+}
+
+/// Hashes a password into the argon2 PHC string expected in `UserConfig::password_hash`
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("failed to hash password: {e}"))?
+        .to_string())
+}
+
+/// Verifies a password against the config on a blocking thread, argon2 is deliberately slow
+async fn check_password(username: String, password: String) -> bool {
+    let config = CONFIG.get();
+    tokio::task::spawn_blocking(move || {
+        let user_hash = config
+            .auth
+            .as_ref()
+            .and_then(|auth| auth.users.get(&username))
+            .and_then(|user| user.password_hash.as_ref())
+            .and_then(|x| PasswordHash::new(x).ok());
+        let known = user_hash.is_some();
+        let password_hash =
+            user_hash.unwrap_or_else(|| PasswordHash::new(&DUMMY_PASSWORD_HASH).unwrap());
+        let verified = Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok();
+        known && verified
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Checks a password unless the client IP or the username is backing off from failed logins,
+/// in which case the remaining backoff is returned
+async fn check_login(ip: IpAddr, username: String, password: String) -> Result<bool, Duration> {
+    let keys = [LoginKey::Ip(ip), LoginKey::User(username.clone())];
+    if let Some(retry_after) = LOGIN_THROTTLE
+        .lock()
+        .unwrap()
+        .retry_after(&keys, Instant::now())
+    {
+        return Err(retry_after);
+    }
+    let verified = check_password(username, password).await;
+    let mut throttle = LOGIN_THROTTLE.lock().unwrap();
+    if verified {
+        throttle.succeeded(&keys);
+    } else {
+        throttle.failed(&keys, Instant::now());
+    }
+    Ok(verified)
+}
+
+fn session_user(auth: &AuthConfig, token: &str) -> Option<String> {
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = sessions.get(token)?;
+    if session.expires < Instant::now() || !auth.users.contains_key(&session.user) {
+        sessions.remove(token);
+        return None;
+    }
+    Some(session.user.clone())
+}
+
+/// The user owning an API token, matched by the digest of `token`
+fn token_user(auth: &AuthConfig, token: &str) -> Option<String> {
+    let digest = hex::encode(Sha256::digest(token.as_bytes()));
+    auth.users
+        .iter()
+        .find(|(_, user)| {
+            user.api_tokens
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(&digest))
+        })
+        .map(|(name, _)| name.clone())
+}
+
+/// Resolves the user of a request from its session cookie, bearer token or basic auth
+async fn authenticate(auth: &AuthConfig, headers: &HeaderMap, ip: IpAddr) -> Option<String> {
+    if let Some(cookie) = headers.typed_get::<Cookie>() {
+        if let Some(user) = cookie
+            .get(SESSION_COOKIE)
+            .and_then(|token| session_user(auth, token))
+        {
+            return Some(user);
+        }
+    }
+    if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+        return token_user(auth, bearer.token());
+    }
+    if auth.allow_basic {
+        if let Some(Authorization(basic)) = headers.typed_get::<Authorization<Basic>>() {
+            let username = basic.username().to_string();
+            if let Ok(true) = check_login(ip, username.clone(), basic.password().to_string()).await
+            {
+                return Some(username);
+            }
+        }
+    }
+    None
+}
+
+/// Rejects unauthenticated requests: API clients get a 401, browsers are sent to the login page
+pub async fn require_auth<B>(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = CONFIG.get();
+    let Some(auth) = &config.auth else {
+        request.extensions_mut().insert(AuthUser { name: None });
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    if path == "/login" || path == "/health" {
+        return next.run(request).await;
+    }
+
+    let Some(user) = authenticate(auth, request.headers(), addr.ip()).await else {
+        if request.uri().path().starts_with("/api/") {
+            let challenge = if auth.allow_basic {
+                "Basic realm=\"rmr\""
+            } else {
+                "Bearer"
+            };
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, challenge)],
+            )
+                .into_response();
+        }
+        return Redirect::to(&format!("{}login", config.web_base)).into_response();
+    };
+    request
+        .extensions_mut()
+        .insert(AuthUser { name: Some(user) });
+    next.run(request).await
+}
+
+fn login_page_response(status: StatusCode, error: Option<&str>) -> ApiResult<Response> {
+    let total = format!(
+        r#"
+        <html>
+        <head>
+            <title>RMR Login</title>
+            <style>
+            * {{
+                font-size: 36px
+            }}
+            </style>
+        </head>
+        <body>
+            <div>{}</div>
+            <form method="post" action="login">
+                <div><input type="text" name="username" placeholder="username" autocomplete="username"></div>
+                <div><input type="password" name="password" placeholder="password" autocomplete="current-password"></div>
+                <div><button type="submit">Login</button></div>
+            </form>
+        </body>
+        </html>
+    "#,
+        error.unwrap_or_default()
+    );
+
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "text/html")
+        .body(BoxBody::new::<_>(
+            Full::new(Bytes::from(total)).map_err(|_| unreachable!()),
+        ))?)
+}
+
+pub async fn login_page() -> ApiResult<Response> {
+    login_page_response(StatusCode::OK, None)
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+/// Session cookies are only sent back over HTTPS when it is served
+fn secure_attribute(config: &Config) -> &'static str {
+    if config.tls_cert.is_some() {
+        "; Secure"
+    } else {
+        ""
+    }
+}
+
+pub async fn login(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Form(form): Form<LoginForm>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let Some(auth) = &config.auth else {
+        return Ok(Redirect::to(&config.web_base).into_response());
+    };
+    match check_login(addr.ip(), form.username.clone(), form.password).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return login_page_response(
+                StatusCode::UNAUTHORIZED,
+                Some("Invalid username or password"),
+            )
+        }
+        Err(retry_after) => {
+            return login_page_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                Some(&format!(
+                    "Too many failed logins, try again in {} seconds",
+                    retry_after.as_secs().max(1)
+                )),
+            )
+        }
+    }
+
+    let mut token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    let token = hex::encode(token);
+    {
+        let now = Instant::now();
+        let mut sessions = SESSIONS.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                user: form.username,
+                expires: now + Duration::from_secs(auth.session_ttl_secs),
+            },
+        );
+    }
+
+    let cookie = format!(
+        "{SESSION_COOKIE}={token}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        config.web_base,
+        auth.session_ttl_secs,
+        secure_attribute(&config),
+    );
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to(&config.web_base),
+    )
+        .into_response())
+}
+
+pub async fn logout(headers: HeaderMap) -> Response {
+    if let Some(cookie) = headers.typed_get::<Cookie>() {
+        if let Some(token) = cookie.get(SESSION_COOKIE) {
+            SESSIONS.lock().unwrap().remove(token);
+        }
+    }
+    let config = CONFIG.get();
+    let cookie = format!(
+        "{SESSION_COOKIE}=; Path={}; Max-Age=0; HttpOnly; SameSite=Lax{}",
+        config.web_base,
+        secure_attribute(&config),
+    );
+    (
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to(&format!("{}login", config.web_base)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    const CONFIG_YAML: &str = r"
+cameras:
+  front:
+    rtsp: rtsp://camera/front
+    mode: record
+  back:
+    rtsp: rtsp://camera/back
+    mode: record
+  garage:
+    rtsp: rtsp://camera/garage
+    mode: disable
+auth:
+  users:
+    admin:
+      # sha256 of 'admin-token'
+      api_tokens: [10A4C7C9FC5206D6F36DC6944A81BB6F4A3CB0E25014AE3B12E6C3E52712292A]
+    guest:
+      cameras: [front, garage]
+";
+
+    fn config() -> Config {
+        test_config(CONFIG_YAML)
+    }
+
+    fn user(name: Option<&str>) -> AuthUser {
+        AuthUser {
+            name: name.map(|x| x.to_string()),
+        }
+    }
+
+    #[test]
+    fn camera_access() {
+        let config = config();
+        let can_access = |name, camera| user(name).can_access(&config, camera);
+        // auth disabled
+        assert!(can_access(None, "back"));
+        assert!(can_access(Some("admin"), "back"));
+        assert!(can_access(Some("guest"), "front"));
+        assert!(!can_access(Some("guest"), "back"));
+        // removed from the config since logging in
+        assert!(!can_access(Some("intruder"), "front"));
+
+        assert!(user(Some("guest")).camera(&config, "front").is_ok());
+        assert!(user(Some("guest")).camera(&config, "back").is_err());
+        // allowed, but disabled
+        assert!(user(Some("guest")).camera(&config, "garage").is_err());
+        assert!(user(Some("admin")).camera(&config, "missing").is_err());
+    }
+
+    #[test]
+    fn admin() {
+        let config = config();
+        // auth disabled
+        assert!(!user(None).is_admin(&config));
+        assert!(user(Some("admin")).is_admin(&config));
+        assert!(!user(Some("guest")).is_admin(&config));
+        assert!(!user(Some("intruder")).is_admin(&config));
+    }
+
+    #[test]
+    fn api_tokens() {
+        let config = config();
+        let auth = config.auth.as_ref().unwrap();
+        assert_eq!(token_user(auth, "admin-token").as_deref(), Some("admin"));
+        assert_eq!(token_user(auth, "ADMIN-TOKEN"), None);
+        assert_eq!(token_user(auth, ""), None);
+    }
+
+    #[test]
+    fn session_expiry() {
+        let config = config();
+        let auth = config.auth.as_ref().unwrap();
+        let now = Instant::now();
+        let session = |token: &str, user: &str, expires: Instant| {
+            SESSIONS.lock().unwrap().insert(
+                token.to_string(),
+                Session {
+                    user: user.to_string(),
+                    expires,
+                },
+            );
+        };
+        session("test-valid", "guest", now + Duration::from_secs(60));
+        session("test-expired", "guest", now - Duration::from_millis(1));
+        session("test-removed", "intruder", now + Duration::from_secs(60));
+
+        assert_eq!(session_user(auth, "test-valid").as_deref(), Some("guest"));
+        assert_eq!(session_user(auth, "test-expired"), None);
+        assert_eq!(session_user(auth, "test-removed"), None);
+        assert_eq!(session_user(auth, "test-unknown"), None);
+        let sessions = SESSIONS.lock().unwrap();
+        assert!(sessions.contains_key("test-valid"));
+        assert!(!sessions.contains_key("test-expired"));
+        assert!(!sessions.contains_key("test-removed"));
+    }
+
+    #[test]
+    fn login_backoff() {
+        let ip = |x: u8| LoginKey::Ip(IpAddr::from([192, 0, 2, x]));
+        let user = |x: &str| LoginKey::User(x.to_string());
+        let mut throttle = LoginThrottle::default();
+        let mut now = Instant::now();
+        let keys = [ip(1), user("admin")];
+
+        for _ in 0..FREE_LOGIN_FAILURES - 1 {
+            throttle.failed(&keys, now);
+            assert_eq!(throttle.retry_after(&keys, now), None);
+        }
+        throttle.failed(&keys, now);
+        assert_eq!(
+            throttle.retry_after(&keys, now),
+            Some(Duration::from_secs(1))
+        );
+        // both the address and the username are backing off
+        assert!(throttle.retry_after(&[ip(1), user("guest")], now).is_some());
+        assert!(throttle.retry_after(&[ip(2), user("admin")], now).is_some());
+        assert_eq!(throttle.retry_after(&[ip(2), user("guest")], now), None);
+
+        now += Duration::from_secs(1);
+        assert_eq!(throttle.retry_after(&keys, now), None);
+        throttle.failed(&keys, now);
+        assert_eq!(
+            throttle.retry_after(&keys, now),
+            Some(Duration::from_secs(2))
+        );
+        for _ in 0..20 {
+            throttle.failed(&keys, now);
+        }
+        assert_eq!(throttle.retry_after(&keys, now), Some(MAX_LOGIN_BACKOFF));
+
+        throttle.succeeded(&[user("admin")]);
+        assert_eq!(throttle.retry_after(&[ip(2), user("admin")], now), None);
+        assert!(throttle.retry_after(&keys, now).is_some());
+
+        // failure counts are forgotten after a quiet period
+        now += MAX_LOGIN_BACKOFF;
+        throttle.failed(&[ip(3)], now);
+        assert_eq!(throttle.retry_after(&keys, now), None);
+        throttle.failed(&keys, now);
+        assert_eq!(throttle.retry_after(&keys, now), None);
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::Cursor;
+
+use axum::{
+    body::{Body, BoxBody, Bytes, HttpBody},
+    extract::Path,
+    response::Response,
+    Extension,
+};
+use axum_util::errors::{ApiError, ApiResult};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use log::error;
+
+use crate::{
+    blob::Blob,
+    config::CONFIG,
+    frames::{self, DebugFrame},
+};
+
+use super::auth::AuthUser;
+
+const BOUNDARY: &str = "frame";
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// 5x7 glyphs, one row per byte with the leftmost pixel in bit 4. Text is uppercased before drawing.
+const FONT: &[(char, [u8; 7])] = &[
+    ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+    ('<', [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02]),
+    ('>', [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08]),
+];
+
+/// Draws a line of text on a dark background, unknown characters are left blank
+fn draw_text(image: &mut RgbImage, x: u32, y: u32, scale: u32, text: &str, color: Rgb<u8>) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let width = advance * text.chars().count() as u32 + scale;
+    let height = (GLYPH_HEIGHT + 2) * scale;
+    for py in y..(y + height).min(image.height()) {
+        for px in x..(x + width).min(image.width()) {
+            image.put_pixel(px, py, Rgb([0, 0, 0]));
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        let Some((_, rows)) = FONT.iter().find(|(x, _)| *x == c.to_ascii_uppercase()) else {
+            continue;
+        };
+        let left = x + scale + i as u32 * advance;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = left + column * scale + dx;
+                        let py = y + (row as u32 + 1) * scale + dy;
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn draw_box(image: &mut RgbImage, blob: &Blob, color: Rgb<u8>) {
+    let right = blob.x + blob.width - 1;
+    let bottom = blob.y + blob.height - 1;
+    for x in blob.x..=right {
+        image.put_pixel(x, blob.y, color);
+        image.put_pixel(x, bottom, color);
+    }
+    for y in blob.y..=bottom {
+        image.put_pixel(blob.x, y, color);
+        image.put_pixel(right, y, color);
+    }
+}
+
+fn color(ok: bool) -> Rgb<u8> {
+    if ok {
+        Rgb([0, 255, 0])
+    } else {
+        Rgb([255, 64, 64])
+    }
+}
+
+/// The diff image with the detector's values and objects drawn on it, green where a threshold passes and red where it doesn't
+fn render(frame: &DebugFrame) -> anyhow::Result<Vec<u8>> {
+    let mut image = frame.image.clone();
+    for blob in &frame.blobs {
+        draw_box(&mut image, blob, Rgb([255, 255, 0]));
+    }
+    let scale = (image.width() / 320).max(1);
+    let line = (GLYPH_HEIGHT + 3) * scale;
+    draw_text(
+        &mut image,
+        0,
+        0,
+        scale,
+        &format!(
+            "change {:.02} min {:.02} max {:.02}",
+            frame.change, frame.change_minimum, frame.change_maximum
+        ),
+        color(frame.change > frame.change_minimum && frame.change < frame.change_maximum),
+    );
+    draw_text(
+        &mut image,
+        0,
+        line,
+        scale,
+        &format!(
+            "stddev {:.02} min {:.02}",
+            frame.stddev, frame.stddev_minimum
+        ),
+        color(frame.stddev > frame.stddev_minimum),
+    );
+    draw_text(
+        &mut image,
+        0,
+        line * 2,
+        scale,
+        frame.state,
+        Rgb([255, 255, 255]),
+    );
+    let mut out = vec![];
+    DynamicImage::ImageRgb8(image).write_to(&mut Cursor::new(&mut out), ImageFormat::Jpeg)?;
+    Ok(out)
+}
+
+/// Streams what the motion detector sees as MJPEG, for tuning `change_minimum` and `stddev_minimum`
+pub async fn stream(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    user.camera(&CONFIG.get(), &name)?
+        .motion_detection
+        .as_ref()
+        .ok_or(ApiError::NotFound)?;
+    let receiver = frames::subscribe_debug(&name);
+
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        let frame = loop {
+            receiver.changed().await.ok()?;
+            if let Some(frame) = receiver.borrow_and_update().clone() {
+                break frame;
+            }
+        };
+        let jpeg = match tokio::task::spawn_blocking(move || render(&frame)).await {
+            Ok(Ok(x)) => x,
+            Ok(Err(e)) => return Some((Err(e), receiver)),
+            Err(e) => return Some((Err(e.into()), receiver)),
+        };
+        let mut part = format!(
+            "--{BOUNDARY}\r\ncontent-type: image/jpeg\r\ncontent-length: {}\r\n\r\n",
+            jpeg.len()
+        )
+        .into_bytes();
+        part.extend(jpeg);
+        part.extend(b"\r\n");
+        Some((Ok(Bytes::from(part)), receiver))
+    });
+
+    Ok(Response::builder()
+        .header(
+            "content-type",
+            format!("multipart/x-mixed-replace; boundary={BOUNDARY}"),
+        )
+        .header("cache-control", "no-store")
+        .body(BoxBody::new(Body::wrap_stream(stream).map_err(|e| {
+            error!("debug stream error: {e:?}");
+            axum::Error::new(e)
+        })))?)
+}
@@ -1,22 +1,47 @@
-use axum::{extract::Path, headers::Range, response::Response, TypedHeader};
+use axum::{
+    body::{BoxBody, Bytes, Full, HttpBody},
+    extract::Path,
+    headers::Range,
+    response::Response,
+    Extension, TypedHeader,
+};
 use axum_util::errors::{ApiError, ApiResult};
 
-use crate::config::CONFIG;
+use crate::{config::CONFIG, event::event_camera};
 
-use super::get_video::stream_video;
+use super::{auth::AuthUser, get_video::stream_video};
 
 pub async fn get_event(
     Path(filename): Path<String>,
+    Extension(user): Extension<AuthUser>,
     range: Option<TypedHeader<Range>>,
 ) -> ApiResult<Response> {
-    if !filename.ends_with(".mp4") {
+    let config = CONFIG.get();
+    let is_preview = filename.ends_with(".jpg");
+    if !filename.ends_with(".mp4") && !is_preview {
         return Err(ApiError::NotFound);
     }
-    let mut video_path = CONFIG.event_dir.clone();
+    match event_camera(&config, &filename) {
+        Some(camera) if user.can_access(&config, camera) => (),
+        None if user.allowed_cameras(&config).is_none() => (),
+        _ => return Err(ApiError::NotFound),
+    }
+    let mut video_path = config.event_dir.clone();
     if filename.contains("/") || filename.contains("..") {
         return Err(ApiError::NotFound);
     }
     video_path.push(filename);
 
+    if is_preview {
+        if !tokio::fs::try_exists(&video_path).await? {
+            return Err(ApiError::NotFound);
+        }
+        let preview = tokio::fs::read(&video_path).await?;
+        return Ok(Response::builder()
+            .header("content-type", "image/jpeg")
+            .body(BoxBody::new::<_>(
+                Full::new(Bytes::from(preview)).map_err(|_| unreachable!()),
+            ))?);
+    }
     stream_video(&video_path, range).await
 }
@@ -5,7 +5,7 @@ use axum::{
     extract::Path,
     headers::{ContentRange, HeaderMapExt, Range},
     response::Response,
-    TypedHeader,
+    Extension, TypedHeader,
 };
 use axum_util::errors::{ApiError, ApiResult};
 use log::error;
@@ -13,7 +13,9 @@ use serde::Deserialize;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
 
-use crate::config::{CameraMode, CONFIG};
+use crate::config::CONFIG;
+
+use super::auth::AuthUser;
 
 #[derive(Deserialize)]
 pub struct VideoPath {
@@ -85,16 +87,13 @@ pub async fn stream_video(
 
 pub async fn get_video(
     Path(VideoPath { name, filename }): Path<VideoPath>,
+    Extension(user): Extension<AuthUser>,
     range: Option<TypedHeader<Range>>,
 ) -> ApiResult<Response> {
-    let Some(camera) = CONFIG.cameras.get(&name) else {
-        return Err(ApiError::NotFound);
-    };
-    if camera.mode == CameraMode::Disable {
-        return Err(ApiError::NotFound);
-    }
+    let config = CONFIG.get();
+    user.camera(&config, &name)?;
 
-    let mut video_path = CONFIG.recording_dir.clone();
+    let mut video_path = config.recording_dir.clone();
     video_path.push(&name);
     if filename.contains("/") || filename.contains("..") {
         return Err(ApiError::NotFound);
@@ -1,31 +1,60 @@
 use axum::{
     body::{BoxBody, Bytes, Full, HttpBody},
     response::Response,
+    Extension,
 };
 use axum_util::errors::ApiResult;
 use typed_html::elements::FlowContent;
 use typed_html::{dom::DOMTree, html, text};
 
-use crate::config::{CameraMode, CONFIG};
+use crate::{
+    arming,
+    config::{CameraMode, CONFIG},
+};
+
+use super::auth::AuthUser;
 
 #[allow(unused_braces)]
-pub async fn list_camera() -> ApiResult<Response> {
+pub async fn list_camera(Extension(user): Extension<AuthUser>) -> ApiResult<Response> {
+    let config = CONFIG.get();
     let mut out = Vec::<Box<dyn FlowContent<String>>>::new();
 
     out.push(html! {
         <div>
-            <a href={format!("{}events", CONFIG.web_base)}>{ text!("Events") }</a>
+            <a href={format!("{}events", config.web_base)}>{ text!("Events") }</a>
         </div>
     });
-    for (name, camera) in &CONFIG.cameras {
-        if camera.mode == CameraMode::Disable {
+    if config.auth.is_some() {
+        out.push(html! {
+            <div>
+                <a href={format!("{}logout", config.web_base)}>{ text!("Logout") }</a>
+            </div>
+        });
+    }
+    if let Some(mode) = arming::current() {
+        out.push(html! {
+            <div>
+                { text!("Mode: {}", mode) }
+            </div>
+        });
+    }
+    for (name, camera) in &config.cameras {
+        if camera.mode == CameraMode::Disable || !user.can_access(&config, name) {
             continue;
         }
+        let zones_link = camera.motion_detection.as_ref().map(
+            |_| -> Box<dyn FlowContent<String>> {
+                html! {
+                    <a href={format!("{}camera/{name}/zones", config.web_base)} style="margin-left: 30px">{ text!("Zones") }</a>
+                }
+            },
+        );
         out.push(html! {
             <div>
-                {text!("{}: ", name)} <a href={format!("{}camera/{name}/live_hls", CONFIG.web_base)}>{ text!("Live (HLS)") }</a>
-                <a href={format!("{}camera/{name}/live_mp4", CONFIG.web_base)} style="margin-left: 30px">{ text!("Live (MP4)") }</a>
-                <a href={format!("{}camera/{name}", CONFIG.web_base)} style="margin-left: 30px">{ text!("Recordings") }</a>
+                {text!("{}: ", name)} <a href={format!("{}camera/{name}/live_hls", config.web_base)}>{ text!("Live (HLS)") }</a>
+                <a href={format!("{}camera/{name}/live_mp4", config.web_base)} style="margin-left: 30px">{ text!("Live (MP4)") }</a>
+                <a href={format!("{}camera/{name}", config.web_base)} style="margin-left: 30px">{ text!("Recordings") }</a>
+                { zones_link }
             </div>
         });
     }
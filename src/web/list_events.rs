@@ -1,15 +1,23 @@
 use axum::{
     body::{BoxBody, Bytes, Full, HttpBody},
     response::Response,
+    Extension,
 };
-use axum_util::errors::ApiResult;
+use axum_util::errors::{ApiError, ApiResult};
 use typed_html::elements::FlowContent;
 use typed_html::{dom::DOMTree, html, text};
 
-use crate::{config::CONFIG, event::EventMetadata};
+use crate::{
+    config::CONFIG,
+    event::StoredEvent,
+    index::{self, EventFilter},
+};
+
+use super::auth::AuthUser;
 
 #[allow(unused_braces)]
-pub async fn list_events() -> ApiResult<Response> {
+pub async fn list_events(Extension(user): Extension<AuthUser>) -> ApiResult<Response> {
+    let config = CONFIG.get();
     let mut out = Vec::<Box<dyn FlowContent<String>>>::new();
 
     out.push(html! {
@@ -19,26 +27,20 @@ pub async fn list_events() -> ApiResult<Response> {
     });
     out.push(html! {
         <div>
-            <a href={&CONFIG.web_base}>{ text!("Home") }</a>
+            <a href={&config.web_base}>{ text!("Home") }</a>
         </div>
     });
-    let mut read_dir = tokio::fs::read_dir(&CONFIG.event_dir).await?;
-    let mut entries = vec![];
-    while let Some(entry) = read_dir.next_entry().await? {
-        let filename = entry.file_name().to_string_lossy().into_owned();
-        if !filename.ends_with(".mp4") {
-            continue;
-        }
-        let metadata_file = CONFIG.event_dir.join(&filename).with_extension("json");
-        let parsed: EventMetadata =
-            serde_json::from_str(&tokio::fs::read_to_string(&metadata_file).await?)?;
-        entries.push((parsed, filename));
-    }
-    entries.sort_by_key(|x| x.0.when);
-    for (metadata, filename) in entries {
+    let filter = EventFilter {
+        cameras: user.allowed_cameras(&config),
+        ..Default::default()
+    };
+    let (_, events) = index::query_events(filter, 0, None)
+        .await
+        .map_err(ApiError::Other)?;
+    for StoredEvent { filename, metadata } in events.into_iter().rev() {
         out.push(html! {
             <div>
-                <a href={format!("{}event/{filename}", CONFIG.web_base)}>{ text!("{}", filename) }</a>
+                <a href={format!("{}event/{filename}", config.web_base)}>{ text!("{}", filename) }</a>
                 {text!(": {} score, {} frames in {}", metadata.total_score, metadata.end_stream_frame_number.saturating_sub(metadata.start_stream_frame_number), metadata.camera) }
             </div>
         });
@@ -2,25 +2,23 @@ use axum::{
     body::{BoxBody, Bytes, Full, HttpBody},
     extract::Path,
     response::Response,
+    Extension,
 };
 use axum_util::errors::{ApiError, ApiResult};
-use chrono::{DateTime, Utc};
 use typed_html::elements::FlowContent;
 use typed_html::{dom::DOMTree, html, text};
 
-use crate::config::{CameraMode, CONFIG};
+use crate::{config::CONFIG, index, recording::Recording};
 
-#[allow(unused_braces)]
-pub async fn list_recording(Path(name): Path<String>) -> ApiResult<Response> {
-    let Some(camera) = CONFIG.cameras.get(&name) else {
-        return Err(ApiError::NotFound);
-    };
-    if camera.mode == CameraMode::Disable {
-        return Err(ApiError::NotFound);
-    }
+use super::auth::AuthUser;
 
-    let mut recording_dir = CONFIG.recording_dir.clone();
-    recording_dir.push(&name);
+#[allow(unused_braces)]
+pub async fn list_recording(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    user.camera(&config, &name)?;
 
     let mut out = Vec::<Box<dyn FlowContent<String>>>::new();
 
@@ -31,32 +29,22 @@ pub async fn list_recording(Path(name): Path<String>) -> ApiResult<Response> {
     });
     out.push(html! {
         <div>
-            <a href={&CONFIG.web_base}>{ text!("Home") }</a>
+            <a href={&config.web_base}>{ text!("Home") }</a>
         </div>
     });
     out.push(html! {
         <div>
-            <a href={format!("{}camera/{name}/live_hls", CONFIG.web_base)}>{ text!("Live (HLS)") }</a>
-            <a href={format!("{}camera/{name}/live_mp4", CONFIG.web_base)} style="margin-left: 30px">{ text!("Live (MP4)") }</a>
+            <a href={format!("{}camera/{name}/live_hls", config.web_base)}>{ text!("Live (HLS)") }</a>
+            <a href={format!("{}camera/{name}/live_mp4", config.web_base)} style="margin-left: 30px">{ text!("Live (MP4)") }</a>
         </div>
     });
-    let mut entries = vec![];
-    if tokio::fs::try_exists(&recording_dir).await? {
-        let mut read_dir = tokio::fs::read_dir(&recording_dir).await?;
-        while let Some(entry) = read_dir.next_entry().await? {
-            let filename = entry.file_name().to_string_lossy().into_owned();
-            if !filename.ends_with(".mp4") {
-                continue;
-            }
-            let modified: DateTime<Utc> = entry.metadata().await?.modified()?.into();
-            entries.push((modified, filename));
-        }
-    }
-    entries.sort_by_key(|x| x.0);
-    for (modified, filename) in entries {
+    for Recording { end, filename, .. } in index::query_recordings(name.clone())
+        .await
+        .map_err(ApiError::Other)?
+    {
         out.push(html! {
             <div>
-                <a href={format!("{}camera/{name}/video/{filename}", CONFIG.web_base)}>{ text!("{} -> {}", modified, filename) }</a>
+                <a href={format!("{}camera/{name}/video/{filename}", config.web_base)}>{ text!("{} -> {}", end, filename) }</a>
             </div>
         });
     }
@@ -5,6 +5,7 @@ use axum::{
     body::{Body, BoxBody, Bytes, Full, HttpBody},
     extract::Path,
     response::Response,
+    Extension,
 };
 use axum_util::errors::{ApiError, ApiResult};
 use log::{error, info};
@@ -17,22 +18,28 @@ use tokio::{
 use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
-use crate::config::{CameraConfig, CameraMode, CONFIG};
+use crate::config::{CameraConfig, CONFIG};
+
+use super::auth::AuthUser;
 
 lazy_static::lazy_static! {
     static ref HLS: RwLock<HashMap<Uuid, Arc<Notify>>> = RwLock::new(HashMap::default());
 }
 
-async fn start_hls_manager(camera: &'static CameraConfig) -> ApiResult<Uuid> {
+async fn start_hls_manager(camera: CameraConfig) -> ApiResult<Uuid> {
     let uuid = Uuid::new_v4();
     let notify = Arc::new(Notify::new());
 
     HLS.write().await.insert(uuid, notify.clone());
 
-    let path = CONFIG.live_dir.join(uuid.to_string()).join("playlist.m3u8");
+    let path = CONFIG
+        .get()
+        .live_dir
+        .join(uuid.to_string())
+        .join("playlist.m3u8");
 
     tokio::spawn(async move {
-        if let Err(e) = hls_manager(uuid, camera, notify).await {
+        if let Err(e) = hls_manager(uuid, &camera, notify).await {
             error!("[{uuid}] HLS failed: {e:#}");
         }
     });
@@ -54,11 +61,12 @@ async fn start_hls_manager(camera: &'static CameraConfig) -> ApiResult<Uuid> {
 }
 
 async fn hls_manager(uuid: Uuid, camera: &CameraConfig, notify: Arc<Notify>) -> Result<()> {
-    let path = CONFIG.live_dir.join(uuid.to_string());
+    let config = CONFIG.get();
+    let path = config.live_dir.join(uuid.to_string());
     tokio::fs::create_dir_all(&path).await?;
     let playlist = path.join("playlist.m3u8");
     let mut args = vec![];
-    if CONFIG.force_tcp {
+    if config.force_tcp {
         args.extend(["-rtsp_transport", "tcp"]);
     }
     let rtsp = camera.rtsp.to_string();
@@ -76,7 +84,7 @@ async fn hls_manager(uuid: Uuid, camera: &CameraConfig, notify: Arc<Notify>) ->
         "1",
         playlist.to_str().unwrap(),
     ]);
-    let mut process = Command::new(&CONFIG.ffmpeg_bin)
+    let mut process = Command::new(&config.ffmpeg_bin)
         .args(args)
         .stderr(Stdio::piped())
         .spawn()?;
@@ -112,15 +120,14 @@ async fn hls_manager(uuid: Uuid, camera: &CameraConfig, notify: Arc<Notify>) ->
     Ok(())
 }
 
-pub async fn page(Path(name): Path<String>) -> ApiResult<Response> {
-    let Some(camera) = CONFIG.cameras.get(&name) else {
-        return Err(ApiError::NotFound);
-    };
-    if camera.mode == CameraMode::Disable {
-        return Err(ApiError::NotFound);
-    }
+pub async fn page(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let camera = user.camera(&config, &name)?;
 
-    let uuid = start_hls_manager(camera).await?;
+    let uuid = start_hls_manager(camera.clone()).await?;
 
     let total = format!(
         r#"
@@ -160,7 +167,7 @@ pub async fn page(Path(name): Path<String>) -> ApiResult<Response> {
         </body>
         </html>
     "#,
-        CONFIG.web_base
+        config.web_base
     );
 
     Ok(Response::builder()
@@ -179,13 +186,10 @@ pub struct StreamPath {
 
 pub async fn stream(
     Path(StreamPath { name, uuid, path }): Path<StreamPath>,
+    Extension(user): Extension<AuthUser>,
 ) -> ApiResult<Response> {
-    let Some(camera) = CONFIG.cameras.get(&name) else {
-        return Err(ApiError::NotFound);
-    };
-    if camera.mode == CameraMode::Disable {
-        return Err(ApiError::NotFound);
-    }
+    let config = CONFIG.get();
+    user.camera(&config, &name)?;
 
     {
         let hls = HLS.read().await;
@@ -197,7 +201,7 @@ pub async fn stream(
     if path.contains("/") || path.contains("..") {
         return Err(ApiError::BadRequest("malformed path".to_string()));
     }
-    let filepath = CONFIG.live_dir.join(uuid.to_string()).join(&path);
+    let filepath = config.live_dir.join(uuid.to_string()).join(&path);
 
     if !tokio::fs::try_exists(&filepath).await? {
         return Err(ApiError::NotFound);
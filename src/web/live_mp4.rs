@@ -8,8 +8,9 @@ use axum::{
     body::{Body, BoxBody, Bytes, Full, HttpBody},
     extract::Path,
     response::Response,
+    Extension,
 };
-use axum_util::errors::{ApiError, ApiResult};
+use axum_util::errors::ApiResult;
 use futures::Stream;
 use log::error;
 use pin_project::{pin_project, pinned_drop};
@@ -19,11 +20,14 @@ use tokio::{
 };
 use tokio_util::io::ReaderStream;
 
-use crate::config::{CameraConfig, CameraMode, CONFIG};
+use crate::config::{CameraConfig, CONFIG};
+
+use super::auth::AuthUser;
 
 async fn run_mp4(camera: &CameraConfig) -> ApiResult<(Child, ChildStdout)> {
+    let config = CONFIG.get();
     let mut args = vec![];
-    if CONFIG.force_tcp {
+    if config.force_tcp {
         args.extend(["-rtsp_transport", "tcp"]);
     }
     let rtsp = camera.rtsp.to_string();
@@ -40,7 +44,7 @@ async fn run_mp4(camera: &CameraConfig) -> ApiResult<(Child, ChildStdout)> {
         "copy",
         "-",
     ]);
-    let mut process = Command::new(&CONFIG.ffmpeg_bin)
+    let mut process = Command::new(&config.ffmpeg_bin)
         .args(args)
         .stderr(Stdio::piped())
         .stdout(Stdio::piped())
@@ -57,13 +61,12 @@ async fn run_mp4(camera: &CameraConfig) -> ApiResult<(Child, ChildStdout)> {
     Ok((process, stdout))
 }
 
-pub async fn page(Path(name): Path<String>) -> ApiResult<Response> {
-    let Some(camera) = CONFIG.cameras.get(&name) else {
-        return Err(ApiError::NotFound);
-    };
-    if camera.mode == CameraMode::Disable {
-        return Err(ApiError::NotFound);
-    }
+pub async fn page(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    user.camera(&config, &name)?;
 
     let total = format!(
         r#"
@@ -89,7 +92,7 @@ pub async fn page(Path(name): Path<String>) -> ApiResult<Response> {
         </body>
         </html>
     "#,
-        CONFIG.web_base
+        config.web_base
     );
 
     Ok(Response::builder()
@@ -124,13 +127,12 @@ impl PinnedDrop for FfmpegStream {
     }
 }
 
-pub async fn stream(Path(name): Path<String>) -> ApiResult<Response> {
-    let Some(camera) = CONFIG.cameras.get(&name) else {
-        return Err(ApiError::NotFound);
-    };
-    if camera.mode == CameraMode::Disable {
-        return Err(ApiError::NotFound);
-    }
+pub async fn stream(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let camera = user.camera(&config, &name)?;
 
     let (process, stdout) = run_mp4(camera).await?;
 
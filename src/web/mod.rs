@@ -1,9 +1,12 @@
 use std::sync::Arc;
 
-use axum::{routing, Router};
+use axum::{middleware, routing, Router};
 use axum_util::logger::{LoggerConfig, LoggerLayer};
 use log::Level;
 
+mod api;
+pub mod auth;
+mod debug;
 mod get_event;
 mod get_video;
 mod list_camera;
@@ -11,6 +14,8 @@ mod list_events;
 mod list_recording;
 mod live_hls;
 mod live_mp4;
+pub mod tls;
+mod zones;
 
 async fn health() {}
 
@@ -37,7 +42,22 @@ pub fn route() -> Router {
             "/camera/:name/live_mp4/stream.mp4",
             routing::get(live_mp4::stream),
         )
+        .route(
+            "/camera/:name/zones",
+            routing::get(zones::page).post(zones::save),
+        )
+        .route("/camera/:name/zones/frame.jpg", routing::get(zones::frame))
+        .route(
+            "/camera/:name/zones/heatmap.jpg",
+            routing::get(zones::heatmap),
+        )
+        .route("/camera/:name/zones/mask.png", routing::post(zones::mask))
+        .route("/camera/:name/debug.mjpeg", routing::get(debug::stream))
         .route("/health", routing::get(health))
+        .route("/login", routing::get(auth::login_page).post(auth::login))
+        .route("/logout", routing::get(auth::logout))
+        .nest("/api/v1", api::route())
+        .layer(middleware::from_fn(auth::require_auth))
         .layer(LoggerLayer::new(LoggerConfig {
             log_level_filter: Arc::new(|x| {
                 if x == "/health" {
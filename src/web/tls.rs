@@ -0,0 +1,70 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::Host,
+    http::Uri,
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use log::{error, info};
+
+use crate::config::CONFIG;
+
+/// How often the certificate and key are checked for modifications
+const CERT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+fn modified(cert: &Path, key: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|x| x.modified()).ok();
+    (modified(cert), modified(key))
+}
+
+/// Reloads `tls` whenever the certificate or key file changes, e.g. after a renewal. Runs forever.
+pub async fn watch(tls: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    let mut last_modified = modified(&cert, &key);
+    loop {
+        tokio::time::sleep(CERT_POLL_INTERVAL).await;
+        let current = modified(&cert, &key);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        match tls.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => info!("reloaded TLS certificate '{}'", cert.display()),
+            Err(e) => error!(
+                "failed to reload TLS certificate '{}', keeping the previous one: {e}",
+                cert.display()
+            ),
+        }
+    }
+}
+
+async fn redirect(Host(host): Host, uri: Uri) -> Response {
+    // drop any port from the Host header, HTTPS is served on the port of `web_bind`
+    let host = match host.rfind(':') {
+        // a colon followed by ']' is part of an IPv6 literal, not a port
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => &host,
+    };
+    let port = match CONFIG.get().web_bind.port() {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+    Redirect::permanent(&format!("https://{host}{port}{path}")).into_response()
+}
+
+/// Serves redirects from plain HTTP to HTTPS on `CONFIG.http_redirect_bind`
+pub async fn serve_redirect() -> anyhow::Result<()> {
+    let Some(bind) = CONFIG.get().http_redirect_bind else {
+        return Ok(());
+    };
+    info!("redirecting HTTP @ {bind} to HTTPS");
+    axum::Server::bind(&bind)
+        .serve(Router::new().fallback(redirect).into_make_service())
+        .await?;
+    Ok(())
+}
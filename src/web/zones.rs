@@ -0,0 +1,478 @@
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use axum::{
+    body::{BoxBody, Bytes, Full, HttpBody},
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_util::errors::{ApiError, ApiResult};
+use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+use indexmap::IndexMap;
+
+use crate::{
+    config::{self, Config, MotionDetectionConfig, CONFIG},
+    frames,
+    modect::MotionDetector,
+    zone::{self, ZoneConfig},
+};
+
+use super::auth::AuthUser;
+
+fn motion_detection<'a>(
+    config: &'a Config,
+    user: &AuthUser,
+    name: &str,
+) -> ApiResult<&'a MotionDetectionConfig> {
+    user.camera(config, name)?
+        .motion_detection
+        .as_ref()
+        .ok_or(ApiError::NotFound)
+}
+
+fn image_response(
+    image: DynamicImage,
+    format: ImageFormat,
+    content_type: &str,
+) -> ApiResult<Response> {
+    let mut out = vec![];
+    image
+        .write_to(&mut Cursor::new(&mut out), format)
+        .map_err(|e| ApiError::Other(e.into()))?;
+    Ok(Response::builder()
+        .header("content-type", content_type)
+        .header("cache-control", "no-store")
+        .body(BoxBody::new::<_>(
+            Full::new(Bytes::from(out)).map_err(|_| unreachable!()),
+        ))?)
+}
+
+pub async fn frame(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    motion_detection(&CONFIG.get(), &user, &name)?;
+    let (_, frame) = frames::latest(&name)
+        .await
+        .ok_or_else(|| ApiError::Other(anyhow!("no frames received from {name}")))?;
+    image_response(
+        DynamicImage::ImageRgb8((*frame).clone()),
+        ImageFormat::Jpeg,
+        "image/jpeg",
+    )
+}
+
+pub async fn heatmap(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    motion_detection(&CONFIG.get(), &user, &name)?;
+    let (Some(previous), frame) = frames::latest(&name)
+        .await
+        .ok_or_else(|| ApiError::Other(anyhow!("no frames received from {name}")))?
+    else {
+        return Err(ApiError::NotFound);
+    };
+    let heatmap = MotionDetector {}.heatmap(&previous, &frame);
+    image_response(
+        DynamicImage::ImageRgb8(heatmap),
+        ImageFormat::Jpeg,
+        "image/jpeg",
+    )
+}
+
+fn validate(
+    zones: &IndexMap<String, ZoneConfig>,
+    motion_detection: &MotionDetectionConfig,
+) -> ApiResult<()> {
+    for (name, zone) in zones {
+        if let Some((field, message)) =
+            zone::validate(zone, motion_detection.width, motion_detection.height)
+                .into_iter()
+                .next()
+        {
+            return Err(ApiError::BadRequest(format!("{name}.{field}: {message}")));
+        }
+    }
+    Ok(())
+}
+
+/// Rasterizes the posted zones like the motion detector would, as a mask in the format of `mask_file`
+pub async fn mask(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+    Json(zones): Json<IndexMap<String, ZoneConfig>>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let motion_detection = motion_detection(&config, &user, &name)?;
+    validate(&zones, motion_detection)?;
+    let (width, height) = (motion_detection.width, motion_detection.height);
+    let mask_file = motion_detection.config.mask_file.clone();
+    let mask = tokio::task::spawn_blocking(move || {
+        let mask_image = match &mask_file {
+            Some(mask_file) => Some(image::open(mask_file)?.to_luma8()),
+            None => None,
+        };
+        if zones.is_empty() {
+            return anyhow::Ok(
+                mask_image.unwrap_or_else(|| GrayImage::from_pixel(width, height, Luma([0]))),
+            );
+        }
+        let zones: Vec<GrayImage> = zones
+            .values()
+            .map(|zone| zone.rasterize(width, height, mask_image.as_ref()))
+            .collect();
+        Ok(GrayImage::from_fn(width, height, |x, y| {
+            let detected = zones.iter().any(|zone| zone.get_pixel(x, y).0[0] == 0);
+            Luma([if detected { 0 } else { 255 }])
+        }))
+    })
+    .await
+    .map_err(|e| ApiError::Other(e.into()))?
+    .map_err(ApiError::Other)?;
+    image_response(
+        DynamicImage::ImageLuma8(mask),
+        ImageFormat::Png,
+        "image/png",
+    )
+}
+
+/// Saves the posted zones to the zones file, where they replace those of the camera in the config file, only allowed with `auth` configured and for users with access to every camera
+pub async fn save(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+    Json(zones): Json<IndexMap<String, ZoneConfig>>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let motion_detection = motion_detection(&config, &user, &name)?;
+    if !user.is_admin(&config) {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "editing zones requires auth and access to every camera",
+        )
+            .into_response());
+    }
+    validate(&zones, motion_detection)?;
+    tokio::task::spawn_blocking(move || config::save_zones(&name, &zones))
+        .await
+        .map_err(|e| ApiError::Other(e.into()))?
+        .map_err(|e| ApiError::BadRequest(format!("{e:#}")))?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn page(
+    Path(name): Path<String>,
+    Extension(user): Extension<AuthUser>,
+) -> ApiResult<Response> {
+    let config = CONFIG.get();
+    let motion_detection = motion_detection(&config, &user, &name)?;
+    // embedded in a script, so a zone named `</script>` can't end it early
+    let zones = serde_json::to_string(&motion_detection.config.zones)
+        .map_err(|e| ApiError::Other(e.into()))?
+        .replace("</", "<\\/");
+    let (width, height) = (motion_detection.width, motion_detection.height);
+
+    let total = format!(
+        r#"
+        <html>
+        <head>
+            <title>{name} Zones</title>
+            <style>
+            * {{
+                font-size: 24px
+            }}
+            #view {{
+                position: relative;
+                max-width: 100%;
+                aspect-ratio: {width} / {height};
+            }}
+            #view img, #view canvas {{
+                position: absolute;
+                width: 100%;
+                height: 100%;
+            }}
+            #heatmap, #mask {{
+                opacity: 0.5;
+                display: none;
+            }}
+            </style>
+        </head>
+        <body>
+            <div>
+                {name} <a href="{0}">Home</a> <a href="{0}camera/{name}">Recordings</a> <a href="{0}camera/{name}/debug.mjpeg">Detector debug</a>
+            </div>
+            <div>
+                <select id="zone"></select>
+                <button onclick="addZone()">Add Zone</button>
+                <button onclick="deleteZone()">Delete Zone</button>
+                <button onclick="newPolygon('include')">New Include</button>
+                <button onclick="newPolygon('exclude')">New Exclude</button>
+                <button onclick="undoPoint()">Undo Point</button>
+                <button onclick="deletePolygon()">Delete Polygon</button>
+            </div>
+            <div>
+                <label><input type="checkbox" id="show_heatmap" onchange="toggleHeatmap()"> Heatmap</label>
+                <label><input type="checkbox" id="show_mask" onchange="toggleMask()"> Mask</label>
+                <button onclick="save()">Save</button>
+                <span id="message"></span>
+            </div>
+            <div id="view">
+                <img id="frame" src="zones/frame.jpg">
+                <img id="heatmap">
+                <img id="mask">
+                <canvas id="canvas" width="{width}" height="{height}"></canvas>
+            </div>
+            <script>
+                const zones = {zones};
+                const canvas = document.getElementById("canvas");
+                const context = canvas.getContext("2d");
+                const select = document.getElementById("zone");
+                // the polygon new points are added to, as [zone, "include" | "exclude", index]
+                let active = null;
+                let dragging = null;
+                let heatmapTimer = null;
+
+                function message(text) {{
+                    document.getElementById("message").textContent = text;
+                }}
+
+                function zoneName() {{
+                    return select.value;
+                }}
+
+                function refreshZones() {{
+                    const selected = select.value;
+                    select.innerHTML = "";
+                    for (const name of Object.keys(zones)) {{
+                        const option = document.createElement("option");
+                        option.value = name;
+                        option.textContent = name;
+                        select.appendChild(option);
+                    }}
+                    if (selected in zones) {{
+                        select.value = selected;
+                    }}
+                    draw();
+                }}
+
+                function polygons(callback) {{
+                    for (const [name, zone] of Object.entries(zones)) {{
+                        for (const kind of ["include", "exclude"]) {{
+                            (zone[kind] || []).forEach((polygon, i) => callback(name, kind, i, polygon));
+                        }}
+                    }}
+                }}
+
+                function draw() {{
+                    context.clearRect(0, 0, canvas.width, canvas.height);
+                    polygons((name, kind, i, polygon) => {{
+                        const isActive = active && active[0] == name && active[1] == kind && active[2] == i;
+                        const color = kind == "include" ? "0, 200, 0" : "200, 0, 0";
+                        context.strokeStyle = `rgba(${{color}}, 1)`;
+                        context.fillStyle = `rgba(${{color}}, ${{name == zoneName() ? 0.3 : 0.1}})`;
+                        context.lineWidth = isActive ? 3 : 1;
+                        context.beginPath();
+                        polygon.forEach(([x, y]) => context.lineTo(x * canvas.width, y * canvas.height));
+                        context.closePath();
+                        context.fill();
+                        context.stroke();
+                        for (const [x, y] of polygon) {{
+                            context.fillStyle = `rgba(${{color}}, 1)`;
+                            context.fillRect(x * canvas.width - 3, y * canvas.height - 3, 6, 6);
+                        }}
+                    }});
+                }}
+
+                function position(event) {{
+                    const rect = canvas.getBoundingClientRect();
+                    const clamp = (x) => Math.min(1, Math.max(0, x));
+                    return [
+                        clamp((event.clientX - rect.left) / rect.width),
+                        clamp((event.clientY - rect.top) / rect.height),
+                    ];
+                }}
+
+                function nearestPoint([x, y]) {{
+                    const rect = canvas.getBoundingClientRect();
+                    let nearest = null;
+                    polygons((name, kind, i, polygon) => {{
+                        polygon.forEach((point, j) => {{
+                            const distance = Math.hypot((point[0] - x) * rect.width, (point[1] - y) * rect.height);
+                            if (distance < 10 && (!nearest || distance < nearest.distance)) {{
+                                nearest = {{ point, distance, polygon: [name, kind, i] }};
+                            }}
+                        }});
+                    }});
+                    return nearest;
+                }}
+
+                function activePolygon() {{
+                    if (!active || !(active[0] in zones)) {{
+                        return null;
+                    }}
+                    return (zones[active[0]][active[1]] || [])[active[2]] || null;
+                }}
+
+                canvas.addEventListener("mousedown", (event) => {{
+                    const point = position(event);
+                    const nearest = nearestPoint(point);
+                    if (nearest) {{
+                        dragging = nearest.point;
+                        active = nearest.polygon;
+                        select.value = active[0];
+                    }} else if (activePolygon()) {{
+                        activePolygon().push(point);
+                    }} else {{
+                        message("add a zone and polygon first");
+                    }}
+                    draw();
+                }});
+                canvas.addEventListener("mousemove", (event) => {{
+                    if (dragging) {{
+                        const [x, y] = position(event);
+                        dragging[0] = x;
+                        dragging[1] = y;
+                        draw();
+                    }}
+                }});
+                window.addEventListener("mouseup", () => {{
+                    if (dragging) {{
+                        dragging = null;
+                        refreshMask();
+                    }}
+                }});
+                select.addEventListener("change", () => {{
+                    active = null;
+                    draw();
+                }});
+
+                function addZone() {{
+                    const name = prompt("Zone name");
+                    if (!name || name in zones) {{
+                        return;
+                    }}
+                    zones[name] = {{ include: [], exclude: [] }};
+                    refreshZones();
+                    select.value = name;
+                    newPolygon("include");
+                }}
+
+                function deleteZone() {{
+                    if (zoneName() && confirm(`Delete zone ${{zoneName()}}?`)) {{
+                        delete zones[zoneName()];
+                        active = null;
+                        refreshZones();
+                        refreshMask();
+                    }}
+                }}
+
+                function newPolygon(kind) {{
+                    const zone = zones[zoneName()];
+                    if (!zone) {{
+                        message("add a zone first");
+                        return;
+                    }}
+                    zone[kind] = zone[kind] || [];
+                    zone[kind].push([]);
+                    active = [zoneName(), kind, zone[kind].length - 1];
+                    message("click to add points, drag points to move them");
+                    draw();
+                }}
+
+                function undoPoint() {{
+                    const polygon = activePolygon();
+                    if (polygon) {{
+                        polygon.pop();
+                        draw();
+                        refreshMask();
+                    }}
+                }}
+
+                function deletePolygon() {{
+                    if (activePolygon()) {{
+                        zones[active[0]][active[1]].splice(active[2], 1);
+                        active = null;
+                        draw();
+                        refreshMask();
+                    }}
+                }}
+
+                // polygons still being drawn are left out, the server rejects them
+                function completeZones() {{
+                    const out = {{}};
+                    for (const [name, zone] of Object.entries(zones)) {{
+                        out[name] = {{}};
+                        for (const kind of ["include", "exclude"]) {{
+                            out[name][kind] = (zone[kind] || []).filter((x) => x.length >= 3);
+                        }}
+                    }}
+                    return out;
+                }}
+
+                async function post(url, body) {{
+                    const response = await fetch(url, {{
+                        method: "POST",
+                        headers: {{ "content-type": "application/json" }},
+                        body: JSON.stringify(body),
+                    }});
+                    if (!response.ok) {{
+                        throw new Error(await response.text() || response.statusText);
+                    }}
+                    return response;
+                }}
+
+                async function refreshMask() {{
+                    if (!document.getElementById("show_mask").checked) {{
+                        return;
+                    }}
+                    try {{
+                        const response = await post("zones/mask.png", completeZones());
+                        const mask = document.getElementById("mask");
+                        URL.revokeObjectURL(mask.src);
+                        mask.src = URL.createObjectURL(await response.blob());
+                    }} catch (e) {{
+                        message(`failed to preview mask: ${{e.message}}`);
+                    }}
+                }}
+
+                function toggleMask() {{
+                    const show = document.getElementById("show_mask").checked;
+                    document.getElementById("mask").style.display = show ? "block" : "none";
+                    refreshMask();
+                }}
+
+                function toggleHeatmap() {{
+                    const show = document.getElementById("show_heatmap").checked;
+                    const heatmap = document.getElementById("heatmap");
+                    heatmap.style.display = show ? "block" : "none";
+                    clearInterval(heatmapTimer);
+                    if (show) {{
+                        heatmapTimer = setInterval(() => heatmap.src = `zones/heatmap.jpg?${{Date.now()}}`, 500);
+                    }}
+                }}
+
+                async function save() {{
+                    try {{
+                        await post("zones", completeZones());
+                        message("saved, the detector picks up the change on the next config reload");
+                    }} catch (e) {{
+                        message(`failed to save: ${{e.message}}`);
+                    }}
+                }}
+
+                refreshZones();
+            </script>
+        </body>
+        </html>
+    "#,
+        config.web_base
+    );
+
+    Ok(Response::builder()
+        .header("content-type", "text/html")
+        .body(BoxBody::new::<_>(
+            Full::new(Bytes::from(total)).map_err(|_| unreachable!()),
+        ))?)
+}
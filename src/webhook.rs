@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use log::warn;
+use reqwest::{Client, Response, StatusCode};
+use serde::Serialize;
+use sha2::Sha256;
+use url::Url;
+
+use crate::{
+    alert::{Alert, AlertState, Notifier},
+    classify::Label,
+    config::WebhookConfig,
+};
+
+lazy_static::lazy_static! {
+    static ref CLIENT: Client = Client::new();
+}
+
+/// Header carrying the hex encoded HMAC-SHA256 of the request body, if a secret is configured
+const SIGNATURE_HEADER: &str = "X-Rmr-Signature";
+/// Delay before the first retry, doubled for every retry after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    camera: &'a str,
+    state: AlertState,
+    time: DateTime<Utc>,
+    total_score: f64,
+    start_stream_frame_number: u64,
+    end_stream_frame_number: u64,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    zones: &'a [String],
+    labels: &'a [Label],
+    event_url: Option<Url>,
+    preview_url: Option<Url>,
+    /// number of events in the window, if this alert ended an aggregating cooldown. Includes the event whose alert started the window.
+    aggregated_events: Option<usize>,
+}
+
+/// Value of `SIGNATURE_HEADER` for `body`
+fn signature(secret: &str, body: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(body);
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// Client errors won't be fixed by retrying, except for rate limiting
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+pub struct Webhook {
+    config: WebhookConfig,
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self { config }
+    }
+
+    async fn send(&self, body: &[u8]) -> Result<Response> {
+        let mut request = CLIENT
+            .post(self.config.url.clone())
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .header("content-type", "application/json")
+            .body(body.to_vec());
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        if let Some(secret) = &self.config.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, body)?);
+        }
+
+        Ok(request.send().await?)
+    }
+
+    async fn alert_event(&self, alert: &Alert) -> Result<()> {
+        let payload = WebhookPayload {
+            camera: &alert.camera,
+            state: alert.state,
+            time: alert.time,
+            total_score: alert.event.total_score,
+            start_stream_frame_number: alert.event.start_stream_frame_number,
+            end_stream_frame_number: alert.event.end_stream_frame_number,
+            start_time: alert.event.start_time(),
+            end_time: alert.event.end_time(),
+            zones: &alert.event.zones,
+            labels: &alert.labels,
+            event_url: alert.event_url(),
+            preview_url: alert.preview_url(),
+            aggregated_events: alert.aggregate.map(|x| x.events),
+        };
+        let body = serde_json::to_vec(&payload)?;
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let e = match self.send(&body).await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let e = anyhow!(
+                        "HTTP status {status}:\n{}",
+                        response.text().await.unwrap_or_default()
+                    );
+                    if !retryable(status) {
+                        return Err(e);
+                    }
+                    e
+                }
+                Err(e) => e,
+            };
+            if attempt >= self.config.retries {
+                return Err(e);
+            }
+            attempt += 1;
+            warn!(
+                "{}: webhook to {} failed, retry {attempt}/{} in {backoff:?}: {e:#}",
+                alert.camera, self.config.url, self.config.retries
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.alert_event(alert))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_known_vector() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?").unwrap(),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn retry_decision() {
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::GATEWAY_TIMEOUT,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            assert!(retryable(status), "{status}");
+        }
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+            StatusCode::GONE,
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ] {
+            assert!(!retryable(status), "{status}");
+        }
+    }
+}
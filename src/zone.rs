@@ -0,0 +1,141 @@
+use image::{GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+
+/// A point in normalized coordinates, `[0, 0]` is the top left and `[1, 1]` the bottom right of the frame
+pub type Point = [f64; 2];
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ZoneConfig {
+    /// Polygons the zone covers, the whole frame if empty
+    #[serde(default)]
+    pub include: Vec<Vec<Point>>,
+    /// Polygons cut out of the zone
+    #[serde(default)]
+    pub exclude: Vec<Vec<Point>>,
+}
+
+/// Even-odd rule, so self-intersecting polygons still rasterize predictably
+fn polygon_contains(polygon: &[Point], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    let mut previous = match polygon.last() {
+        Some(x) => x,
+        None => return false,
+    };
+    for current in polygon {
+        let ([x1, y1], [x2, y2]) = (*previous, *current);
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+        previous = current;
+    }
+    inside
+}
+
+impl ZoneConfig {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| polygon_contains(p, x, y)))
+            && !self.exclude.iter().any(|p| polygon_contains(p, x, y))
+    }
+
+    /// Rasterizes the zone into a mask in the format of `mask_file`, where 0 is detected and anything else ignored.
+    /// Pixels ignored by `mask` are ignored by the zone too.
+    pub fn rasterize(&self, width: u32, height: u32, mask: Option<&GrayImage>) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let masked = mask
+                .and_then(|mask| mask.get_pixel_checked(x, y))
+                .is_some_and(|x| x.0[0] != 0);
+            let inside = self.contains(
+                (x as f64 + 0.5) / width as f64,
+                (y as f64 + 0.5) / height as f64,
+            );
+            Luma([if inside && !masked { 0 } else { 255 }])
+        })
+    }
+}
+
+/// Zones are checked for coordinates in [0, 1], polygons with at least 3 points and covering at least one pixel of a `width` x `height` frame
+pub fn validate(zone: &ZoneConfig, width: u32, height: u32) -> Vec<(String, String)> {
+    let mut problems = vec![];
+    for (field, polygons) in [("include", &zone.include), ("exclude", &zone.exclude)] {
+        for (i, polygon) in polygons.iter().enumerate() {
+            if polygon.len() < 3 {
+                problems.push((
+                    format!("{field}.{i}"),
+                    "polygons need at least 3 points".to_string(),
+                ));
+            }
+            if polygon.iter().flatten().any(|x| !(0.0..=1.0).contains(x)) {
+                problems.push((
+                    format!("{field}.{i}"),
+                    "coordinates must be between 0 and 1".to_string(),
+                ));
+            }
+        }
+    }
+    // an empty zone would have no pixels to average its change over
+    if problems.is_empty()
+        && !zone
+            .rasterize(width, height, None)
+            .pixels()
+            .any(|x| x.0[0] == 0)
+    {
+        problems.push((
+            "include".to_string(),
+            "zone covers no pixels at the detector's resolution".to_string(),
+        ));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(yaml: &str) -> ZoneConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn rasterize() {
+        let left_half = zone(
+            r"
+include: [ [ [0, 0], [0.5, 0], [0.5, 1], [0, 1] ] ]
+exclude: [ [ [0, 0], [0.25, 0], [0.25, 0.5], [0, 0.5] ] ]
+",
+        );
+        let mask = GrayImage::from_fn(4, 2, |x, _| Luma([if x == 1 { 255 } else { 0 }]));
+        let raster = left_half.rasterize(4, 2, Some(&mask));
+        let detected = |x, y| raster.get_pixel(x, y).0[0] == 0;
+        // excluded
+        assert!(!detected(0, 0));
+        assert!(detected(0, 1));
+        // masked
+        assert!(!detected(1, 1));
+        // outside
+        assert!(!detected(2, 1));
+        assert!(!detected(3, 0));
+
+        let everything = ZoneConfig::default().rasterize(4, 2, None);
+        assert!(everything.pixels().all(|x| x.0[0] == 0));
+    }
+
+    #[test]
+    fn validate_zones() {
+        let problems = |yaml| {
+            validate(&zone(yaml), 64, 48)
+                .into_iter()
+                .map(|x| x.0)
+                .collect::<Vec<_>>()
+        };
+        assert!(problems("include: [ [ [0, 0], [1, 0], [0, 1] ] ]").is_empty());
+        assert_eq!(
+            problems("include: [ [ [0, 0], [1, 0] ] ]\nexclude: [ [ [0, 0], [2, 0], [0, 1] ] ]"),
+            ["include.0", "exclude.0"]
+        );
+        // between pixel centers
+        assert_eq!(
+            problems("include: [ [ [0, 0], [0.001, 0], [0, 0.001] ] ]"),
+            ["include"]
+        );
+    }
+}